@@ -1,7 +1,18 @@
-use crate::display::DisplayManager;
-use crate::utils::config::ConfigManager;
+use crate::daemon;
+use crate::display::transaction::MonitorChange;
+use crate::display::{
+    BackendKind, BackendOptions, Changes, DisplayManager, ModeSpec, Monitor, Orientation,
+    Resolution, Transaction,
+};
+use crate::utils::config::{find_monitor, ConfigManager};
+use crate::utils::launcher::{self, Outcome};
+use crate::utils::rules::Watcher;
 use anyhow::{anyhow, Result};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use log::warn;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -9,24 +20,147 @@ pub struct Cli {
     #[arg(long, global = true)]
     pub debug: bool,
 
+    /// Display backend to use (defaults to the native one for this platform)
+    #[arg(long, global = true, value_enum)]
+    pub backend: Option<BackendKind>,
+
+    /// Virtual monitor topology (TOML or JSON) for the sim backend
+    #[arg(long, global = true, value_name = "FILE")]
+    pub topology: Option<PathBuf>,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
+impl Cli {
+    pub fn backend_options(&self) -> BackendOptions {
+        BackendOptions {
+            topology: self.topology.clone(),
+        }
+    }
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// Switch to a specific profile
     Switch {
         /// Name of the profile to switch to
         profile_name: String,
+
+        /// Only check whether the profile would be accepted, change nothing
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Change the current display settings without going through a profile
+    Set {
+        /// Monitor to change (id, name, device name or 1-based index)
+        #[arg(long, value_name = "MONITOR")]
+        monitor: Option<String>,
+
+        /// Mode to set, e.g. 1920x1080, 1920x1080@144, 2560x1440@59.94:32,
+        /// native or max. The closest mode the monitor supports is used
+        #[arg(long, value_name = "MODE")]
+        mode: Option<ModeSpec>,
+
+        /// Rotation: 0, 90, 180, 270, normal, left, inverted or right
+        #[arg(long, value_name = "ORIENTATION")]
+        rotate: Option<Orientation>,
+
+        /// Make the monitor primary, or the one named here
+        #[arg(long, value_name = "MONITOR", num_args = 0..=1)]
+        primary: Option<Option<String>>,
+
+        /// Top left corner in desktop coordinates, e.g. 1920,0
+        #[arg(long, value_name = "X,Y", value_parser = parse_position, allow_hyphen_values = true)]
+        pos: Option<(i32, i32)>,
+    },
+    /// Run a program under a profile, restoring the current settings once
+    /// it and everything it started have exited
+    #[command(
+        after_help = "Examples:\n  yarm run --profile \"1080p-Gaming\" -- %command%\n  yarm run --profile Gaming --app C:\\Games\\Cyberpunk.exe"
+    )]
+    Run {
+        /// Profile to apply while the program runs
+        #[arg(long)]
+        profile: String,
+
+        /// Program to run, instead of giving it after `--`
+        #[arg(long, value_name = "PATH")]
+        app: Option<String>,
+
+        /// Program and its arguments
+        #[arg(last = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+    /// Apply the profiles of the `[[rules]]` in config.toml while matching
+    /// programs run, until stopped with Ctrl-C
+    Watch,
+    /// Serve the displays to other yarm instances and scripts over a local
+    /// socket, watching `[[rules]]` and reverting unconfirmed changes
+    /// while no window is open
+    Daemon,
+    /// Save the current settings of the monitors as a profile
+    Save {
+        /// Name of the profile to create
+        profile_name: String,
+
+        /// Only save these monitors (ids, names, device names or 1-based
+        /// indexes, comma separated)
+        #[arg(long, value_name = "MONITORS", value_delimiter = ',')]
+        monitors: Vec<String>,
+
+        /// Replace an existing profile of the same name
+        #[arg(long)]
+        force: bool,
+
+        /// Switch to the profile by itself whenever the monitors connected
+        /// now are connected again
+        #[arg(long = "match")]
+        match_monitors: bool,
+    },
+    /// Show the connected monitors and their current settings
+    Monitors {
+        /// Also list every mode each monitor supports
+        #[arg(long)]
+        modes: bool,
+
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
     },
     // Future expansion
     List,
 }
 
-pub fn handle_cli(cli: Cli) -> Result<()> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Aligned columns for reading
+    Table,
+    Json,
+    Toml,
+}
+
+/// A monitor as printed by `yarm monitors`, `Monitor` itself leaves its
+/// modes out when serialized.
+#[derive(Serialize)]
+struct MonitorReport<'a> {
+    index: usize,
+    #[serde(flatten)]
+    monitor: &'a Monitor,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    available_resolutions: Option<&'a [Resolution]>,
+}
+
+#[derive(Serialize)]
+struct MonitorsReport<'a> {
+    monitors: Vec<MonitorReport<'a>>,
+}
+
+pub fn handle_cli(cli: Cli, display: &DisplayManager) -> Result<()> {
     match cli.command {
-        Some(Commands::Switch { profile_name }) => {
+        Some(Commands::Switch {
+            profile_name,
+            dry_run,
+        }) => {
             let config = ConfigManager::load()?;
             let profile = config
                 .profiles
@@ -34,18 +168,244 @@ pub fn handle_cli(cli: Cli) -> Result<()> {
                 .find(|p| p.name == profile_name)
                 .ok_or_else(|| anyhow!("Profile '{}' not found", profile_name))?;
 
-            println!("Switching to profile: {}", profile_name);
+            if dry_run {
+                println!("Checking profile: {}", profile_name);
+            } else {
+                println!("Switching to profile: {}", profile_name);
+            }
 
-            for setting in &profile.settings {
+            let monitors = display.enumerate_monitors()?;
+            let transaction = profile.transaction(display, &monitors)?;
+
+            if dry_run {
+                return check_transaction(display, &transaction, &monitors);
+            }
+            for change in transaction.changes() {
+                println!(
+                    "Setting monitor {} to {}",
+                    monitor_id(&monitors, change),
+                    change
+                );
+            }
+
+            display
+                .apply(&transaction)
+                .map_err(|e| anyhow!("Failed to switch to profile '{}': {}", profile_name, e))
+        }
+        Some(Commands::Set {
+            monitor,
+            mode,
+            rotate,
+            primary,
+            pos,
+        }) => {
+            let monitors = display.enumerate_monitors()?;
+            let target = monitor
+                .as_deref()
+                .map(|name| find_monitor(&monitors, name))
+                .transpose()?;
+            let mut transaction = Transaction::new();
+
+            if mode.is_some() || rotate.is_some() || pos.is_some() {
+                let monitor =
+                    target.ok_or_else(|| anyhow!("--mode, --rotate and --pos need --monitor"))?;
+                if !monitor.enabled {
+                    transaction.set_enabled(&monitor.device_name, true);
+                }
+                if let Some(spec) = mode {
+                    let resolution = closest_mode(monitor, spec, rotate)?;
+                    transaction.set_resolution(&monitor.device_name, resolution);
+                }
+                if let Some(orientation) = rotate {
+                    transaction.set_orientation(&monitor.device_name, orientation);
+                }
+                if let Some(position) = pos {
+                    transaction.set_position(&monitor.device_name, position);
+                }
+            }
+            let primary = match primary {
+                Some(Some(name)) => Some(find_monitor(&monitors, &name)?),
+                Some(None) => Some(target.ok_or_else(|| {
+                    anyhow!("--primary needs a monitor, either --monitor or --primary <MONITOR>")
+                })?),
+                None => None,
+            };
+            if primary.is_some() && !display.capabilities().primary {
+                return Err(anyhow!(
+                    "The {} backend has no primary monitor",
+                    display.backend_name()
+                ));
+            }
+            if let Some(monitor) = primary.filter(|m| !m.is_primary) {
+                transaction.set_primary(&monitor.device_name);
+            }
+
+            if transaction.is_empty() {
+                println!("Nothing to change");
+                return Ok(());
+            }
+            for change in transaction.changes() {
                 println!(
                     "Setting monitor {} to {}",
-                    setting.monitor_id, setting.resolution
+                    monitor_id(&monitors, change),
+                    change
                 );
-                if let Err(e) =
-                    DisplayManager::set_resolution(&setting.monitor_id, &setting.resolution)
-                {
-                    eprintln!("Failed to set resolution for {}: {}", setting.monitor_id, e);
+            }
+            display.apply(&transaction)?;
+
+            // Report what the backend actually did, it may round or reflow
+            let updated = display.enumerate_monitors()?;
+            for change in transaction.changes() {
+                if let Some(m) = updated.iter().find(|m| m.device_name == change.device_name) {
+                    println!("{} is now {}", m.id, describe(m));
+                }
+            }
+            Ok(())
+        }
+        Some(Commands::Run {
+            profile: profile_name,
+            app,
+            command,
+        }) => {
+            let (program, args) = match &app {
+                Some(app) => (app, command.as_slice()),
+                None => command
+                    .split_first()
+                    .ok_or_else(|| anyhow!("Nothing to run, give a program after -- or --app"))?,
+            };
+            let config = ConfigManager::load()?;
+            let profile = config
+                .profiles
+                .iter()
+                .find(|p| p.name == profile_name)
+                .ok_or_else(|| anyhow!("Profile '{}' not found", profile_name))?;
+
+            // From here on Ctrl-C must not leave the profile applied
+            launcher::catch_interrupts();
+            let monitors = display.enumerate_monitors()?;
+            let transaction = profile.transaction(display, &monitors)?;
+            let restore = transaction.inverse(&monitors);
+            println!("Switching to profile: {}", profile_name);
+            display
+                .apply(&transaction)
+                .map_err(|e| anyhow!("Failed to switch to profile '{}': {}", profile_name, e))?;
+
+            let outcome = if launcher::interrupted() {
+                Ok(Outcome::Interrupted)
+            } else {
+                println!("Running {}", program);
+                launcher::run(program, args)
+            };
+
+            println!("Restoring the previous display settings");
+            display.apply(&restore)?;
+            match outcome? {
+                Outcome::Interrupted => Err(anyhow!("Interrupted")),
+                Outcome::Exited(Some(0)) => Ok(()),
+                Outcome::Exited(Some(code)) => std::process::exit(code),
+                Outcome::Exited(None) => Err(anyhow!("{} was killed", program)),
+            }
+        }
+        Some(Commands::Watch) => {
+            if display.is_remote() {
+                return Err(anyhow!(
+                    "The yarm daemon is running and already watches the rules"
+                ));
+            }
+            let config = ConfigManager::load()?;
+            let mut watcher = Watcher::new(&config)?;
+            let matching = config
+                .profiles
+                .iter()
+                .filter(|p| p.matching.is_some())
+                .count();
+            if config.rules.is_empty() && matching == 0 {
+                return Err(anyhow!(
+                    "No [[rules]] or [profiles.match] in config.toml, nothing to watch"
+                ));
+            }
+            launcher::catch_interrupts();
+            println!(
+                "Watching for {} rule(s) and {} profile(s) matching monitors, press Ctrl-C to stop",
+                config.rules.len(),
+                matching
+            );
+
+            let interval = Duration::from_secs(config.general.watch_interval.max(1));
+            let refresh_freq = config.general.refresh_freq;
+            let mut changes = Changes::watch(
+                display,
+                (refresh_freq > 0).then(|| Duration::from_secs(refresh_freq)),
+            );
+            // Monitors may be unreadable for a moment, e.g. while one is
+            // plugged in, watching goes on
+            if let Err(e) = watcher.displays_changed(display, &config) {
+                warn!("Cannot match the connected monitors: {}", e);
+            }
+            while !launcher::interrupted() {
+                if let Err(e) = watcher.tick(display, &config) {
+                    warn!("Cannot check the rules: {}", e);
                 }
+                let deadline = Instant::now() + interval;
+                while !launcher::interrupted() && Instant::now() < deadline {
+                    if !changes.wait(Duration::from_millis(100)) {
+                        continue;
+                    }
+                    if let Err(e) = watcher.displays_changed(display, &config) {
+                        warn!("Cannot match the connected monitors: {}", e);
+                    }
+                }
+            }
+            watcher.stop(display);
+            Ok(())
+        }
+        Some(Commands::Daemon) => daemon::run(display.clone()),
+        Some(Commands::Save {
+            profile_name,
+            monitors: selected,
+            force,
+            match_monitors,
+        }) => {
+            let mut config = ConfigManager::load()?;
+            let monitors = display.enumerate_monitors()?;
+            let (profile, exists) = config.save_profile(
+                display,
+                &monitors,
+                &profile_name,
+                &selected,
+                force,
+                match_monitors,
+            )?;
+            for setting in &profile.settings {
+                let monitor = find_monitor(&monitors, &setting.monitor_id)?;
+                println!("Saving monitor {}: {}", monitor.id, describe(monitor));
+            }
+            ConfigManager::save(&config)?;
+            if exists {
+                println!("Profile '{}' overwritten", profile_name);
+            } else {
+                println!("Profile '{}' saved", profile_name);
+            }
+            Ok(())
+        }
+        Some(Commands::Monitors { modes, format }) => {
+            let monitors = display.enumerate_monitors()?;
+            let report = MonitorsReport {
+                monitors: monitors
+                    .iter()
+                    .enumerate()
+                    .map(|(i, monitor)| MonitorReport {
+                        index: i + 1,
+                        monitor,
+                        available_resolutions: modes
+                            .then_some(monitor.available_resolutions.as_slice()),
+                    })
+                    .collect(),
+            };
+            match format {
+                OutputFormat::Table => print_monitors(&report),
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+                OutputFormat::Toml => print!("{}", toml::to_string_pretty(&report)?),
             }
             Ok(())
         }
@@ -60,3 +420,154 @@ pub fn handle_cli(cli: Cli) -> Result<()> {
         None => Ok(()), // Should launch GUI
     }
 }
+
+/// Prints a verdict per monitor, failing if any change would be rejected.
+fn check_transaction(
+    display: &DisplayManager,
+    transaction: &Transaction,
+    monitors: &[Monitor],
+) -> Result<()> {
+    let verdicts = display.validate(transaction)?;
+    for (change, verdict) in transaction.changes().iter().zip(&verdicts) {
+        let id = monitor_id(monitors, change);
+        match &verdict.rejection {
+            None => println!("✓ {}: {}", id, change),
+            Some(reason) => println!("✗ {}: {} ({})", id, change, reason),
+        }
+    }
+
+    let rejected = verdicts.iter().filter(|v| !v.is_accepted()).count();
+    if rejected > 0 {
+        return Err(anyhow!("{} monitor(s) would reject the profile", rejected));
+    }
+    println!("Profile would be applied successfully");
+    Ok(())
+}
+
+/// The supported mode closest to `spec`, in the frame of the orientation the
+/// monitor will have. A size given in the other frame is accepted too, so
+/// `--mode 1920x1080 --rotate 90` works as well as `--mode 1080x1920`.
+fn closest_mode(
+    monitor: &Monitor,
+    spec: ModeSpec,
+    rotate: Option<Orientation>,
+) -> Result<Resolution> {
+    let rotates =
+        rotate.is_some_and(|o| o.is_portrait() != monitor.current_orientation.is_portrait());
+    let resolution = if rotates {
+        spec.rotated().resolve(monitor)
+    } else {
+        spec.resolve(monitor)
+    };
+    let resolution = resolution.ok_or_else(|| {
+        let mut sizes: Vec<String> = Vec::new();
+        for r in &monitor.available_resolutions {
+            let size = format!(
+                "{}x{}{}",
+                r.width,
+                r.height,
+                if r.interlaced { "i" } else { "" }
+            );
+            if !sizes.contains(&size) {
+                sizes.push(size);
+            }
+        }
+        anyhow!(
+            "{} has no mode like {}, it supports {}",
+            monitor.name,
+            spec,
+            sizes.join(", ")
+        )
+    })?;
+    Ok(if rotates {
+        resolution.rotated()
+    } else {
+        resolution.clone()
+    })
+}
+
+fn parse_position(s: &str) -> Result<(i32, i32)> {
+    let (x, y) = s
+        .split_once(',')
+        .ok_or_else(|| anyhow!("expected X,Y, e.g. 1920,0"))?;
+    Ok((x.trim().parse()?, y.trim().parse()?))
+}
+
+/// Prints one row per monitor, its modes indented below it if requested.
+fn print_monitors(report: &MonitorsReport) {
+    let rows: Vec<[String; 7]> = report
+        .monitors
+        .iter()
+        .map(|r| {
+            let m = r.monitor;
+            [
+                r.index.to_string(),
+                m.id.clone(),
+                m.name.clone(),
+                m.device_name.clone(),
+                if m.enabled {
+                    m.current_resolution.to_string()
+                } else {
+                    "disabled".to_string()
+                },
+                m.current_orientation.to_string(),
+                format!("{},{}", m.position.0, m.position.1),
+            ]
+        })
+        .collect();
+    let header = ["#", "ID", "NAME", "DEVICE", "MODE", "ROTATION", "POSITION"];
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain(Some(header[i].len()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let line = |cells: &[&str], primary: &str| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:<width$}", cell))
+            .collect();
+        let line = format!("{}  {}", padded.join("  "), primary);
+        println!("{}", line.trim_end());
+    };
+
+    line(&header, "PRIMARY");
+    for (row, r) in rows.iter().zip(&report.monitors) {
+        let cells: Vec<&str> = row.iter().map(String::as_str).collect();
+        line(&cells, if r.monitor.is_primary { "yes" } else { "" });
+        for mode in r.available_resolutions.unwrap_or_default() {
+            let current = r.monitor.enabled && *mode == r.monitor.current_resolution;
+            println!("    {} {}", if current { "*" } else { " " }, mode);
+        }
+    }
+}
+
+/// One line summary of a monitor's current state.
+fn describe(monitor: &Monitor) -> String {
+    if !monitor.enabled {
+        return "disabled".to_string();
+    }
+    let mut description = format!(
+        "{}, {} at ({}, {})",
+        monitor.current_resolution,
+        monitor.current_orientation,
+        monitor.position.0,
+        monitor.position.1
+    );
+    if monitor.is_primary {
+        description.push_str(" as primary");
+    }
+    description
+}
+
+/// The id users know a monitor by, falling back to the backend's device name.
+fn monitor_id<'a>(monitors: &'a [Monitor], change: &'a MonitorChange) -> &'a str {
+    monitors
+        .iter()
+        .find(|m| m.device_name == change.device_name)
+        .map_or(change.device_name.as_str(), |m| m.id.as_str())
+}
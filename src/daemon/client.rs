@@ -0,0 +1,234 @@
+//! Talking to a running daemon, and a display backend that forwards to it
+//! so the CLI and GUI work the same with or without one.
+
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use super::protocol::{ApplyParams, MonitorInfo, Request, Response, SettleParams, ValidateParams};
+use super::transport::{self, Stream};
+use crate::display::backend::{Capabilities, DisplayBackend, Verdict};
+use crate::display::transaction::MonitorChange;
+use crate::display::{Monitor, Orientation, Resolution};
+
+/// One connection to the daemon, requests are answered in order.
+pub struct Client {
+    reader: BufReader<Stream>,
+    writer: Stream,
+    next_id: u64,
+}
+
+impl Client {
+    /// Connects to the daemon, failing if none is running.
+    pub fn connect() -> Result<Self> {
+        let writer = transport::connect()?;
+        Ok(Self {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+            next_id: 1,
+        })
+    }
+
+    fn send(&mut self, request: &Request) -> Result<()> {
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(anyhow!("The yarm daemon closed the connection"));
+        }
+        Ok(line)
+    }
+
+    pub fn call<P: Serialize, R: DeserializeOwned>(
+        &mut self,
+        method: &str,
+        params: P,
+    ) -> Result<R> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(&Request::new(id, method, serde_json::to_value(params)?))?;
+        loop {
+            let response: Response = serde_json::from_str(&self.read_line()?)?;
+            if response.id != json!(id) {
+                continue;
+            }
+            if let Some(error) = response.error {
+                return Err(anyhow!("{}", error.message));
+            }
+            return Ok(serde_json::from_value(
+                response.result.unwrap_or(Value::Null),
+            )?);
+        }
+    }
+
+    /// Turns the connection into a stream of notifications, see
+    /// `protocol`.
+    pub fn subscribe(mut self) -> Result<Notifications> {
+        let _: bool = self.call("subscribe", ())?;
+        Ok(Notifications { client: self })
+    }
+}
+
+/// Notifications from the daemon as `(method, params)`, blocking until the
+/// next one arrives. Ends when the daemon goes away.
+pub struct Notifications {
+    client: Client,
+}
+
+impl Iterator for Notifications {
+    type Item = (String, Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = self.client.read_line().ok()?;
+        let request: Request = serde_json::from_str(&line).ok()?;
+        Some((request.method, request.params))
+    }
+}
+
+/// The display backend of a running daemon.
+pub struct RemoteBackend {
+    client: Mutex<Client>,
+    capabilities: Capabilities,
+}
+
+impl RemoteBackend {
+    /// `None` if no daemon is running.
+    pub fn connect() -> Option<Self> {
+        let mut client = Client::connect().ok()?;
+        let capabilities = client.call("capabilities", ()).ok()?;
+        Some(Self {
+            client: Mutex::new(client),
+            capabilities,
+        })
+    }
+
+    fn call<P: Serialize, R: DeserializeOwned>(&self, method: &str, params: P) -> Result<R> {
+        self.client
+            .lock()
+            .map_err(|_| anyhow!("The connection to the yarm daemon is broken"))?
+            .call(method, params)
+    }
+
+    fn apply_one(&self, change: MonitorChange) -> Result<()> {
+        self.apply(&[change])
+    }
+}
+
+impl DisplayBackend for RemoteBackend {
+    fn name(&self) -> &'static str {
+        "daemon"
+    }
+
+    fn is_remote(&self) -> bool {
+        true
+    }
+
+    fn enumerate_monitors(&self) -> Result<Vec<Monitor>> {
+        let monitors: Vec<MonitorInfo> = self.call("monitors.list", ())?;
+        Ok(monitors.into_iter().map(Monitor::from).collect())
+    }
+
+    fn set_resolution(&self, device_name: &str, resolution: &Resolution) -> Result<()> {
+        self.apply_one(MonitorChange {
+            resolution: Some(resolution.clone()),
+            ..MonitorChange::new(device_name)
+        })
+    }
+
+    fn set_orientation(&self, device_name: &str, orientation: Orientation) -> Result<()> {
+        self.apply_one(MonitorChange {
+            orientation: Some(orientation),
+            ..MonitorChange::new(device_name)
+        })
+    }
+
+    fn set_position(&self, device_name: &str, position: (i32, i32)) -> Result<()> {
+        self.apply_one(MonitorChange {
+            position: Some(position),
+            ..MonitorChange::new(device_name)
+        })
+    }
+
+    fn set_primary(&self, device_name: &str) -> Result<()> {
+        self.apply_one(MonitorChange {
+            primary: true,
+            ..MonitorChange::new(device_name)
+        })
+    }
+
+    fn set_enabled(&self, device_name: &str, enabled: bool) -> Result<()> {
+        self.apply_one(MonitorChange {
+            enabled: Some(enabled),
+            ..MonitorChange::new(device_name)
+        })
+    }
+
+    fn set_scale(&self, device_name: &str, scale: f64) -> Result<()> {
+        self.apply_one(MonitorChange {
+            scale: Some(scale),
+            ..MonitorChange::new(device_name)
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    fn apply(&self, changes: &[MonitorChange]) -> Result<()> {
+        self.call(
+            "monitors.apply",
+            ApplyParams {
+                changes: changes.to_vec(),
+                revert_after: None,
+            },
+        )
+    }
+
+    fn validate(&self, changes: &[MonitorChange]) -> Result<Vec<Verdict>> {
+        self.call(
+            "monitors.validate",
+            ValidateParams {
+                changes: changes.to_vec(),
+            },
+        )
+    }
+
+    fn apply_with_revert(&self, changes: &[MonitorChange], timeout: Duration) -> Result<()> {
+        self.call(
+            "monitors.apply",
+            ApplyParams {
+                changes: changes.to_vec(),
+                revert_after: Some(timeout.as_secs()),
+            },
+        )
+    }
+
+    fn settle_revert(&self, keep: bool) -> Result<()> {
+        let _: bool = self.call("revert.settle", SettleParams { keep })?;
+        Ok(())
+    }
+
+    fn watch_changes(&self) -> Result<Receiver<()>> {
+        let notifications = Client::connect()?.subscribe()?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for (method, _) in notifications {
+                if method == "monitors.changed" && sender.send(()).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(receiver)
+    }
+}
@@ -0,0 +1,425 @@
+//! `yarm daemon`: owns the display backend and serves it over a local
+//! socket (see `protocol`), so rule watching and revert timers go on after
+//! the GUI is closed. The CLI and GUI use it through `RemoteBackend` when it
+//! is running.
+
+pub mod client;
+pub mod protocol;
+mod transport;
+
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use self::protocol::{
+    ApplyParams, MonitorInfo, ProfileParams, Request, Response, RpcError, SaveParams, SettleParams,
+    ValidateParams, FAILED, INVALID_PARAMS, METHOD_NOT_FOUND, PARSE_ERROR,
+};
+use self::transport::{Listener, Stream};
+use crate::display::backend::Capabilities;
+use crate::display::{Changes, DisplayManager, Transaction};
+use crate::utils::config::{ConfigManager, Profile};
+use crate::utils::launcher;
+use crate::utils::rules::Watcher;
+
+/// Changes applied with `revert_after` that nobody kept yet.
+struct PendingRevert {
+    restore: Transaction,
+    deadline: Instant,
+}
+
+struct Daemon {
+    display: DisplayManager,
+    /// Held while the monitors are being changed, so clients and the rules
+    /// watcher take turns
+    pending: Mutex<Option<PendingRevert>>,
+    /// Queues of the subscribed clients, each written out by the client's
+    /// own thread
+    subscribers: Mutex<Vec<SyncSender<Request>>>,
+}
+
+/// Notifications a subscriber may fall behind by before it is dropped.
+const NOTIFICATION_BACKLOG: usize = 64;
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError {
+        code: INVALID_PARAMS,
+        message: e.to_string(),
+    })
+}
+
+fn failed(error: anyhow::Error) -> RpcError {
+    RpcError {
+        code: FAILED,
+        message: error.to_string(),
+    }
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|e| failed(e.into()))
+}
+
+fn write_line<T: Serialize>(stream: &mut Stream, message: &T) -> Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+impl Daemon {
+    fn handle(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            // Clients are told about changes whether or not the backend
+            // has events, the daemon polls for them otherwise
+            "capabilities" => to_value(Capabilities {
+                revert_timer: true,
+                change_events: true,
+                ..self.display.capabilities()
+            }),
+            "monitors.list" => {
+                let monitors = self.display.enumerate_monitors().map_err(failed)?;
+                to_value(monitors.iter().map(MonitorInfo::from).collect::<Vec<_>>())
+            }
+            "monitors.validate" => {
+                let ValidateParams { changes } = self::params(params)?;
+                let verdicts = self
+                    .display
+                    .validate(&Transaction::from(changes))
+                    .map_err(failed)?;
+                to_value(verdicts)
+            }
+            "monitors.apply" => {
+                let ApplyParams {
+                    changes,
+                    revert_after,
+                } = self::params(params)?;
+                self.apply(&Transaction::from(changes), revert_after)
+                    .map_err(failed)?;
+                Ok(Value::Null)
+            }
+            "profiles.list" => to_value(ConfigManager::load().map_err(failed)?.profiles),
+            "profiles.apply" => {
+                let ProfileParams { name, revert_after } = self::params(params)?;
+                self.apply_profile(&name, revert_after).map_err(failed)?;
+                Ok(Value::Null)
+            }
+            "profiles.save" => {
+                let params: SaveParams = self::params(params)?;
+                to_value(self.save_profile(params).map_err(failed)?)
+            }
+            "revert.settle" => {
+                let SettleParams { keep } = self::params(params)?;
+                to_value(self.settle(keep).map_err(failed)?)
+            }
+            _ => Err(RpcError {
+                code: METHOD_NOT_FOUND,
+                message: format!("Unknown method '{}'", method),
+            }),
+        }
+    }
+
+    /// Applies `transaction`, to be reverted after `revert_after` seconds
+    /// unless kept. Any change still pending is kept, reverting it later
+    /// would undo this one too.
+    fn apply(&self, transaction: &Transaction, revert_after: Option<u64>) -> Result<()> {
+        let mut pending = lock(&self.pending);
+        let monitors = self.display.enumerate_monitors()?;
+        let restore = transaction.inverse(&monitors);
+        self.display.apply(transaction)?;
+        *pending = revert_after.map(|seconds| PendingRevert {
+            restore,
+            deadline: Instant::now() + Duration::from_secs(seconds),
+        });
+        Ok(())
+    }
+
+    fn apply_profile(&self, name: &str, revert_after: Option<u64>) -> Result<()> {
+        let config = ConfigManager::load()?;
+        let profile = config
+            .profiles
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| anyhow!("Profile '{}' not found", name))?;
+        let monitors = self.display.enumerate_monitors()?;
+        let transaction = profile.transaction(&self.display, &monitors)?;
+        self.apply(&transaction, revert_after)?;
+        println!("Switched to profile: {}", name);
+        self.notify("profile.applied", json!({ "name": name }));
+        Ok(())
+    }
+
+    fn save_profile(&self, params: SaveParams) -> Result<Profile> {
+        let SaveParams {
+            name,
+            monitors: selected,
+            force,
+            match_monitors,
+        } = params;
+        let mut config = ConfigManager::load()?;
+        let monitors = self.display.enumerate_monitors()?;
+        let (profile, _) = config.save_profile(
+            &self.display,
+            &monitors,
+            &name,
+            &selected,
+            force,
+            match_monitors,
+        )?;
+        ConfigManager::save(&config)?;
+        println!("Profile '{}' saved", name);
+        Ok(profile)
+    }
+
+    /// Keeps or reverts the pending change, returning whether there was
+    /// one.
+    fn settle(&self, keep: bool) -> Result<bool> {
+        let mut pending = lock(&self.pending);
+        let Some(revert) = pending.take() else {
+            return Ok(false);
+        };
+        if !keep {
+            self.revert(revert)?;
+            drop(pending);
+            self.notify("reverted", json!({}));
+        }
+        Ok(true)
+    }
+
+    /// Undoes a pending change. Called with `pending` locked, subscribers
+    /// are told once it is released.
+    fn revert(&self, revert: PendingRevert) -> Result<()> {
+        println!("Reverting changes that were not kept");
+        self.display.apply(&revert.restore)
+    }
+
+    fn revert_if_due(&self) {
+        let mut pending = lock(&self.pending);
+        let Some(revert) = pending.take_if(|p| p.deadline <= Instant::now()) else {
+            return;
+        };
+        let result = self.revert(revert);
+        drop(pending);
+        match result {
+            Ok(()) => self.notify("reverted", json!({})),
+            Err(e) => eprintln!("Failed to revert: {}", e),
+        }
+    }
+
+    /// Queues a notification for every subscriber without waiting for
+    /// any of them, dropping the ones that went away or stopped reading.
+    fn notify(&self, method: &str, params: Value) {
+        let notification = Request::notification(method, params);
+        lock(&self.subscribers).retain(|queue| queue.try_send(notification.clone()).is_ok());
+    }
+
+    /// Answers requests from one client until it disconnects or
+    /// subscribes, then writes it the notifications.
+    fn serve(&self, stream: Stream) {
+        let Ok(reader) = stream.try_clone() else {
+            return;
+        };
+        let mut writer = stream;
+        for line in BufReader::new(reader).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let request: Request = match serde_json::from_str(&line) {
+                Ok(request) => request,
+                Err(e) => {
+                    let response = Response::failure(Value::Null, PARSE_ERROR, e.to_string());
+                    if write_line(&mut writer, &response).is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            debug!("Daemon request: {}", request.method);
+            let subscribe = request.method == "subscribe";
+            let result = if subscribe {
+                Ok(Value::Bool(true))
+            } else {
+                self.handle(&request.method, request.params)
+            };
+            // Requests without an id want no answer
+            let Some(id) = request.id else {
+                continue;
+            };
+            let response = match result {
+                Ok(result) => Response::success(id, result),
+                Err(error) => Response::failure(id, error.code, error.message),
+            };
+            if subscribe {
+                let (queue, notifications) = mpsc::sync_channel(NOTIFICATION_BACKLOG);
+                {
+                    // Answered under the lock so no notification overtakes it
+                    let mut subscribers = lock(&self.subscribers);
+                    if write_line(&mut writer, &response).is_err() {
+                        return;
+                    }
+                    subscribers.push(queue);
+                }
+                // Ends once the client is dropped from the subscribers
+                for notification in notifications {
+                    if write_line(&mut writer, &notification).is_err() {
+                        break;
+                    }
+                }
+                return;
+            }
+            if write_line(&mut writer, &response).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Runs the daemon until Ctrl-C, then reverts what is pending and removes
+/// the socket.
+pub fn run(display: DisplayManager) -> Result<()> {
+    let mut listener = Listener::bind()?;
+    let mut config = ConfigManager::load()?;
+    let mut watcher = Watcher::new(&config)?;
+    launcher::catch_interrupts();
+
+    let daemon = Arc::new(Daemon {
+        display,
+        pending: Mutex::new(None),
+        subscribers: Mutex::new(Vec::new()),
+    });
+    let acceptor = daemon.clone();
+    thread::spawn(move || loop {
+        match listener.accept() {
+            Ok(stream) => {
+                let daemon = acceptor.clone();
+                thread::spawn(move || daemon.serve(stream));
+            }
+            Err(e) => {
+                warn!("Cannot accept a connection: {}", e);
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    });
+    println!(
+        "Listening on {}, press Ctrl-C to stop",
+        transport::endpoint().display()
+    );
+
+    // Polling every refresh_freq seconds where the backend has no events
+    let refresh_freq = config.general.refresh_freq;
+    let mut changes = Changes::watch(
+        &daemon.display,
+        (refresh_freq > 0).then(|| Duration::from_secs(refresh_freq)),
+    );
+    let mut next_watch = Instant::now();
+    let mut known: Option<Value> = None;
+    announce_changes(&daemon, &mut known);
+    if let Err(e) = watcher.displays_changed(&daemon.display, &config) {
+        warn!("Cannot match the connected monitors: {}", e);
+    }
+    while !launcher::interrupted() {
+        daemon.revert_if_due();
+
+        let now = Instant::now();
+        if now >= next_watch {
+            // Rules and profiles may have been edited since the last look
+            match ConfigManager::load() {
+                Ok(reloaded) => {
+                    if reloaded.rules != config.rules {
+                        match watcher.reload(&reloaded) {
+                            Ok(()) => println!("Watching for {} rule(s)", reloaded.rules.len()),
+                            Err(e) => eprintln!("Keeping the previous rules: {}", e),
+                        }
+                    }
+                    config = reloaded;
+                }
+                Err(e) => warn!("Cannot reload config.toml: {}", e),
+            }
+            let result = {
+                let mut pending = lock(&daemon.pending);
+                let result = watcher.tick(&daemon.display, &config);
+                if watcher.take_applied() {
+                    *pending = None;
+                }
+                result
+            };
+            if let Err(e) = result {
+                warn!("Cannot check the rules: {}", e);
+            }
+            next_watch = now + Duration::from_secs(config.general.watch_interval.max(1));
+        }
+        if changes.wait(Duration::from_millis(100)) {
+            announce_changes(&daemon, &mut known);
+            let result = {
+                let mut pending = lock(&daemon.pending);
+                let result = watcher.displays_changed(&daemon.display, &config);
+                if watcher.take_applied() {
+                    *pending = None;
+                }
+                result
+            };
+            match result {
+                Ok(Some(name)) => {
+                    let applied = config.general.auto_apply;
+                    daemon.notify(
+                        "profile.matched",
+                        json!({ "name": name, "applied": applied }),
+                    );
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Cannot match the connected monitors: {}", e),
+            }
+        }
+    }
+
+    println!("Stopping");
+    // Newest first: the pending change was made on top of the rules' ones
+    let mut pending = lock(&daemon.pending);
+    if let Some(revert) = pending.take() {
+        let result = daemon.revert(revert);
+        drop(pending);
+        match result {
+            Ok(()) => daemon.notify("reverted", json!({})),
+            Err(e) => eprintln!("Failed to revert: {}", e),
+        }
+    }
+    watcher.stop(&daemon.display);
+    transport::release();
+    Ok(())
+}
+
+/// Tells subscribers about monitors that were plugged, unplugged or
+/// changed since the last call.
+fn announce_changes(daemon: &Daemon, known: &mut Option<Value>) {
+    let monitors = match daemon.display.enumerate_monitors() {
+        Ok(monitors) => monitors,
+        Err(e) => {
+            warn!("Cannot list monitors: {}", e);
+            return;
+        }
+    };
+    let Ok(current) =
+        serde_json::to_value(monitors.iter().map(MonitorInfo::from).collect::<Vec<_>>())
+    else {
+        return;
+    };
+    if known.as_ref() != Some(&current) {
+        if known.is_some() {
+            daemon.notify("monitors.changed", json!({ "monitors": current }));
+        }
+        *known = Some(current);
+    }
+}
@@ -0,0 +1,175 @@
+//! JSON-RPC 2.0 messages as they go over the socket, one JSON object per
+//! line in each direction.
+//!
+//! Methods:
+//!
+//! | method              | params                                  | result             |
+//! |---------------------|-----------------------------------------|--------------------|
+//! | `capabilities`      |                                         | `Capabilities`     |
+//! | `monitors.list`     |                                         | `[MonitorInfo]`    |
+//! | `monitors.validate` | `{changes}`                             | `[Verdict]`        |
+//! | `monitors.apply`    | `{changes, revert_after?}`              | `null`             |
+//! | `profiles.list`     |                                         | `[Profile]`        |
+//! | `profiles.apply`    | `{name, revert_after?}`                 | `null`             |
+//! | `profiles.save`     | `{name, monitors?, force?, match?}`     | `Profile`          |
+//! | `revert.settle`     | `{keep}`                                | whether one was pending |
+//! | `subscribe`         |                                         | `true`, then notifications |
+//!
+//! `revert_after` is in seconds: the daemon puts the monitors back unless
+//! `revert.settle` keeps the change in time, even if the client is gone.
+//! After `subscribe` the daemon only writes to the connection, sending
+//! `monitors.changed {monitors}`, `profile.applied {name}`,
+//! `profile.matched {name, applied}` and `reverted {}` notifications.
+//! `profile.matched` follows monitors being plugged or unplugged, `applied`
+//! tells whether `auto_apply` switched to the profile.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::display::transaction::MonitorChange;
+use crate::display::{Monitor, Resolution};
+
+pub const VERSION: &str = "2.0";
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// Anything the display backend or the config refused
+pub const FAILED: i64 = -32000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    /// Absent for notifications, which get no response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub params: Value,
+}
+
+impl Request {
+    pub fn new(id: u64, method: &str, params: Value) -> Self {
+        Self {
+            jsonrpc: VERSION.to_string(),
+            id: Some(id.into()),
+            method: method.to_string(),
+            params,
+        }
+    }
+
+    /// A request without an id, the form notifications take.
+    pub fn notification(method: &str, params: Value) -> Self {
+        Self {
+            jsonrpc: VERSION.to_string(),
+            id: None,
+            method: method.to_string(),
+            params,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn success(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: VERSION.to_string(),
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub fn failure(id: Value, code: i64, message: impl Into<String>) -> Self {
+        Self {
+            jsonrpc: VERSION.to_string(),
+            id,
+            result: None,
+            error: Some(RpcError {
+                code,
+                message: message.into(),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+/// A monitor with its modes, which `Monitor` leaves out when serialized.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonitorInfo {
+    #[serde(flatten)]
+    pub monitor: Monitor,
+    pub available_resolutions: Vec<Resolution>,
+}
+
+impl From<&Monitor> for MonitorInfo {
+    fn from(monitor: &Monitor) -> Self {
+        Self {
+            monitor: monitor.clone(),
+            available_resolutions: monitor.available_resolutions.clone(),
+        }
+    }
+}
+
+impl From<MonitorInfo> for Monitor {
+    fn from(info: MonitorInfo) -> Self {
+        Monitor {
+            available_resolutions: info.available_resolutions,
+            ..info.monitor
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApplyParams {
+    pub changes: Vec<MonitorChange>,
+    /// Seconds until the change is reverted unless kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revert_after: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValidateParams {
+    pub changes: Vec<MonitorChange>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfileParams {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revert_after: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SaveParams {
+    pub name: String,
+    /// Ids, names, device names or 1-based indexes of the monitors to
+    /// save, all if empty
+    #[serde(default)]
+    pub monitors: Vec<String>,
+    #[serde(default)]
+    pub force: bool,
+    /// Pick the profile whenever exactly the monitors connected now are
+    #[serde(default, rename = "match")]
+    pub match_monitors: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SettleParams {
+    /// Keep the change rather than reverting it right away
+    pub keep: bool,
+}
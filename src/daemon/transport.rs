@@ -0,0 +1,171 @@
+//! Where the daemon listens: a Unix domain socket, or a named pipe on
+//! Windows. Either way a connection is a plain byte stream.
+
+use anyhow::{anyhow, Result};
+
+pub use imp::{connect, endpoint, release, Listener, Stream};
+
+/// Fails if a daemon already answers on the endpoint.
+fn ensure_free() -> Result<()> {
+    if connect().is_ok() {
+        return Err(anyhow!(
+            "A yarm daemon is already running on {}",
+            endpoint().display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+mod imp {
+    use anyhow::{anyhow, Result};
+    use std::fs;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+
+    pub type Stream = UnixStream;
+
+    /// `$YARM_SOCKET`, else `yarm.sock` in `$XDG_RUNTIME_DIR`, else a
+    /// per-user socket in /tmp.
+    pub fn endpoint() -> PathBuf {
+        if let Some(path) = std::env::var_os("YARM_SOCKET") {
+            return PathBuf::from(path);
+        }
+        if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+            return PathBuf::from(dir).join("yarm.sock");
+        }
+        PathBuf::from(format!("/tmp/yarm-{}.sock", unsafe { libc::getuid() }))
+    }
+
+    pub fn connect() -> Result<Stream> {
+        Ok(UnixStream::connect(endpoint())?)
+    }
+
+    pub struct Listener {
+        listener: UnixListener,
+    }
+
+    impl Listener {
+        pub fn bind() -> Result<Self> {
+            super::ensure_free()?;
+            let path = endpoint();
+            // Left behind by a daemon that did not stop cleanly
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+            let listener = UnixListener::bind(&path)
+                .map_err(|e| anyhow!("Cannot listen on {}: {}", path.display(), e))?;
+            Ok(Self { listener })
+        }
+
+        pub fn accept(&mut self) -> Result<Stream> {
+            Ok(self.listener.accept()?.0)
+        }
+    }
+
+    /// Removes the socket once the daemon stops.
+    pub fn release() {
+        let _ = fs::remove_file(endpoint());
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use anyhow::{anyhow, Result};
+    use std::fs::{File, OpenOptions};
+    use std::os::windows::io::FromRawHandle;
+    use std::path::PathBuf;
+    use windows::core::HSTRING;
+    use windows::Win32::Foundation::{CloseHandle, ERROR_PIPE_CONNECTED, HANDLE};
+    use windows::Win32::Storage::FileSystem::{
+        FILE_FLAGS_AND_ATTRIBUTES, FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX,
+    };
+    use windows::Win32::System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS,
+        PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+
+    const BUFFER_SIZE: u32 = 64 * 1024;
+
+    pub type Stream = File;
+
+    /// `$YARM_SOCKET`, else a pipe named after the user.
+    pub fn endpoint() -> PathBuf {
+        if let Some(path) = std::env::var_os("YARM_SOCKET") {
+            return PathBuf::from(path);
+        }
+        let user = std::env::var("USERNAME").unwrap_or_default();
+        PathBuf::from(format!(r"\\.\pipe\yarm-{}", user))
+    }
+
+    pub fn connect() -> Result<Stream> {
+        Ok(OpenOptions::new().read(true).write(true).open(endpoint())?)
+    }
+
+    /// A named pipe has one instance per connection, the next one is
+    /// created before waiting for a client.
+    pub struct Listener {
+        // The raw handle, HANDLE itself is not Send
+        next: isize,
+    }
+
+    fn create_instance(first: bool) -> Result<isize> {
+        let flags = if first {
+            PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE
+        } else {
+            PIPE_ACCESS_DUPLEX | FILE_FLAGS_AND_ATTRIBUTES(0)
+        };
+        let handle = unsafe {
+            CreateNamedPipeW(
+                &HSTRING::from(endpoint().as_os_str()),
+                flags,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                BUFFER_SIZE,
+                BUFFER_SIZE,
+                0,
+                None,
+            )
+        };
+        if handle.is_invalid() {
+            return Err(anyhow!(
+                "Cannot listen on {}: {}",
+                endpoint().display(),
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(handle.0 as isize)
+    }
+
+    impl Listener {
+        pub fn bind() -> Result<Self> {
+            super::ensure_free()?;
+            Ok(Self {
+                next: create_instance(true)?,
+            })
+        }
+
+        pub fn accept(&mut self) -> Result<Stream> {
+            let handle = HANDLE(self.next as *mut _);
+            if let Err(e) = unsafe { ConnectNamedPipe(handle, None) } {
+                // The client was quicker than us, which is fine
+                if e.code() != ERROR_PIPE_CONNECTED.to_hresult() {
+                    return Err(e.into());
+                }
+            }
+            self.next = create_instance(false)?;
+            Ok(unsafe { File::from_raw_handle(handle.0) })
+        }
+    }
+
+    impl Drop for Listener {
+        fn drop(&mut self) {
+            unsafe {
+                let _ = CloseHandle(HANDLE(self.next as *mut _));
+            }
+        }
+    }
+
+    /// A pipe goes away with its last handle, nothing to clean up.
+    pub fn release() {}
+}
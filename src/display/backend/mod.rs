@@ -0,0 +1,428 @@
+pub mod sim;
+#[cfg(windows)]
+pub mod win32;
+#[cfg(target_os = "linux")]
+pub mod wlr;
+#[cfg(target_os = "linux")]
+pub mod x11;
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use super::monitor::Monitor;
+use super::orientation::Orientation;
+use super::resolution::Resolution;
+use super::transaction::MonitorChange;
+
+/// What a backend can do beyond the basics every backend supports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// All monitors in a transaction are committed in a single step
+    pub atomic_apply: bool,
+    /// Monitors can be scaled with `set_scale`
+    pub scale: bool,
+    /// One monitor can be made primary with `set_primary`
+    #[serde(default)]
+    pub primary: bool,
+    /// Changes can be reverted on a timer that keeps running after the
+    /// caller exits, see `apply_with_revert`
+    #[serde(default)]
+    pub revert_timer: bool,
+    /// Changes to the monitors are reported by `watch_changes`, so they do
+    /// not have to be polled
+    #[serde(default)]
+    pub change_events: bool,
+}
+
+/// Scale factors accepted by the backends that support scaling.
+pub const SCALE_RANGE: RangeInclusive<f64> = 0.5..=4.0;
+
+/// A platform API capable of reading and changing the display configuration.
+///
+/// Monitors are addressed by their `device_name`, which is whatever the
+/// backend reported in `enumerate_monitors`.
+pub trait DisplayBackend: Send + Sync {
+    /// Short identifier used in logs and error messages.
+    fn name(&self) -> &'static str;
+
+    /// Whether the monitors belong to another process, the daemon, which
+    /// also watches the rules.
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    fn enumerate_monitors(&self) -> Result<Vec<Monitor>>;
+
+    /// Lists the modes supported by a single monitor.
+    ///
+    /// The default implementation re-enumerates everything, backends that can
+    /// query one device cheaply should override it.
+    fn query_modes(&self, device_name: &str) -> Result<Vec<Resolution>> {
+        self.enumerate_monitors()?
+            .into_iter()
+            .find(|m| m.device_name == device_name)
+            .map(|m| m.available_resolutions)
+            .ok_or_else(|| anyhow!("Monitor '{}' not found", device_name))
+    }
+
+    fn set_resolution(&self, device_name: &str, resolution: &Resolution) -> Result<()>;
+
+    fn set_orientation(&self, device_name: &str, orientation: Orientation) -> Result<()>;
+
+    /// Moves a monitor's top left corner to `position` in desktop coordinates.
+    fn set_position(&self, device_name: &str, position: (i32, i32)) -> Result<()>;
+
+    /// Makes a monitor the primary one. Backends where the primary monitor
+    /// defines the desktop origin shift the layout so it sits at (0, 0).
+    fn set_primary(&self, device_name: &str) -> Result<()>;
+
+    /// Attaches a connected monitor to the desktop or detaches it. A monitor
+    /// being attached comes back with the mode and position it last had.
+    fn set_enabled(&self, device_name: &str, enabled: bool) -> Result<()>;
+
+    /// Sets the desktop scale of a monitor. Backends that can should override
+    /// it and report the `scale` capability.
+    fn set_scale(&self, device_name: &str, _scale: f64) -> Result<()> {
+        Err(no_scaling(device_name))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// Applies every change or none of them.
+    ///
+    /// The default implementation emulates this on top of the `set_*` methods,
+    /// rolling back monitors already changed if a later one fails. Backends
+    /// with a native commit should override it and report `atomic_apply`.
+    fn apply(&self, changes: &[MonitorChange]) -> Result<()> {
+        apply_sequentially(self, changes)
+    }
+
+    /// Checks whether each change would be accepted, without applying any.
+    ///
+    /// The default implementation only checks the requested modes against
+    /// the ones the monitor advertises. Backends with a native test (CDS_TEST,
+    /// wlr `test()`) should override it.
+    fn validate(&self, changes: &[MonitorChange]) -> Result<Vec<Verdict>> {
+        validate_against_modes(self, changes)
+    }
+
+    /// Applies the changes and reverts them after `timeout` unless
+    /// `settle_revert` keeps them first. Only backends reporting the
+    /// `revert_timer` capability can, the others leave the timer to the
+    /// caller.
+    fn apply_with_revert(&self, _changes: &[MonitorChange], _timeout: Duration) -> Result<()> {
+        Err(anyhow!("The {} backend has no revert timer", self.name()))
+    }
+
+    /// Keeps or reverts right away the changes of the last
+    /// `apply_with_revert`. Nothing happens if they were already reverted.
+    fn settle_revert(&self, _keep: bool) -> Result<()> {
+        Ok(())
+    }
+
+    /// Starts listening for monitors being plugged, unplugged or
+    /// reconfigured by anyone, yarm included. A message arrives on the
+    /// channel for every event, which may come in bursts; the channel
+    /// closes if the backend stops listening. Backends that can should
+    /// override it and report the `change_events` capability.
+    fn watch_changes(&self) -> Result<Receiver<()>> {
+        Err(anyhow!(
+            "The {} backend cannot report display changes",
+            self.name()
+        ))
+    }
+}
+
+/// Whether the change requested for one monitor would be accepted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Verdict {
+    pub device_name: String,
+    /// Why the change would be refused, `None` if it would be accepted
+    pub rejection: Option<String>,
+}
+
+impl Verdict {
+    pub fn accepted(device_name: &str) -> Self {
+        Self {
+            device_name: device_name.to_string(),
+            rejection: None,
+        }
+    }
+
+    pub fn rejected(device_name: &str, reason: impl std::fmt::Display) -> Self {
+        Self {
+            device_name: device_name.to_string(),
+            rejection: Some(reason.to_string()),
+        }
+    }
+
+    pub fn is_accepted(&self) -> bool {
+        self.rejection.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BackendKind {
+    /// Win32 GDI (ChangeDisplaySettingsEx)
+    Win32,
+    /// X11 RandR
+    X11,
+    /// Wayland wlr-output-management (sway, Hyprland, river, ...)
+    Wlr,
+    /// In-memory monitors loaded from a topology file
+    Sim,
+}
+
+/// Backend specific settings collected from the command line.
+#[derive(Debug, Clone, Default)]
+pub struct BackendOptions {
+    /// Topology file for the sim backend
+    pub topology: Option<PathBuf>,
+}
+
+impl BackendKind {
+    /// Picks the native backend for the platform we are running on.
+    pub fn detect() -> Option<Self> {
+        if cfg!(windows) {
+            Some(BackendKind::Win32)
+        } else if cfg!(target_os = "linux") && std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            Some(BackendKind::Wlr)
+        } else if cfg!(target_os = "linux") && std::env::var_os("DISPLAY").is_some() {
+            Some(BackendKind::X11)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn create(kind: BackendKind, options: &BackendOptions) -> Result<Box<dyn DisplayBackend>> {
+    match kind {
+        BackendKind::Sim => {
+            let path = options
+                .topology
+                .as_deref()
+                .ok_or_else(|| anyhow!("The sim backend needs --topology <FILE>"))?;
+            Ok(Box::new(sim::SimBackend::load(path)?))
+        }
+        #[cfg(windows)]
+        BackendKind::Win32 => Ok(Box::new(win32::Win32Backend)),
+        #[cfg(target_os = "linux")]
+        BackendKind::X11 => Ok(Box::new(x11::X11Backend::connect()?)),
+        #[cfg(target_os = "linux")]
+        BackendKind::Wlr => Ok(Box::new(wlr::WlrBackend::connect()?)),
+        #[allow(unreachable_patterns)]
+        _ => Err(anyhow!(
+            "The {:?} backend is not available on this platform",
+            kind
+        )),
+    }
+}
+
+/// A change translated into the frame of the monitor's current orientation,
+/// which is what `set_resolution` expects.
+struct Step<'a> {
+    monitor: &'a Monitor,
+    resolution: Option<Resolution>,
+    orientation: Orientation,
+    position: (i32, i32),
+    scale: f64,
+    primary: bool,
+    enabled: bool,
+}
+
+fn plan_step<'a, B: DisplayBackend + ?Sized>(
+    backend: &B,
+    monitors: &'a [Monitor],
+    change: &MonitorChange,
+) -> Result<Step<'a>> {
+    let monitor = monitors
+        .iter()
+        .find(|m| m.device_name == change.device_name)
+        .ok_or_else(|| anyhow!("Monitor '{}' not found", change.device_name))?;
+    let enabled = change.enabled.unwrap_or(monitor.enabled);
+    if !enabled && change.configures() {
+        return Err(anyhow!(
+            "Cannot change {}, it would be disabled",
+            monitor.name
+        ));
+    }
+    if let Some(scale) = change.scale {
+        check_scale(backend.capabilities().scale, &monitor.name, scale)?;
+    }
+    let orientation = change.orientation.unwrap_or(monitor.current_orientation);
+    let resolution = change.resolution.as_ref().map(|r| {
+        if orientation.is_portrait() != monitor.current_orientation.is_portrait() {
+            r.rotated()
+        } else {
+            r.clone()
+        }
+    });
+
+    if let Some(resolution) = &resolution {
+        if !backend
+            .query_modes(&monitor.device_name)?
+            .contains(resolution)
+        {
+            return Err(anyhow!("{} does not support {}", monitor.name, resolution));
+        }
+    }
+
+    Ok(Step {
+        monitor,
+        resolution,
+        orientation,
+        position: change.position.unwrap_or(monitor.position),
+        scale: change.scale.unwrap_or(monitor.scale),
+        primary: change.primary,
+        enabled,
+    })
+}
+
+fn no_scaling(device_name: &str) -> anyhow::Error {
+    anyhow!(
+        "Cannot scale {}, the backend does not support scaling",
+        device_name
+    )
+}
+
+/// Fails if a backend without scaling support is asked to scale, or the
+/// scale is outside `SCALE_RANGE`.
+pub fn check_scale(supported: bool, device_name: &str, scale: f64) -> Result<()> {
+    if !supported {
+        return Err(no_scaling(device_name));
+    }
+    if !SCALE_RANGE.contains(&scale) {
+        return Err(anyhow!(
+            "Scale {} of {} is outside {} to {}",
+            scale,
+            device_name,
+            SCALE_RANGE.start(),
+            SCALE_RANGE.end()
+        ));
+    }
+    Ok(())
+}
+
+fn validate_against_modes<B: DisplayBackend + ?Sized>(
+    backend: &B,
+    changes: &[MonitorChange],
+) -> Result<Vec<Verdict>> {
+    let monitors = backend.enumerate_monitors()?;
+    Ok(changes
+        .iter()
+        .map(|change| match plan_step(backend, &monitors, change) {
+            Ok(_) => Verdict::accepted(&change.device_name),
+            Err(e) => Verdict::rejected(&change.device_name, e),
+        })
+        .collect())
+}
+
+fn apply_sequentially<B: DisplayBackend + ?Sized>(
+    backend: &B,
+    changes: &[MonitorChange],
+) -> Result<()> {
+    let monitors = backend.enumerate_monitors()?;
+
+    // Validate everything up front
+    let steps = changes
+        .iter()
+        .map(|change| plan_step(backend, &monitors, change))
+        .collect::<Result<Vec<_>>>()?;
+
+    let primary = monitors.iter().find(|m| m.is_primary);
+    let mut primary_changed = false;
+    let mut applied: Vec<(&Monitor, bool)> = Vec::new();
+    for Step {
+        monitor,
+        resolution,
+        orientation,
+        position,
+        scale,
+        primary: make_primary,
+        enabled,
+    } in steps
+    {
+        let mut result = Ok(());
+        // Attaching restores the last mode, the rest of the change goes on
+        // top of it. A detached monitor has nothing else to change.
+        if enabled != monitor.enabled {
+            result = backend.set_enabled(&monitor.device_name, enabled);
+        }
+        if let Some(resolution) =
+            resolution.filter(|r| result.is_ok() && *r != monitor.current_resolution)
+        {
+            result = backend.set_resolution(&monitor.device_name, &resolution);
+        }
+        if result.is_ok() && orientation != monitor.current_orientation {
+            result = backend.set_orientation(&monitor.device_name, orientation);
+        }
+        if result.is_ok() && scale != monitor.scale {
+            result = backend.set_scale(&monitor.device_name, scale);
+        }
+        if result.is_ok() && position != monitor.position {
+            result = backend.set_position(&monitor.device_name, position);
+        }
+        if result.is_ok() && make_primary && !monitor.is_primary {
+            primary_changed = true;
+            result = backend.set_primary(&monitor.device_name);
+        }
+
+        // Count the failing monitor as applied too, it may be half changed
+        applied.push((monitor, enabled));
+        if let Err(e) = result {
+            roll_back(backend, &applied, primary.filter(|_| primary_changed));
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Puts the `applied` monitors, paired with the enabled state they were
+/// given, back into the state they were enumerated in, most recent first.
+/// `primary` gets its role back if it lost it.
+fn roll_back<B: DisplayBackend + ?Sized>(
+    backend: &B,
+    applied: &[(&Monitor, bool)],
+    primary: Option<&Monitor>,
+) {
+    for &(monitor, enabled) in applied.iter().rev() {
+        let mut restored = Ok(());
+        if enabled != monitor.enabled {
+            restored = backend.set_enabled(&monitor.device_name, monitor.enabled);
+        }
+        if monitor.enabled {
+            restored = restored
+                .and_then(|_| {
+                    backend.set_orientation(&monitor.device_name, monitor.current_orientation)
+                })
+                .and_then(|_| {
+                    backend.set_resolution(&monitor.device_name, &monitor.current_resolution)
+                })
+                .and_then(|_| match backend.capabilities().scale {
+                    true => backend.set_scale(&monitor.device_name, monitor.scale),
+                    false => Ok(()),
+                })
+                .and_then(|_| backend.set_position(&monitor.device_name, monitor.position));
+        }
+        if let Err(rollback) = restored {
+            warn!("Failed to roll back {}: {}", monitor.name, rollback);
+        }
+    }
+    if let Some(primary) = primary {
+        if let Err(rollback) = backend.set_primary(&primary.device_name) {
+            warn!(
+                "Failed to restore {} as primary: {}",
+                primary.name, rollback
+            );
+        }
+    }
+}
@@ -0,0 +1,712 @@
+//! In-memory backend driven by a topology file.
+//!
+//! The topology describes a set of virtual monitors. Changes are applied to the
+//! in-memory copy and, when `persist = true`, written back to the file so the
+//! next invocation picks them up. This lets CI exercise `yarm switch` and the
+//! GUI apply/revert flow on machines without real displays.
+//!
+//! While something watches for changes, edits to the file are picked up too,
+//! which is how monitors are plugged and unplugged here.
+
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use super::{check_scale, Capabilities, DisplayBackend, Verdict};
+use crate::display::edid::Edid;
+use crate::display::identity::MonitorIdentity;
+use crate::display::layout;
+use crate::display::transaction::MonitorChange;
+use crate::display::{Monitor, Orientation, Resolution};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Topology {
+    /// Write state changes back to the topology file
+    #[serde(default)]
+    pub persist: bool,
+    /// Whether monitors can be scaled, off to mimic backends that cannot
+    #[serde(default = "default_true")]
+    pub scaling: bool,
+    #[serde(default)]
+    pub monitors: Vec<SimMonitor>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimMonitor {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub position: (i32, i32),
+    #[serde(default)]
+    pub primary: bool,
+    /// Connected but detached from the desktop when false
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub orientation: Orientation,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    pub current: Resolution,
+    /// Supported modes, `current` is assumed to be the only one if empty
+    #[serde(default)]
+    pub modes: Vec<Resolution>,
+    /// EDID style identity, to test matching profiles across ports
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connector: Option<String>,
+    /// Raw EDID as a hex string, decoded like a real monitor's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fault: Option<Fault>,
+}
+
+// How often a watched topology file is checked for edits
+const FILE_POLL: Duration = Duration::from_millis(250);
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+/// Failure injected into every change made to a monitor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fault {
+    /// The device refuses any new mode or orientation
+    ModeRejected,
+    /// The device vanishes as soon as something tries to change it
+    DeviceGone,
+}
+
+impl SimMonitor {
+    /// The declared modes, which are unrotated. `current` is in the frame
+    /// of `orientation`.
+    fn modes(&self) -> Vec<Resolution> {
+        if self.modes.is_empty() {
+            vec![swap_for(&self.current, self.orientation)]
+        } else {
+            self.modes.clone()
+        }
+    }
+
+    fn to_monitor(&self) -> Monitor {
+        let edid = self.edid.as_deref().and_then(|hex| {
+            decode_hex(hex)
+                .and_then(|bytes| Edid::parse(&bytes))
+                .map_err(|e| warn!("Ignoring EDID of {}: {}", self.id, e))
+                .ok()
+        });
+        // Identity fields in the topology win over the ones from the EDID
+        let from_edid = MonitorIdentity::from_edid(edid.as_ref(), None, &self.name);
+
+        Monitor {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            device_name: self.id.clone(),
+            current_resolution: self.current.clone(),
+            current_orientation: self.orientation,
+            position: self.position,
+            is_primary: self.primary,
+            enabled: self.enabled,
+            scale: self.scale,
+            identity: MonitorIdentity {
+                vendor: self.vendor.clone().or(from_edid.vendor),
+                product: self.product.clone().or(from_edid.product),
+                serial: self.serial.clone().or(from_edid.serial),
+                connector: self.connector.clone(),
+                name: self.name.clone(),
+            },
+            edid,
+            // Reported in the current orientation's frame, like X11 and
+            // Win32 do
+            available_resolutions: self
+                .modes()
+                .iter()
+                .map(|m| swap_for(m, self.orientation))
+                .collect(),
+        }
+    }
+}
+
+struct SimState {
+    topology: Topology,
+    // Monitors that hit a `DeviceGone` fault, hidden until the next start
+    gone: HashSet<String>,
+    // Channels of `watch_changes`
+    listeners: Vec<Sender<()>>,
+}
+
+impl SimState {
+    fn notify(&mut self) {
+        self.listeners.retain(|listener| listener.send(()).is_ok());
+    }
+}
+
+pub struct SimBackend {
+    path: PathBuf,
+    // Shared with the thread watching the file
+    state: Arc<Mutex<SimState>>,
+}
+
+fn read_topology(path: &Path) -> Result<Topology> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read topology {}", path.display()))?;
+    Ok(if is_json(path) {
+        serde_json::from_str(&content)?
+    } else {
+        toml::from_str(&content)?
+    })
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Reloads the topology whenever the file changes, until nobody listens.
+fn watch_file(path: PathBuf, state: Arc<Mutex<SimState>>) {
+    let mut last = modified(&path);
+    loop {
+        thread::sleep(FILE_POLL);
+        let current = modified(&path);
+        if current == last {
+            continue;
+        }
+        last = current;
+        let topology = match read_topology(&path) {
+            Ok(topology) => topology,
+            Err(e) => {
+                warn!("Ignoring the edited topology: {}", e);
+                continue;
+            }
+        };
+        let Ok(mut state) = state.lock() else {
+            return;
+        };
+        state.topology = topology;
+        state.notify();
+        if state.listeners.is_empty() {
+            return;
+        }
+    }
+}
+
+impl SimBackend {
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            state: Arc::new(Mutex::new(SimState {
+                topology: read_topology(path)?,
+                gone: HashSet::new(),
+                listeners: Vec::new(),
+            })),
+        })
+    }
+
+    fn save(&self, topology: &Topology) -> Result<()> {
+        if !topology.persist {
+            return Ok(());
+        }
+        let content = if is_json(&self.path) {
+            serde_json::to_string_pretty(topology)?
+        } else {
+            toml::to_string_pretty(topology)?
+        };
+        fs::write(&self.path, content)?;
+        Ok(())
+    }
+
+    /// Validates every change against the topology and injected faults, then
+    /// applies them all and persists the result.
+    fn apply_changes(&self, changes: &[MonitorChange]) -> Result<()> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| anyhow!("Sim state poisoned"))?;
+        let SimState { topology, gone, .. } = &mut *state;
+
+        // Like Windows, the primary monitor is always at the origin
+        let monitors: Vec<Monitor> = topology
+            .monitors
+            .iter()
+            .filter(|m| !gone.contains(&m.id))
+            .map(SimMonitor::to_monitor)
+            .collect();
+        let changes = layout::reanchor(&monitors, changes);
+
+        let mut updates = Vec::new();
+        for change in &changes {
+            match check_change(topology, gone, change) {
+                Ok(update) => updates.push(update),
+                Err(e) => {
+                    // Actually touching a `DeviceGone` monitor makes it vanish
+                    let faulty = topology
+                        .monitors
+                        .iter()
+                        .any(|m| m.id == change.device_name && m.fault == Some(Fault::DeviceGone));
+                    if faulty {
+                        gone.insert(change.device_name.clone());
+                        state.notify();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut primary = None;
+        for update in updates {
+            let monitor = &mut topology.monitors[update.index];
+            monitor.current = update.resolution;
+            monitor.orientation = update.orientation;
+            monitor.position = update.position;
+            monitor.scale = update.scale;
+            monitor.enabled = update.enabled;
+            if update.primary {
+                primary = Some(update.index);
+            }
+        }
+        if let Some(primary) = primary {
+            for (i, monitor) in topology.monitors.iter_mut().enumerate() {
+                monitor.primary = i == primary;
+            }
+        }
+        self.save(topology)?;
+        state.notify();
+        Ok(())
+    }
+}
+
+fn find_monitor(topology: &Topology, device_name: &str) -> Result<usize> {
+    topology
+        .monitors
+        .iter()
+        .position(|m| m.id == device_name)
+        .ok_or_else(|| anyhow!("Monitor '{}' not found", device_name))
+}
+
+/// State a monitor is left in by a change.
+struct Update {
+    index: usize,
+    resolution: Resolution,
+    orientation: Orientation,
+    position: (i32, i32),
+    scale: f64,
+    primary: bool,
+    enabled: bool,
+}
+
+/// Swaps the axes of a mode for a portrait orientation, which turns it from
+/// the unrotated frame to the frame of `orientation` and back.
+fn swap_for(resolution: &Resolution, orientation: Orientation) -> Resolution {
+    if orientation.is_portrait() {
+        resolution.rotated()
+    } else {
+        resolution.clone()
+    }
+}
+
+/// Works out the state a change would leave its monitor in, failing if the
+/// change would be refused.
+fn check_change(
+    topology: &Topology,
+    gone: &HashSet<String>,
+    change: &MonitorChange,
+) -> Result<Update> {
+    let device_name = &change.device_name;
+    if gone.contains(device_name) {
+        return Err(anyhow!("Device {} is gone", device_name));
+    }
+    let index = find_monitor(topology, device_name)?;
+    let monitor = &topology.monitors[index];
+
+    match monitor.fault {
+        Some(Fault::ModeRejected) => {
+            return Err(anyhow!("Mode rejected by {}", device_name));
+        }
+        Some(Fault::DeviceGone) => {
+            return Err(anyhow!("Device {} is gone", device_name));
+        }
+        None => {}
+    }
+
+    let enabled = change.enabled.unwrap_or(monitor.enabled);
+    if !enabled && change.configures() {
+        return Err(anyhow!(
+            "Cannot change {}, it would be disabled",
+            device_name
+        ));
+    }
+
+    if let Some(scale) = change.scale {
+        check_scale(topology.scaling, device_name, scale)?;
+    }
+
+    let orientation = change.orientation.unwrap_or(monitor.orientation);
+    let resolution = match &change.resolution {
+        Some(resolution) => {
+            // The change is in the frame of its orientation
+            if !monitor.modes().contains(&swap_for(resolution, orientation)) {
+                return Err(anyhow!("Mode {} rejected by {}", resolution, monitor.id));
+            }
+            resolution.clone()
+        }
+        None if orientation.is_portrait() != monitor.orientation.is_portrait() => {
+            monitor.current.rotated()
+        }
+        None => monitor.current.clone(),
+    };
+
+    Ok(Update {
+        index,
+        resolution,
+        orientation,
+        position: change.position.unwrap_or(monitor.position),
+        scale: change.scale.unwrap_or(monitor.scale),
+        primary: change.primary,
+        enabled,
+    })
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    digits
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                .filter(|_| pair.len() == 2)
+                .ok_or_else(|| anyhow!("Invalid hex in EDID"))
+        })
+        .collect()
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+impl DisplayBackend for SimBackend {
+    fn name(&self) -> &'static str {
+        "sim"
+    }
+
+    fn enumerate_monitors(&self) -> Result<Vec<Monitor>> {
+        let state = self
+            .state
+            .lock()
+            .map_err(|_| anyhow!("Sim state poisoned"))?;
+        Ok(state
+            .topology
+            .monitors
+            .iter()
+            .filter(|m| !state.gone.contains(&m.id))
+            .map(SimMonitor::to_monitor)
+            .collect())
+    }
+
+    fn set_resolution(&self, device_name: &str, resolution: &Resolution) -> Result<()> {
+        let mut change = MonitorChange::new(device_name);
+        change.resolution = Some(resolution.clone());
+        self.apply_changes(&[change])
+    }
+
+    fn set_orientation(&self, device_name: &str, orientation: Orientation) -> Result<()> {
+        let mut change = MonitorChange::new(device_name);
+        change.orientation = Some(orientation);
+        self.apply_changes(&[change])
+    }
+
+    fn set_position(&self, device_name: &str, position: (i32, i32)) -> Result<()> {
+        let mut change = MonitorChange::new(device_name);
+        change.position = Some(position);
+        self.apply_changes(&[change])
+    }
+
+    fn set_primary(&self, device_name: &str) -> Result<()> {
+        let mut change = MonitorChange::new(device_name);
+        change.primary = true;
+        self.apply_changes(&[change])
+    }
+
+    fn set_enabled(&self, device_name: &str, enabled: bool) -> Result<()> {
+        let mut change = MonitorChange::new(device_name);
+        change.enabled = Some(enabled);
+        self.apply_changes(&[change])
+    }
+
+    fn set_scale(&self, device_name: &str, scale: f64) -> Result<()> {
+        let mut change = MonitorChange::new(device_name);
+        change.scale = Some(scale);
+        self.apply_changes(&[change])
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let scaling = self.state.lock().is_ok_and(|s| s.topology.scaling);
+        Capabilities {
+            atomic_apply: true,
+            scale: scaling,
+            primary: true,
+            change_events: true,
+            ..Default::default()
+        }
+    }
+
+    fn watch_changes(&self) -> Result<Receiver<()>> {
+        let (sender, receiver) = mpsc::channel();
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| anyhow!("Sim state poisoned"))?;
+        // One thread watches the file for every listener
+        if state.listeners.is_empty() {
+            let (path, shared) = (self.path.clone(), self.state.clone());
+            thread::spawn(move || watch_file(path, shared));
+        }
+        state.listeners.push(sender);
+        Ok(receiver)
+    }
+
+    fn apply(&self, changes: &[MonitorChange]) -> Result<()> {
+        self.apply_changes(changes)
+    }
+
+    fn validate(&self, changes: &[MonitorChange]) -> Result<Vec<Verdict>> {
+        let state = self
+            .state
+            .lock()
+            .map_err(|_| anyhow!("Sim state poisoned"))?;
+        Ok(changes
+            .iter()
+            .map(
+                |change| match check_change(&state.topology, &state.gone, change) {
+                    Ok(_) => Verdict::accepted(&change.device_name),
+                    Err(e) => Verdict::rejected(&change.device_name, e),
+                },
+            )
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESK: &str = r#"
+persist = true
+
+[[monitors]]
+id = "A"
+name = "Left"
+primary = true
+current = "1920x1080@60"
+modes = ["1920x1080@60", "1280x720@60"]
+
+[[monitors]]
+id = "B"
+name = "Right"
+position = [1920, 0]
+current = "2560x1440@144"
+modes = ["2560x1440@144", "1920x1080@60"]
+"#;
+
+    /// Writes `content` to a topology file of its own for the test `name`.
+    fn topology_file(name: &str, content: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("yarm-sim-{}-{}.toml", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn monitor<'a>(monitors: &'a [Monitor], id: &str) -> &'a Monitor {
+        monitors.iter().find(|m| m.id == id).unwrap()
+    }
+
+    /// Everything the backend reports, comparable across instances.
+    fn snapshot(sim: &SimBackend) -> String {
+        format!("{:?}", sim.enumerate_monitors().unwrap())
+    }
+
+    fn mode(text: &str) -> Resolution {
+        text.parse().unwrap()
+    }
+
+    fn change(device_name: &str) -> MonitorChange {
+        MonitorChange::new(device_name)
+    }
+
+    #[test]
+    fn applies_changes() {
+        let path = topology_file("apply", DESK);
+        let sim = SimBackend::load(&path).unwrap();
+        let changes = [
+            MonitorChange {
+                resolution: Some(mode("1280x720@60")),
+                ..change("A")
+            },
+            MonitorChange {
+                primary: true,
+                ..change("B")
+            },
+        ];
+        sim.apply(&changes).unwrap();
+
+        let monitors = sim.enumerate_monitors().unwrap();
+        let (a, b) = (monitor(&monitors, "A"), monitor(&monitors, "B"));
+        assert_eq!(a.current_resolution, mode("1280x720@60"));
+        assert!(b.is_primary && !a.is_primary);
+        // The new primary moves to the origin and takes the others along
+        assert_eq!((a.position, b.position), ((-1920, 0), (0, 0)));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reports_modes_in_the_current_orientation() {
+        let path = topology_file("rotate", DESK);
+        let sim = SimBackend::load(&path).unwrap();
+        sim.set_orientation("B", Orientation::Portrait).unwrap();
+
+        let monitors = sim.enumerate_monitors().unwrap();
+        let b = monitor(&monitors, "B");
+        assert_eq!(b.current_resolution, mode("1440x2560@144"));
+        assert_eq!(
+            b.available_resolutions,
+            [mode("1440x2560@144"), mode("1080x1920@60")]
+        );
+        assert!(sim.set_resolution("B", &mode("1080x1920@60")).is_ok());
+        assert!(sim.set_resolution("B", &mode("1920x1080@60")).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn refuses_impossible_changes() {
+        let path = topology_file(
+            "refuse",
+            &DESK.replacen("persist = true", "scaling = false", 1),
+        );
+        let sim = SimBackend::load(&path).unwrap();
+        let error = |c: MonitorChange| sim.apply(&[c]).unwrap_err().to_string();
+
+        assert!(error(MonitorChange {
+            resolution: Some(mode("3840x2160@60")),
+            ..change("A")
+        })
+        .contains("rejected"));
+        assert!(error(MonitorChange {
+            scale: Some(1.5),
+            ..change("A")
+        })
+        .contains("scal"));
+        assert!(error(MonitorChange {
+            enabled: Some(false),
+            position: Some((0, 1080)),
+            ..change("B")
+        })
+        .contains("disabled"));
+        assert!(error(change("C")).contains("not found"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn a_rejected_mode_rolls_back_the_whole_change() {
+        let faulty = format!("{}fault = \"mode-rejected\"\n", DESK);
+        let path = topology_file("mode-rejected", &faulty);
+        let sim = SimBackend::load(&path).unwrap();
+        let before = snapshot(&sim);
+        let changes = [
+            MonitorChange {
+                resolution: Some(mode("1280x720@60")),
+                ..change("A")
+            },
+            MonitorChange {
+                resolution: Some(mode("1920x1080@60")),
+                ..change("B")
+            },
+        ];
+
+        let verdicts = sim.validate(&changes).unwrap();
+        assert!(verdicts[0].rejection.is_none());
+        assert!(verdicts[1]
+            .rejection
+            .as_deref()
+            .is_some_and(|r| r.contains("rejected")));
+        assert!(sim.apply(&changes).is_err());
+        assert_eq!(snapshot(&sim), before);
+        assert_eq!(fs::read_to_string(&path).unwrap(), faulty);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn a_gone_device_vanishes_once_touched() {
+        let path = topology_file("gone", &format!("{}fault = \"device-gone\"\n", DESK));
+        let sim = SimBackend::load(&path).unwrap();
+        let events = sim.watch_changes().unwrap();
+        assert_eq!(sim.enumerate_monitors().unwrap().len(), 2);
+
+        assert!(sim.set_primary("B").is_err());
+        let monitors = sim.enumerate_monitors().unwrap();
+        assert_eq!(monitors.len(), 1);
+        assert_eq!(monitors[0].id, "A");
+        assert!(events.try_recv().is_ok());
+        assert!(sim
+            .set_primary("B")
+            .unwrap_err()
+            .to_string()
+            .contains("gone"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn persists_the_state() {
+        let path = topology_file("persist", DESK);
+        let sim = SimBackend::load(&path).unwrap();
+        sim.set_orientation("A", Orientation::Portrait).unwrap();
+        sim.set_enabled("B", false).unwrap();
+        let expected = snapshot(&sim);
+        assert_eq!(snapshot(&SimBackend::load(&path).unwrap()), expected);
+        fs::remove_file(&path).unwrap();
+
+        // Without `persist` the file is left alone
+        let transient = DESK.replacen("persist = true", "", 1);
+        let path = topology_file("transient", &transient);
+        let sim = SimBackend::load(&path).unwrap();
+        sim.set_orientation("A", Orientation::Portrait).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), transient);
+        let reloaded = SimBackend::load(&path)
+            .unwrap()
+            .enumerate_monitors()
+            .unwrap();
+        assert_eq!(
+            monitor(&reloaded, "A").current_orientation,
+            Orientation::Landscape
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reads_json_topologies() {
+        let path = std::env::temp_dir().join(format!("yarm-sim-{}-json.json", std::process::id()));
+        let topology: Topology = toml::from_str(DESK).unwrap();
+        fs::write(&path, serde_json::to_string(&topology).unwrap()).unwrap();
+        let sim = SimBackend::load(&path).unwrap();
+        sim.set_primary("B").unwrap();
+        let saved = read_topology(&path).unwrap();
+        assert!(saved.monitors[1].primary && !saved.monitors[0].primary);
+        assert_eq!(saved.monitors[1].position, (0, 0));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,1030 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use windows::core::{w, PCWSTR};
+use windows::Win32::Devices::Display::{
+    DisplayConfigGetDeviceInfo, GetDisplayConfigBufferSizes, QueryDisplayConfig, SetDisplayConfig,
+    DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME, DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
+    DISPLAYCONFIG_MODE_INFO, DISPLAYCONFIG_PATH_INFO, DISPLAYCONFIG_SOURCE_DEVICE_NAME,
+    DISPLAYCONFIG_TARGET_DEVICE_NAME, QDC_ONLY_ACTIVE_PATHS, SDC_APPLY, SDC_TOPOLOGY_CLONE,
+    SDC_TOPOLOGY_EXTEND, SDC_VALIDATE, SET_DISPLAY_CONFIG_FLAGS,
+};
+use windows::Win32::Foundation::{
+    ERROR_SUCCESS, HINSTANCE, HWND, LPARAM, LRESULT, POINTL, WPARAM,
+};
+use windows::Win32::Graphics::Gdi::{
+    ChangeDisplaySettingsExW, EnumDisplayDevicesW, EnumDisplaySettingsW, CDS_GLOBAL, CDS_NORESET,
+    CDS_SET_PRIMARY, CDS_TEST, CDS_TYPE, CDS_UPDATEREGISTRY, DEVMODEW, DEVMODE_DISPLAY_ORIENTATION,
+    DEVMODE_FIELD_FLAGS, DISPLAY_DEVICEW, DISPLAY_DEVICE_ATTACHED_TO_DESKTOP, DISP_CHANGE,
+    DISP_CHANGE_SUCCESSFUL, DMDFO_CENTER, DMDFO_DEFAULT, DMDFO_STRETCH, DM_BITSPERPEL,
+    DM_DISPLAYFIXEDOUTPUT, DM_DISPLAYFLAGS, DM_DISPLAYFREQUENCY, DM_DISPLAYORIENTATION,
+    DM_INTERLACED, DM_PELSHEIGHT, DM_PELSWIDTH, DM_POSITION, ENUM_CURRENT_SETTINGS,
+    ENUM_DISPLAY_SETTINGS_MODE, ENUM_REGISTRY_SETTINGS,
+};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::System::Registry::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_BINARY};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetMessageW,
+    PostQuitMessage, RegisterClassW, HMENU, MSG, WINDOW_EX_STYLE, WINDOW_STYLE, WM_DISPLAYCHANGE,
+    WNDCLASSW,
+};
+
+use super::{check_scale, Capabilities, DisplayBackend, Verdict};
+use crate::display::edid::Edid;
+use crate::display::identity::{pnp_id, MonitorIdentity};
+use crate::display::layout;
+use crate::display::transaction::MonitorChange;
+use crate::display::{Monitor, Orientation, RefreshRate, Resolution, ScalingMode};
+
+pub struct Win32Backend;
+
+/// What QueryDisplayConfig tells us about the monitor behind a GDI device.
+struct TargetInfo {
+    friendly_name: String,
+    identity: MonitorIdentity,
+    edid: Option<Edid>,
+}
+
+// DISPLAYCONFIG_TARGET_DEVICE_NAME_FLAGS.edidIdsValid
+const EDID_IDS_VALID: u32 = 0x4;
+
+const MIRROR_ALL: &str = "Windows can only mirror all monitors together";
+
+/// A device whose new settings have been validated, ready to be written.
+struct Staged {
+    device_name: Vec<u16>,
+    original: DEVMODEW,
+    target: DEVMODEW,
+    // Whether the device was attached to the desktop before the change
+    attached: bool,
+    primary: bool,
+}
+
+impl Win32Backend {
+    /// The source to target paths currently driving a monitor, empty if
+    /// QueryDisplayConfig fails.
+    fn active_paths() -> Vec<DISPLAYCONFIG_PATH_INFO> {
+        let mut num_paths = 0;
+        let mut num_modes = 0;
+
+        unsafe {
+            if GetDisplayConfigBufferSizes(QDC_ONLY_ACTIVE_PATHS, &mut num_paths, &mut num_modes)
+                != ERROR_SUCCESS
+            {
+                return Vec::new();
+            }
+
+            let mut paths = vec![DISPLAYCONFIG_PATH_INFO::default(); num_paths as usize];
+            let mut modes = vec![DISPLAYCONFIG_MODE_INFO::default(); num_modes as usize];
+
+            if QueryDisplayConfig(
+                QDC_ONLY_ACTIVE_PATHS,
+                &mut num_paths,
+                paths.as_mut_ptr(),
+                &mut num_modes,
+                modes.as_mut_ptr(),
+                None,
+            ) != ERROR_SUCCESS
+            {
+                return Vec::new();
+            }
+
+            // Resize vector to actual returned count, just in case
+            paths.truncate(num_paths as usize);
+            paths
+        }
+    }
+
+    /// Whether two monitors are fed by the same source, i.e. the desktop is
+    /// in clone mode. Cloned monitors show up as a single GDI device.
+    fn is_cloned() -> bool {
+        let mut sources: Vec<_> = Self::active_paths()
+            .iter()
+            .map(|p| {
+                let adapter = p.sourceInfo.adapterId;
+                (adapter.HighPart, adapter.LowPart, p.sourceInfo.id)
+            })
+            .collect();
+        let count = sources.len();
+        sources.sort_unstable();
+        sources.dedup();
+        sources.len() < count
+    }
+
+    /// Switches every monitor to one of the built-in topologies, or only
+    /// checks that it would work with `SDC_VALIDATE`.
+    fn set_topology(
+        topology: SET_DISPLAY_CONFIG_FLAGS,
+        mode: SET_DISPLAY_CONFIG_FLAGS,
+    ) -> Result<()> {
+        let result = unsafe { SetDisplayConfig(None, None, topology | mode) };
+        if result != ERROR_SUCCESS.0 as i32 {
+            return Err(anyhow!(
+                "Failed to change the display topology. Error code: {}",
+                result
+            ));
+        }
+        Ok(())
+    }
+
+    /// Applies `changes` device by device through GDI, validating all of
+    /// them with CDS_TEST before touching anything.
+    fn apply_settings(&self, changes: &[MonitorChange]) -> Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        // The primary monitor defines the origin, moving it moves everyone
+        let changes = layout::reanchor(&self.enumerate_monitors()?, changes);
+
+        let staged = changes
+            .iter()
+            .map(Self::test_change)
+            .collect::<Result<Vec<_>>>()?;
+
+        // Write all devices to the registry without resetting, then switch
+        // them in a single call
+        for (i, s) in staged.iter().enumerate() {
+            let mut flags = CDS_UPDATEREGISTRY | CDS_GLOBAL | CDS_NORESET;
+            if s.primary {
+                flags |= CDS_SET_PRIMARY;
+            }
+            let result = Self::change_settings(
+                PCWSTR::from_raw(s.device_name.as_ptr()),
+                Some(&s.target),
+                flags,
+            );
+            if result != DISP_CHANGE_SUCCESSFUL {
+                Self::restore(&staged[..i], false);
+                return Err(anyhow!(
+                    "Failed to stage display settings. Error code: {:?}",
+                    result
+                ));
+            }
+        }
+
+        let result = Self::change_settings(PCWSTR::null(), None, CDS_TYPE(0));
+        if result != DISP_CHANGE_SUCCESSFUL {
+            Self::restore(&staged, true);
+            return Err(anyhow!(
+                "Failed to commit display settings. Error code: {:?}",
+                result
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// What is left to apply of `changes` once the desktop is cloned. Only
+    /// the monitors still attached are their own GDI device, the others
+    /// show the same source, and everyone sits at the origin.
+    fn cloned_changes(&self, changes: &[MonitorChange]) -> Result<Vec<MonitorChange>> {
+        let attached: Vec<String> = self
+            .enumerate_monitors()?
+            .into_iter()
+            .filter(|m| m.enabled)
+            .map(|m| m.device_name)
+            .collect();
+        Ok(changes
+            .iter()
+            .filter(|c| attached.contains(&c.device_name))
+            .map(|c| MonitorChange {
+                position: None,
+                enabled: None,
+                ..c.clone()
+            })
+            .filter(|c| c.configures())
+            .collect())
+    }
+
+    /// `Some(true)` if `changes` mirror every enabled monitor, `Some(false)`
+    /// if they mirror only some of them, which Windows cannot do.
+    fn plans_clone(monitors: &[Monitor], changes: &[MonitorChange]) -> Option<bool> {
+        let groups = layout::mirror_groups(&layout::planned(monitors, changes));
+        groups
+            .iter()
+            .any(|group| group.len() > 1)
+            .then_some(groups.len() == 1)
+    }
+
+    // Helper to get a map of GDI Device Name -> Friendly Name and identity using QueryDisplayConfig
+    fn get_display_names_map() -> HashMap<String, TargetInfo> {
+        let mut names_map = HashMap::new();
+
+        unsafe {
+            for path in Self::active_paths() {
+                // 1. Get Source Name (GDI Device Name)
+                let mut source_name = DISPLAYCONFIG_SOURCE_DEVICE_NAME::default();
+                source_name.header.r#type = DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME;
+                source_name.header.size = mem::size_of::<DISPLAYCONFIG_SOURCE_DEVICE_NAME>() as u32;
+                source_name.header.adapterId = path.sourceInfo.adapterId;
+                source_name.header.id = path.sourceInfo.id;
+
+                if DisplayConfigGetDeviceInfo(&mut source_name.header) == ERROR_SUCCESS.0 as i32 {
+                    let gdi_device_name = String::from_utf16_lossy(&source_name.viewGdiDeviceName)
+                        .trim_matches(char::from(0))
+                        .to_string();
+
+                    // 2. Get Target Name (Friendly Name)
+                    let mut target_name = DISPLAYCONFIG_TARGET_DEVICE_NAME::default();
+                    target_name.header.r#type = DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME;
+                    target_name.header.size =
+                        mem::size_of::<DISPLAYCONFIG_TARGET_DEVICE_NAME>() as u32;
+                    target_name.header.adapterId = path.targetInfo.adapterId;
+                    target_name.header.id = path.targetInfo.id;
+
+                    if DisplayConfigGetDeviceInfo(&mut target_name.header) == ERROR_SUCCESS.0 as i32
+                    {
+                        let friendly_name =
+                            String::from_utf16_lossy(&target_name.monitorFriendlyDeviceName)
+                                .trim_matches(char::from(0))
+                                .to_string();
+
+                        let device_path = String::from_utf16_lossy(&target_name.monitorDevicePath)
+                            .trim_matches(char::from(0))
+                            .to_string();
+
+                        let edid =
+                            Self::read_edid(&device_path).and_then(|data| Edid::parse(&data).ok());
+                        let connector = (!device_path.is_empty()).then_some(device_path);
+
+                        // Without a cached EDID fall back to the ids Windows
+                        // extracted, stored with the EDID byte order swapped
+                        let mut identity = MonitorIdentity::from_edid(edid.as_ref(), connector, "");
+                        if edid.is_none() && target_name.flags.Anonymous.value & EDID_IDS_VALID != 0
+                        {
+                            identity.vendor = pnp_id(target_name.edidManufactureId.swap_bytes());
+                            identity.product =
+                                Some(format!("{:04X}", target_name.edidProductCodeId));
+                        }
+
+                        names_map.insert(
+                            gdi_device_name,
+                            TargetInfo {
+                                friendly_name,
+                                identity,
+                                edid,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+        names_map
+    }
+
+    /// Reads the EDID Windows cached in the registry for a monitor, given the
+    /// device path from QueryDisplayConfig, e.g.
+    /// `\\?\DISPLAY#DEL4092#5&1a2b&0&UID4353#{e6f07b5f-...}`.
+    fn read_edid(device_path: &str) -> Option<Vec<u8>> {
+        let instance = device_path
+            .trim_start_matches(r"\\?\")
+            .split('#')
+            .take(3)
+            .collect::<Vec<_>>()
+            .join("\\");
+        let subkey: Vec<u16> = format!(
+            r"SYSTEM\CurrentControlSet\Enum\{}\Device Parameters",
+            instance
+        )
+        .encode_utf16()
+        .chain(Some(0))
+        .collect();
+        let value: Vec<u16> = "EDID".encode_utf16().chain(Some(0)).collect();
+
+        unsafe {
+            let mut size = 0u32;
+            if RegGetValueW(
+                HKEY_LOCAL_MACHINE,
+                PCWSTR::from_raw(subkey.as_ptr()),
+                PCWSTR::from_raw(value.as_ptr()),
+                RRF_RT_REG_BINARY,
+                None,
+                None,
+                Some(&mut size),
+            ) != ERROR_SUCCESS
+            {
+                return None;
+            }
+
+            let mut data = vec![0u8; size as usize];
+            if RegGetValueW(
+                HKEY_LOCAL_MACHINE,
+                PCWSTR::from_raw(subkey.as_ptr()),
+                PCWSTR::from_raw(value.as_ptr()),
+                RRF_RT_REG_BINARY,
+                None,
+                Some(data.as_mut_ptr() as *mut c_void),
+                Some(&mut size),
+            ) != ERROR_SUCCESS
+            {
+                return None;
+            }
+            data.truncate(size as usize);
+            Some(data)
+        }
+    }
+
+    /// Current settings of a device, or with ENUM_REGISTRY_SETTINGS the ones
+    /// stored for it, which is all a detached device has.
+    fn display_settings(
+        device_name: PCWSTR,
+        which: ENUM_DISPLAY_SETTINGS_MODE,
+    ) -> Option<DEVMODEW> {
+        let mut dev_mode = DEVMODEW {
+            dmSize: mem::size_of::<DEVMODEW>() as u16,
+            ..Default::default()
+        };
+        let found = unsafe { EnumDisplaySettingsW(device_name, which, &mut dev_mode) };
+        found.as_bool().then_some(dev_mode)
+    }
+
+    /// State flags of a GDI display device, `None` if there is no such device.
+    fn device_flags(device_name: &str) -> Option<u32> {
+        let mut dev_num = 0;
+        loop {
+            let mut display_device = DISPLAY_DEVICEW {
+                cb: mem::size_of::<DISPLAY_DEVICEW>() as u32,
+                ..Default::default()
+            };
+            if !unsafe { EnumDisplayDevicesW(None, dev_num, &mut display_device, 0) }.as_bool() {
+                return None;
+            }
+            let name = String::from_utf16_lossy(&display_device.DeviceName);
+            if name.trim_matches(char::from(0)) == device_name {
+                return Some(display_device.StateFlags);
+            }
+            dev_num += 1;
+        }
+    }
+
+    /// The monitor plugged into a display adapter output, if any.
+    fn monitor_device(device_name: PCWSTR) -> Option<DISPLAY_DEVICEW> {
+        let mut monitor_device = DISPLAY_DEVICEW {
+            cb: mem::size_of::<DISPLAY_DEVICEW>() as u32,
+            ..Default::default()
+        };
+        unsafe { EnumDisplayDevicesW(device_name, 0, &mut monitor_device, 0) }
+            .as_bool()
+            .then_some(monitor_device)
+    }
+
+    /// The mode a DEVMODE describes.
+    fn resolution_of(dev_mode: &DEVMODEW) -> Resolution {
+        let (flags, fixed_output) = unsafe {
+            (
+                dev_mode.Anonymous2.dmDisplayFlags,
+                dev_mode.Anonymous1.Anonymous2.dmDisplayFixedOutput,
+            )
+        };
+        Resolution {
+            width: dev_mode.dmPelsWidth,
+            height: dev_mode.dmPelsHeight,
+            // 0 stands for the hardware default, as would a bogus rate
+            refresh: RefreshRate::from_hz(dev_mode.dmDisplayFrequency)
+                .unwrap_or(RefreshRate::from_millihertz(0)),
+            bits_per_pixel: dev_mode.dmBitsPerPel,
+            interlaced: flags & DM_INTERLACED.0 != 0,
+            scaling: match fixed_output {
+                DMDFO_STRETCH => ScalingMode::Stretch,
+                DMDFO_CENTER => ScalingMode::Center,
+                _ => ScalingMode::Default,
+            },
+        }
+    }
+
+    /// Writes `resolution` into `dev_mode`, returning the fields it set.
+    /// GDI has no fixed output value for keeping the aspect ratio.
+    fn set_mode(dev_mode: &mut DEVMODEW, resolution: &Resolution) -> Result<DEVMODE_FIELD_FLAGS> {
+        let fixed_output = match resolution.scaling {
+            ScalingMode::Default => DMDFO_DEFAULT,
+            ScalingMode::Stretch => DMDFO_STRETCH,
+            ScalingMode::Center => DMDFO_CENTER,
+            ScalingMode::Aspect => {
+                return Err(anyhow!(
+                    "Windows cannot select {} scaling for {}",
+                    resolution.scaling.to_string().to_lowercase(),
+                    resolution
+                ))
+            }
+        };
+        dev_mode.dmPelsWidth = resolution.width;
+        dev_mode.dmPelsHeight = resolution.height;
+        dev_mode.dmDisplayFrequency = resolution.refresh.whole_hz();
+        dev_mode.dmBitsPerPel = resolution.bits_per_pixel;
+        unsafe {
+            let flags = dev_mode.Anonymous2.dmDisplayFlags & !DM_INTERLACED.0;
+            dev_mode.Anonymous2.dmDisplayFlags = if resolution.interlaced {
+                flags | DM_INTERLACED.0
+            } else {
+                flags
+            };
+            dev_mode.Anonymous1.Anonymous2.dmDisplayFixedOutput = fixed_output;
+        }
+        Ok(DM_PELSWIDTH
+            | DM_PELSHEIGHT
+            | DM_DISPLAYFREQUENCY
+            | DM_BITSPERPEL
+            | DM_DISPLAYFLAGS
+            | DM_DISPLAYFIXEDOUTPUT)
+    }
+
+    /// Builds the settings `change` asks for on top of the current ones.
+    /// A device being attached always gets a position, Windows needs one.
+    fn target_mode(
+        current: &DEVMODEW,
+        change: &MonitorChange,
+        attaching: bool,
+    ) -> Result<DEVMODEW> {
+        let mut dev_mode = *current;
+        let old_orientation =
+            unsafe { Orientation::from_u32(dev_mode.Anonymous1.Anonymous2.dmDisplayOrientation.0) };
+        let orientation = change.orientation.unwrap_or(old_orientation);
+
+        let mode_fields = match &change.resolution {
+            Some(resolution) => Self::set_mode(&mut dev_mode, resolution)?,
+            None => {
+                if orientation.is_portrait() != old_orientation.is_portrait() {
+                    mem::swap(&mut dev_mode.dmPelsWidth, &mut dev_mode.dmPelsHeight);
+                }
+                DM_PELSWIDTH | DM_PELSHEIGHT | DM_DISPLAYFREQUENCY | DM_BITSPERPEL
+            }
+        };
+
+        dev_mode.Anonymous1.Anonymous2.dmDisplayOrientation =
+            DEVMODE_DISPLAY_ORIENTATION(orientation.to_u32());
+        dev_mode.dmFields = mode_fields | DM_DISPLAYORIENTATION;
+
+        // Leaving the position out lets Windows reflow the other monitors
+        if let Some((x, y)) = change.position {
+            dev_mode.Anonymous1.Anonymous2.dmPosition = POINTL { x, y };
+            dev_mode.dmFields |= DM_POSITION;
+        }
+        if attaching {
+            dev_mode.dmFields |= DM_POSITION;
+        }
+        Ok(dev_mode)
+    }
+
+    /// Settings that detach a device from the desktop: an empty mode.
+    fn detached_mode(current: &DEVMODEW) -> DEVMODEW {
+        let mut dev_mode = *current;
+        dev_mode.dmPelsWidth = 0;
+        dev_mode.dmPelsHeight = 0;
+        dev_mode.Anonymous1.Anonymous2.dmPosition = POINTL { x: 0, y: 0 };
+        dev_mode.dmFields = DM_PELSWIDTH | DM_PELSHEIGHT | DM_POSITION;
+        dev_mode
+    }
+
+    fn change_settings(
+        device_name: PCWSTR,
+        dev_mode: Option<&DEVMODEW>,
+        flags: CDS_TYPE,
+    ) -> DISP_CHANGE {
+        unsafe {
+            ChangeDisplaySettingsExW(
+                device_name,
+                dev_mode.map(|m| m as *const DEVMODEW),
+                HWND(std::ptr::null_mut()),
+                flags,
+                None,
+            )
+        }
+    }
+
+    /// Asks the driver whether it would accept a change, using CDS_TEST.
+    fn test_change(change: &MonitorChange) -> Result<Staged> {
+        let device_name: Vec<u16> = change.device_name.encode_utf16().chain(Some(0)).collect();
+        let device_name_pcwstr = PCWSTR::from_raw(device_name.as_ptr());
+        let flags = Self::device_flags(&change.device_name)
+            .ok_or_else(|| anyhow!("Monitor '{}' not found", change.device_name))?;
+        let attached = flags & DISPLAY_DEVICE_ATTACHED_TO_DESKTOP != 0;
+        let which = if attached {
+            ENUM_CURRENT_SETTINGS
+        } else {
+            ENUM_REGISTRY_SETTINGS
+        };
+        let mut original = Self::display_settings(device_name_pcwstr, which)
+            .ok_or_else(|| anyhow!("Monitor '{}' not found", change.device_name))?;
+        // A device that was never attached has an empty registry mode, it
+        // comes up in its best mode as `enumerate_monitors` reports it
+        if !attached && original.dmPelsWidth == 0 {
+            if let Some(best) = Self::enum_modes(device_name_pcwstr).first() {
+                Self::set_mode(&mut original, best)?;
+            }
+        }
+
+        // GDI works in physical pixels, DPI scaling is not reachable from here
+        if let Some(scale) = change.scale {
+            check_scale(false, &change.device_name, scale)?;
+        }
+        let enabled = change.enabled.unwrap_or(attached);
+        if !enabled && change.configures() {
+            return Err(anyhow!(
+                "Cannot change {}, it would be disabled",
+                change.device_name
+            ));
+        }
+        let target = if enabled {
+            Self::target_mode(&original, change, !attached)?
+        } else {
+            Self::detached_mode(&original)
+        };
+
+        let result = Self::change_settings(device_name_pcwstr, Some(&target), CDS_TEST);
+        if result != DISP_CHANGE_SUCCESSFUL {
+            return Err(anyhow!(
+                "{} rejected the new settings. Error code: {:?}",
+                change.device_name,
+                result
+            ));
+        }
+        Ok(Staged {
+            device_name,
+            original,
+            target,
+            attached,
+            primary: change.primary,
+        })
+    }
+
+    /// Writes the original settings of `staged` back to the registry, and
+    /// switches to them if `commit` is set.
+    fn restore(staged: &[Staged], commit: bool) {
+        for s in staged {
+            let mut original = s.original;
+            // The primary monitor is the one at the origin
+            let position = unsafe { original.Anonymous1.Anonymous2.dmPosition };
+            let mut flags = CDS_UPDATEREGISTRY | CDS_GLOBAL | CDS_NORESET;
+            if s.attached && (position.x, position.y) == (0, 0) {
+                flags |= CDS_SET_PRIMARY;
+            }
+            original.dmFields = DM_PELSWIDTH
+                | DM_PELSHEIGHT
+                | DM_DISPLAYFREQUENCY
+                | DM_BITSPERPEL
+                | DM_DISPLAYFLAGS
+                | DM_DISPLAYFIXEDOUTPUT
+                | DM_DISPLAYORIENTATION
+                | DM_POSITION;
+            if !s.attached {
+                original = Self::detached_mode(&original);
+            }
+            Self::change_settings(
+                PCWSTR::from_raw(s.device_name.as_ptr()),
+                Some(&original),
+                flags,
+            );
+        }
+        if commit {
+            Self::change_settings(PCWSTR::null(), None, CDS_TYPE(0));
+        }
+    }
+
+    fn enum_modes(device_name: PCWSTR) -> Vec<Resolution> {
+        let mut resolutions = Vec::new();
+        let mut mode_num = 0;
+        loop {
+            let mut mode = DEVMODEW {
+                dmSize: mem::size_of::<DEVMODEW>() as u16,
+                ..Default::default()
+            };
+            let success = unsafe {
+                EnumDisplaySettingsW(device_name, ENUM_DISPLAY_SETTINGS_MODE(mode_num), &mut mode)
+            };
+
+            if !success.as_bool() {
+                break;
+            }
+
+            let res = Self::resolution_of(&mode);
+
+            // Basic de-duplication
+            if !resolutions.contains(&res) {
+                resolutions.push(res);
+            }
+
+            mode_num += 1;
+        }
+
+        // Sorting
+        resolutions.sort_by(|a, b| {
+            b.width
+                .cmp(&a.width)
+                .then(b.height.cmp(&a.height))
+                .then(b.refresh.cmp(&a.refresh))
+                .then(a.interlaced.cmp(&b.interlaced))
+        });
+
+        resolutions
+    }
+}
+
+thread_local! {
+    // Where the hidden window of a change watching thread reports to
+    static CHANGES: RefCell<Option<Sender<()>>> = const { RefCell::new(None) };
+}
+
+unsafe extern "system" fn on_window_message(
+    hwnd: HWND,
+    message: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    if message == WM_DISPLAYCHANGE {
+        let delivered = CHANGES.with_borrow(|changes| {
+            changes
+                .as_ref()
+                .is_some_and(|sender| sender.send(()).is_ok())
+        });
+        if !delivered {
+            PostQuitMessage(0);
+        }
+    }
+    DefWindowProcW(hwnd, message, wparam, lparam)
+}
+
+/// Reports WM_DISPLAYCHANGE until the receiver goes away. Windows only
+/// broadcasts it to top level windows, so this runs a hidden one.
+fn watch_display_changes(changes: Sender<()>) -> Result<()> {
+    unsafe {
+        let instance: HINSTANCE = GetModuleHandleW(PCWSTR::null())?.into();
+        let class_name = w!("YarmDisplayChanges");
+        let class = WNDCLASSW {
+            lpfnWndProc: Some(on_window_message),
+            hInstance: instance,
+            lpszClassName: class_name,
+            ..Default::default()
+        };
+        // Fails harmlessly if an earlier watcher registered it already
+        RegisterClassW(&class);
+        let hwnd = CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            class_name,
+            class_name,
+            WINDOW_STYLE::default(),
+            0,
+            0,
+            0,
+            0,
+            HWND::default(),
+            HMENU::default(),
+            instance,
+            None,
+        )?;
+        CHANGES.set(Some(changes));
+        let mut message = MSG::default();
+        while GetMessageW(&mut message, HWND::default(), 0, 0).as_bool() {
+            DispatchMessageW(&message);
+        }
+        CHANGES.set(None);
+        let _ = DestroyWindow(hwnd);
+    }
+    Ok(())
+}
+
+impl DisplayBackend for Win32Backend {
+    fn name(&self) -> &'static str {
+        "win32"
+    }
+
+    fn enumerate_monitors(&self) -> Result<Vec<Monitor>> {
+        let mut monitors = Vec::new();
+        let mut dev_num = 0;
+
+        // Pre-fetch friendly names mapping
+        let names_map = Self::get_display_names_map();
+
+        loop {
+            let mut display_device = DISPLAY_DEVICEW {
+                cb: mem::size_of::<DISPLAY_DEVICEW>() as u32,
+                ..Default::default()
+            };
+
+            let result = unsafe { EnumDisplayDevicesW(None, dev_num, &mut display_device, 0) };
+
+            if !result.as_bool() {
+                break;
+            }
+
+            // Attached to the desktop, or detached with a monitor plugged in
+            let attached = (display_device.StateFlags & DISPLAY_DEVICE_ATTACHED_TO_DESKTOP) != 0;
+            let connected = attached
+                || Self::monitor_device(PCWSTR::from_raw(display_device.DeviceName.as_ptr()))
+                    .is_some();
+            if connected {
+                let device_name_os = display_device.DeviceName;
+                let device_name_str = String::from_utf16_lossy(&device_name_os)
+                    .trim_matches(char::from(0))
+                    .to_string();
+
+                let is_primary = (display_device.StateFlags
+                    & windows::Win32::Graphics::Gdi::DISPLAY_DEVICE_PRIMARY_DEVICE)
+                    != 0;
+
+                // Try to get friendly name from QueryDisplayConfig map first
+                let target = names_map.get(&device_name_str);
+                let mut friendly_name = target.map(|t| t.friendly_name.clone()).unwrap_or_default();
+                let mut identity = target.map(|t| t.identity.clone()).unwrap_or_default();
+                let edid = target.and_then(|t| t.edid.clone());
+
+                // Fallback to EnumDisplayDevices logic if empty
+                if friendly_name.is_empty() {
+                    friendly_name = match Self::monitor_device(PCWSTR::from_raw(
+                        display_device.DeviceName.as_ptr(),
+                    )) {
+                        Some(monitor_device) => {
+                            String::from_utf16_lossy(&monitor_device.DeviceString)
+                                .trim_matches(char::from(0))
+                                .to_string()
+                        }
+                        None => format!("Display {}", dev_num + 1),
+                    };
+                }
+
+                // If empty, default to Display X
+                if friendly_name.is_empty() {
+                    friendly_name = format!("Display {}", dev_num + 1);
+                }
+
+                // Get current settings, or the last used ones if detached
+                let which = if attached {
+                    ENUM_CURRENT_SETTINGS
+                } else {
+                    ENUM_REGISTRY_SETTINGS
+                };
+                let dev_mode = Self::display_settings(
+                    PCWSTR::from_raw(display_device.DeviceName.as_ptr()),
+                    which,
+                )
+                .unwrap_or(DEVMODEW {
+                    dmSize: mem::size_of::<DEVMODEW>() as u16,
+                    ..Default::default()
+                });
+
+                // Get available resolutions
+                let resolutions =
+                    Self::enum_modes(PCWSTR::from_raw(display_device.DeviceName.as_ptr()));
+
+                let current_res = match dev_mode.dmPelsWidth {
+                    // Never attached, it would come up in its best mode
+                    0 if !attached => match resolutions.first() {
+                        Some(best) => best.clone(),
+                        None => {
+                            dev_num += 1;
+                            continue;
+                        }
+                    },
+                    _ => Self::resolution_of(&dev_mode),
+                };
+
+                let current_orientation = unsafe {
+                    Orientation::from_u32(dev_mode.Anonymous1.Anonymous2.dmDisplayOrientation.0)
+                };
+
+                let position = unsafe {
+                    (
+                        dev_mode.Anonymous1.Anonymous2.dmPosition.x,
+                        dev_mode.Anonymous1.Anonymous2.dmPosition.y,
+                    )
+                };
+
+                identity.name = friendly_name.clone();
+
+                monitors.push(Monitor {
+                    id: device_name_str.clone(),
+                    name: friendly_name,
+                    device_name: device_name_str,
+                    current_resolution: current_res,
+                    current_orientation,
+                    position,
+                    is_primary,
+                    enabled: attached,
+                    scale: 1.0,
+                    identity,
+                    edid,
+                    available_resolutions: resolutions,
+                });
+            }
+
+            dev_num += 1;
+        }
+
+        Ok(monitors)
+    }
+
+    fn query_modes(&self, device_name: &str) -> Result<Vec<Resolution>> {
+        let device_name_w: Vec<u16> = device_name.encode_utf16().chain(Some(0)).collect();
+        Ok(Self::enum_modes(PCWSTR::from_raw(device_name_w.as_ptr())))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            atomic_apply: true,
+            scale: false,
+            primary: true,
+            change_events: true,
+            ..Default::default()
+        }
+    }
+
+    fn watch_changes(&self) -> Result<Receiver<()>> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            if let Err(e) = watch_display_changes(sender) {
+                warn!("Stopped listening for display changes: {}", e);
+            }
+        });
+        Ok(receiver)
+    }
+
+    fn apply(&self, changes: &[MonitorChange]) -> Result<()> {
+        // GDI cannot put two sources on the same spot, mirroring switches the
+        // whole desktop to the clone topology instead. The topology is
+        // switched first, the other changes apply to the devices it leaves.
+        let clone = match Self::plans_clone(&self.enumerate_monitors()?, changes) {
+            Some(true) => true,
+            Some(false) => return Err(anyhow!(MIRROR_ALL)),
+            None if Self::is_cloned() => false,
+            None => return self.apply_settings(changes),
+        };
+        let (topology, previous) = if clone {
+            (SDC_TOPOLOGY_CLONE, SDC_TOPOLOGY_EXTEND)
+        } else {
+            (SDC_TOPOLOGY_EXTEND, SDC_TOPOLOGY_CLONE)
+        };
+        Self::set_topology(topology, SDC_VALIDATE)?;
+        Self::set_topology(topology, SDC_APPLY)?;
+
+        let result = if clone {
+            self.cloned_changes(changes)
+                .and_then(|changes| self.apply_settings(&changes))
+        } else {
+            self.apply_settings(changes)
+        };
+        if result.is_err() {
+            if let Err(e) = Self::set_topology(previous, SDC_APPLY) {
+                warn!("Cannot switch back to the previous topology: {}", e);
+            }
+        }
+        result
+    }
+
+    fn validate(&self, changes: &[MonitorChange]) -> Result<Vec<Verdict>> {
+        let clone = match Self::plans_clone(&self.enumerate_monitors()?, changes) {
+            Some(true) => Some(Self::set_topology(SDC_TOPOLOGY_CLONE, SDC_VALIDATE)),
+            Some(false) => Some(Err(anyhow!(MIRROR_ALL))),
+            None => None,
+        };
+        if let Some(result) = clone {
+            return Ok(changes
+                .iter()
+                .map(|change| match &result {
+                    Ok(()) => Verdict::accepted(&change.device_name),
+                    Err(e) => Verdict::rejected(&change.device_name, e),
+                })
+                .collect());
+        }
+
+        Ok(changes
+            .iter()
+            .map(|change| match Self::test_change(change) {
+                Ok(_) => Verdict::accepted(&change.device_name),
+                Err(e) => Verdict::rejected(&change.device_name, e),
+            })
+            .collect())
+    }
+
+    fn set_resolution(&self, device_name: &str, resolution: &Resolution) -> Result<()> {
+        let mut dev_mode = DEVMODEW {
+            dmSize: mem::size_of::<DEVMODEW>() as u16,
+            ..Default::default()
+        };
+
+        let device_name_w: Vec<u16> = device_name.encode_utf16().chain(Some(0)).collect();
+        let device_name_pcwstr = PCWSTR::from_raw(device_name_w.as_ptr());
+
+        // Get current settings first to fill in other fields
+        unsafe {
+            let _ = EnumDisplaySettingsW(device_name_pcwstr, ENUM_CURRENT_SETTINGS, &mut dev_mode);
+        }
+
+        dev_mode.dmFields = Self::set_mode(&mut dev_mode, resolution)?;
+
+        let result = unsafe {
+            ChangeDisplaySettingsExW(
+                device_name_pcwstr,
+                Some(&dev_mode),
+                HWND(std::ptr::null_mut()),
+                CDS_UPDATEREGISTRY | CDS_GLOBAL,
+                None,
+            )
+        };
+
+        if result == DISP_CHANGE_SUCCESSFUL {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Failed to change display settings. Error code: {:?}",
+                result
+            ))
+        }
+    }
+
+    fn set_orientation(&self, device_name: &str, orientation: Orientation) -> Result<()> {
+        let mut dev_mode = DEVMODEW {
+            dmSize: mem::size_of::<DEVMODEW>() as u16,
+            ..Default::default()
+        };
+
+        let device_name_w: Vec<u16> = device_name.encode_utf16().chain(Some(0)).collect();
+        let device_name_pcwstr = PCWSTR::from_raw(device_name_w.as_ptr());
+
+        // Get current settings first
+        unsafe {
+            let _ = EnumDisplaySettingsW(device_name_pcwstr, ENUM_CURRENT_SETTINGS, &mut dev_mode);
+        }
+
+        let old_orientation =
+            unsafe { Orientation::from_u32(dev_mode.Anonymous1.Anonymous2.dmDisplayOrientation.0) };
+
+        // Update orientation
+        dev_mode.Anonymous1.Anonymous2.dmDisplayOrientation =
+            DEVMODE_DISPLAY_ORIENTATION(orientation.to_u32());
+        dev_mode.dmFields = DM_DISPLAYORIENTATION;
+
+        // Swap width/height if orientation changes between landscape/portrait types
+        let is_old_portrait = old_orientation == Orientation::Portrait
+            || old_orientation == Orientation::PortraitFlipped;
+        let is_new_portrait =
+            orientation == Orientation::Portrait || orientation == Orientation::PortraitFlipped;
+
+        if is_old_portrait != is_new_portrait {
+            let w = dev_mode.dmPelsWidth;
+            let h = dev_mode.dmPelsHeight;
+            dev_mode.dmPelsWidth = h;
+            dev_mode.dmPelsHeight = w;
+            dev_mode.dmFields |= DM_PELSWIDTH | DM_PELSHEIGHT;
+        }
+
+        let result = unsafe {
+            ChangeDisplaySettingsExW(
+                device_name_pcwstr,
+                Some(&dev_mode),
+                HWND(std::ptr::null_mut()),
+                CDS_UPDATEREGISTRY | CDS_GLOBAL,
+                None,
+            )
+        };
+
+        if result == DISP_CHANGE_SUCCESSFUL {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Failed to change display orientation. Error code: {:?}",
+                result
+            ))
+        }
+    }
+
+    fn set_position(&self, device_name: &str, (x, y): (i32, i32)) -> Result<()> {
+        let device_name_w: Vec<u16> = device_name.encode_utf16().chain(Some(0)).collect();
+        let device_name_pcwstr = PCWSTR::from_raw(device_name_w.as_ptr());
+
+        let mut dev_mode = Self::display_settings(device_name_pcwstr, ENUM_CURRENT_SETTINGS)
+            .ok_or_else(|| anyhow!("Monitor '{}' not found", device_name))?;
+        dev_mode.Anonymous1.Anonymous2.dmPosition = POINTL { x, y };
+        dev_mode.dmFields = DM_POSITION;
+
+        let result = Self::change_settings(
+            device_name_pcwstr,
+            Some(&dev_mode),
+            CDS_UPDATEREGISTRY | CDS_GLOBAL,
+        );
+        if result == DISP_CHANGE_SUCCESSFUL {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Failed to change display position. Error code: {:?}",
+                result
+            ))
+        }
+    }
+
+    fn set_primary(&self, device_name: &str) -> Result<()> {
+        let mut change = MonitorChange::new(device_name);
+        change.primary = true;
+        self.apply(&[change])
+    }
+
+    fn set_enabled(&self, device_name: &str, enabled: bool) -> Result<()> {
+        let mut change = MonitorChange::new(device_name);
+        change.enabled = Some(enabled);
+        self.apply(&[change])
+    }
+}
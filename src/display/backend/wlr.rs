@@ -0,0 +1,666 @@
+//! Wayland backend speaking `zwlr_output_manager_v1`.
+//!
+//! Supported by wlroots based compositors (sway, Hyprland, river, ...). Every
+//! operation opens its own connection, waits for the manager's `done` event to
+//! get a consistent snapshot of all heads, then submits a full configuration.
+//! Configurations are always validated with `test()` before `apply()`.
+
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use std::fs;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{wl_output::Transform, wl_registry};
+use wayland_client::{event_created_child, Connection, Dispatch, EventQueue, QueueHandle, WEnum};
+use wayland_protocols_wlr::output_management::v1::client::{
+    zwlr_output_configuration_head_v1::ZwlrOutputConfigurationHeadV1,
+    zwlr_output_configuration_v1::{self, ZwlrOutputConfigurationV1},
+    zwlr_output_head_v1::{self, ZwlrOutputHeadV1},
+    zwlr_output_manager_v1::{self, ZwlrOutputManagerV1},
+    zwlr_output_mode_v1::{self, ZwlrOutputModeV1},
+};
+
+use super::{check_scale, Capabilities, DisplayBackend, Verdict};
+use crate::display::edid::Edid;
+use crate::display::identity::MonitorIdentity;
+use crate::display::transaction::MonitorChange;
+use crate::display::{Monitor, Orientation, RefreshRate, Resolution, ScalingMode};
+
+// wlr-output-management has no notion of colour depth, see the X11 backend.
+const BITS_PER_PIXEL: u32 = 32;
+
+pub struct WlrBackend;
+
+struct Head {
+    proxy: ZwlrOutputHeadV1,
+    name: String,
+    description: String,
+    make: String,
+    model: String,
+    serial_number: String,
+    enabled: bool,
+    modes: Vec<ZwlrOutputModeV1>,
+    current_mode: Option<ZwlrOutputModeV1>,
+    position: (i32, i32),
+    transform: Transform,
+    scale: f64,
+}
+
+struct Mode {
+    proxy: ZwlrOutputModeV1,
+    width: i32,
+    height: i32,
+    // mHz, zero if unknown
+    refresh: i32,
+    preferred: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Default)]
+struct State {
+    heads: Vec<Head>,
+    modes: Vec<Mode>,
+    serial: Option<u32>,
+    outcome: Option<Outcome>,
+}
+
+/// Desired state of one head in a configuration we are about to submit.
+struct HeadConfig {
+    head: ZwlrOutputHeadV1,
+    enabled: bool,
+    mode: Option<ZwlrOutputModeV1>,
+    position: (i32, i32),
+    transform: Transform,
+    scale: f64,
+}
+
+/// A consistent view of the compositor's outputs on a private connection.
+struct Session {
+    queue: EventQueue<State>,
+    state: State,
+    manager: ZwlrOutputManagerV1,
+}
+
+impl WlrBackend {
+    pub fn connect() -> Result<Self> {
+        // Fail early if the compositor does not speak the protocol
+        Self::session()?;
+        Ok(Self)
+    }
+
+    fn session() -> Result<Session> {
+        let conn = Connection::connect_to_env().context("Failed to connect to Wayland")?;
+        let (globals, mut queue) = registry_queue_init::<State>(&conn)?;
+        let manager: ZwlrOutputManagerV1 = globals
+            .bind(&queue.handle(), 1..=4, ())
+            .context("Compositor does not support wlr-output-management")?;
+
+        let mut state = State::default();
+        while state.serial.is_none() {
+            queue.blocking_dispatch(&mut state)?;
+        }
+
+        Ok(Session {
+            queue,
+            state,
+            manager,
+        })
+    }
+}
+
+impl Session {
+    fn mode(&self, proxy: &ZwlrOutputModeV1) -> Option<&Mode> {
+        self.state.modes.iter().find(|m| &m.proxy == proxy)
+    }
+
+    fn head(&self, name: &str) -> Result<&Head> {
+        self.state
+            .heads
+            .iter()
+            .find(|h| h.name == name)
+            .ok_or_else(|| anyhow!("Output '{}' not found", name))
+    }
+
+    /// The mode a head is using, or the one it would be enabled with.
+    fn active_mode(&self, head: &Head) -> Option<&Mode> {
+        let modes = || head.modes.iter().filter_map(|m| self.mode(m));
+        head.current_mode
+            .as_ref()
+            .and_then(|m| self.mode(m))
+            .or_else(|| modes().find(|m| m.preferred))
+            .or_else(|| modes().next())
+    }
+
+    fn current_config(&self) -> Vec<HeadConfig> {
+        self.state
+            .heads
+            .iter()
+            .map(|h| HeadConfig {
+                head: h.proxy.clone(),
+                enabled: h.enabled,
+                mode: h.current_mode.clone(),
+                position: h.position,
+                transform: h.transform,
+                scale: h.scale,
+            })
+            .collect()
+    }
+
+    /// Updates the head configurations with one requested change.
+    fn stage(&self, configs: &mut [HeadConfig], change: &MonitorChange) -> Result<()> {
+        let head = self.head(&change.device_name)?;
+        if change.primary {
+            return Err(no_primary(&change.device_name));
+        }
+        let enabled = change.enabled.unwrap_or(head.enabled);
+        if !enabled && change.configures() {
+            return Err(anyhow!(
+                "Cannot change {}, it would be disabled",
+                change.device_name
+            ));
+        }
+        if let Some(scale) = change.scale {
+            check_scale(true, &change.device_name, scale)?;
+        }
+        let transform = match change.orientation {
+            Some(orientation) => transform_from_orientation(head.transform, orientation),
+            None => head.transform,
+        };
+
+        // Prefer the current mode when several round to the same refresh rate
+        let mode = match &change.resolution {
+            Some(resolution) => Some(
+                head.modes
+                    .iter()
+                    .filter_map(|m| self.mode(m))
+                    .filter(|m| to_resolution(m, transform) == *resolution)
+                    .max_by_key(|m| head.current_mode.as_ref() == Some(&m.proxy))
+                    .map(|m| m.proxy.clone())
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Mode {} not supported by {}",
+                            resolution,
+                            change.device_name
+                        )
+                    })?,
+            ),
+            // A head being enabled needs a mode, it has none while disabled
+            None if enabled && !head.enabled => self.active_mode(head).map(|m| m.proxy.clone()),
+            None => None,
+        };
+
+        for config in configs.iter_mut().filter(|c| c.head == head.proxy) {
+            config.enabled = enabled;
+            config.transform = transform;
+            if let Some(position) = change.position {
+                config.position = position;
+            }
+            if let Some(scale) = change.scale {
+                config.scale = scale;
+            }
+            if let Some(mode) = &mode {
+                config.mode = Some(mode.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Tests the configuration and applies it if the compositor accepts it.
+    fn commit(&mut self, configs: &[HeadConfig]) -> Result<()> {
+        self.submit(configs, true)?;
+        self.submit(configs, false)
+    }
+
+    fn submit(&mut self, configs: &[HeadConfig], test_only: bool) -> Result<()> {
+        let serial = self
+            .state
+            .serial
+            .ok_or_else(|| anyhow!("No output configuration serial"))?;
+        let qh = self.queue.handle();
+        let configuration = self.manager.create_configuration(serial, &qh, ());
+
+        for config in configs {
+            if !config.enabled {
+                configuration.disable_head(&config.head);
+                continue;
+            }
+            let head = configuration.enable_head(&config.head, &qh, ());
+            if let Some(mode) = &config.mode {
+                head.set_mode(mode);
+            }
+            head.set_position(config.position.0, config.position.1);
+            head.set_transform(config.transform);
+            head.set_scale(config.scale);
+        }
+
+        self.state.outcome = None;
+        if test_only {
+            configuration.test();
+        } else {
+            configuration.apply();
+        }
+        let outcome = loop {
+            if let Some(outcome) = self.state.outcome.take() {
+                break outcome;
+            }
+            self.queue.blocking_dispatch(&mut self.state)?;
+        };
+        configuration.destroy();
+
+        let action = if test_only { "test" } else { "apply" };
+        match outcome {
+            Outcome::Succeeded => Ok(()),
+            Outcome::Failed => Err(anyhow!(
+                "Compositor rejected the output configuration ({})",
+                action
+            )),
+            Outcome::Cancelled => Err(anyhow!(
+                "Output configuration changed while trying to {} it, try again",
+                action
+            )),
+        }
+    }
+}
+
+/// Reports every `done` of the output manager but the first, which only
+/// ends the initial description of the heads.
+fn watch_heads(changes: Sender<()>) -> Result<()> {
+    let mut session = WlrBackend::session()?;
+    loop {
+        let serial = session.state.serial;
+        session.queue.blocking_dispatch(&mut session.state)?;
+        if session.state.serial != serial && changes.send(()).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// The EDID of a connector from the kernel, as the protocol does not expose
+/// it. wlroots names heads after their DRM connector, which sysfs lists as
+/// `card0-DP-1`.
+fn sysfs_edid(connector: &str) -> Option<Edid> {
+    let entries = fs::read_dir("/sys/class/drm").ok()?;
+    let path = entries.flatten().map(|e| e.path()).find(|path| {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.split_once('-'))
+            .is_some_and(|(card, port)| card.starts_with("card") && port == connector)
+    })?;
+    Edid::parse(&fs::read(path.join("edid")).ok()?).ok()
+}
+
+/// wlr-output-management has no notion of a primary output, compositors
+/// decide where new windows go on their own.
+fn no_primary(device_name: &str) -> anyhow::Error {
+    anyhow!(
+        "Cannot make {} primary, the compositor has no primary output",
+        device_name
+    )
+}
+
+fn orientation_from_transform(transform: Transform) -> Orientation {
+    match transform {
+        Transform::_90 | Transform::Flipped90 => Orientation::Portrait,
+        Transform::_180 | Transform::Flipped180 => Orientation::LandscapeFlipped,
+        Transform::_270 | Transform::Flipped270 => Orientation::PortraitFlipped,
+        _ => Orientation::Landscape,
+    }
+}
+
+/// Replaces the rotation of `current` while keeping a flip, if any.
+fn transform_from_orientation(current: Transform, orientation: Orientation) -> Transform {
+    let flipped = matches!(
+        current,
+        Transform::Flipped | Transform::Flipped90 | Transform::Flipped180 | Transform::Flipped270
+    );
+    match (orientation, flipped) {
+        (Orientation::Landscape, false) => Transform::Normal,
+        (Orientation::Portrait, false) => Transform::_90,
+        (Orientation::LandscapeFlipped, false) => Transform::_180,
+        (Orientation::PortraitFlipped, false) => Transform::_270,
+        (Orientation::Landscape, true) => Transform::Flipped,
+        (Orientation::Portrait, true) => Transform::Flipped90,
+        (Orientation::LandscapeFlipped, true) => Transform::Flipped180,
+        (Orientation::PortraitFlipped, true) => Transform::Flipped270,
+    }
+}
+
+fn to_resolution(mode: &Mode, transform: Transform) -> Resolution {
+    let (width, height) = match orientation_from_transform(transform) {
+        Orientation::Portrait | Orientation::PortraitFlipped => (mode.height, mode.width),
+        _ => (mode.width, mode.height),
+    };
+    Resolution {
+        width: width as u32,
+        height: height as u32,
+        refresh: RefreshRate::from_millihertz(mode.refresh.max(0) as u32),
+        bits_per_pixel: BITS_PER_PIXEL,
+        // The protocol exposes neither
+        interlaced: false,
+        scaling: ScalingMode::Default,
+    }
+}
+
+impl DisplayBackend for WlrBackend {
+    fn name(&self) -> &'static str {
+        "wlr"
+    }
+
+    fn enumerate_monitors(&self) -> Result<Vec<Monitor>> {
+        let session = Self::session()?;
+
+        let mut monitors = Vec::new();
+        for head in &session.state.heads {
+            let Some(current) = session.active_mode(head) else {
+                continue;
+            };
+
+            let mut resolutions = Vec::new();
+            for mode in head.modes.iter().filter_map(|m| session.mode(m)) {
+                let r = to_resolution(mode, head.transform);
+                if !resolutions.contains(&r) {
+                    resolutions.push(r);
+                }
+            }
+            resolutions.sort_by(|a, b| {
+                b.width
+                    .cmp(&a.width)
+                    .then(b.height.cmp(&a.height))
+                    .then(b.refresh.cmp(&a.refresh))
+            });
+
+            let name = if head.description.is_empty() {
+                head.name.clone()
+            } else {
+                head.description.clone()
+            };
+
+            // The EDID gives the same identity as on X11 and Windows. Without
+            // it, make/model/serial are free text, only sent from version 2
+            // on and left out by compositors that could not read the EDID
+            let edid = sysfs_edid(&head.name);
+            let identity = if edid.is_some() {
+                MonitorIdentity::from_edid(edid.as_ref(), Some(head.name.clone()), &name)
+            } else {
+                let non_empty = |s: &String| (!s.is_empty()).then(|| s.clone());
+                MonitorIdentity {
+                    vendor: non_empty(&head.make),
+                    product: non_empty(&head.model),
+                    serial: non_empty(&head.serial_number),
+                    connector: Some(head.name.clone()),
+                    name: name.clone(),
+                }
+            };
+
+            monitors.push(Monitor {
+                id: head.name.clone(),
+                name,
+                device_name: head.name.clone(),
+                current_resolution: to_resolution(current, head.transform),
+                current_orientation: orientation_from_transform(head.transform),
+                position: head.position,
+                // The protocol has no primary output
+                is_primary: false,
+                enabled: head.enabled,
+                scale: head.scale,
+                identity,
+                edid,
+                available_resolutions: resolutions,
+            });
+        }
+
+        Ok(monitors)
+    }
+
+    fn set_resolution(&self, device_name: &str, resolution: &Resolution) -> Result<()> {
+        let mut change = MonitorChange::new(device_name);
+        change.resolution = Some(resolution.clone());
+        self.apply(&[change])
+    }
+
+    fn set_orientation(&self, device_name: &str, orientation: Orientation) -> Result<()> {
+        let mut change = MonitorChange::new(device_name);
+        change.orientation = Some(orientation);
+        self.apply(&[change])
+    }
+
+    fn set_position(&self, device_name: &str, position: (i32, i32)) -> Result<()> {
+        let mut change = MonitorChange::new(device_name);
+        change.position = Some(position);
+        self.apply(&[change])
+    }
+
+    fn set_primary(&self, device_name: &str) -> Result<()> {
+        Err(no_primary(device_name))
+    }
+
+    fn set_enabled(&self, device_name: &str, enabled: bool) -> Result<()> {
+        let mut change = MonitorChange::new(device_name);
+        change.enabled = Some(enabled);
+        self.apply(&[change])
+    }
+
+    fn set_scale(&self, device_name: &str, scale: f64) -> Result<()> {
+        let mut change = MonitorChange::new(device_name);
+        change.scale = Some(scale);
+        self.apply(&[change])
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            atomic_apply: true,
+            scale: true,
+            change_events: true,
+            ..Default::default()
+        }
+    }
+
+    fn watch_changes(&self) -> Result<Receiver<()>> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            if let Err(e) = watch_heads(sender) {
+                warn!("Stopped listening for output changes: {}", e);
+            }
+        });
+        Ok(receiver)
+    }
+
+    fn apply(&self, changes: &[MonitorChange]) -> Result<()> {
+        let mut session = Self::session()?;
+        let mut configs = session.current_config();
+        for change in changes {
+            session.stage(&mut configs, change)?;
+        }
+        session.commit(&configs)
+    }
+
+    fn validate(&self, changes: &[MonitorChange]) -> Result<Vec<Verdict>> {
+        let mut session = Self::session()?;
+        let mut configs = session.current_config();
+        let mut verdicts: Vec<Verdict> = changes
+            .iter()
+            .map(|change| match session.stage(&mut configs, change) {
+                Ok(()) => Verdict::accepted(&change.device_name),
+                Err(e) => Verdict::rejected(&change.device_name, e),
+            })
+            .collect();
+
+        // The compositor only judges the configuration as a whole
+        if verdicts.iter().all(Verdict::is_accepted) {
+            if let Err(e) = session.submit(&configs, true) {
+                for verdict in &mut verdicts {
+                    verdict.rejection = Some(e.to_string());
+                }
+            }
+        }
+        Ok(verdicts)
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrOutputManagerV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrOutputManagerV1,
+        event: zwlr_output_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_output_manager_v1::Event::Head { head } => state.heads.push(Head {
+                proxy: head,
+                name: String::new(),
+                description: String::new(),
+                make: String::new(),
+                model: String::new(),
+                serial_number: String::new(),
+                enabled: false,
+                modes: Vec::new(),
+                current_mode: None,
+                position: (0, 0),
+                transform: Transform::Normal,
+                scale: 1.0,
+            }),
+            zwlr_output_manager_v1::Event::Done { serial } => state.serial = Some(serial),
+            _ => {}
+        }
+    }
+
+    event_created_child!(State, ZwlrOutputManagerV1, [
+        zwlr_output_manager_v1::EVT_HEAD_OPCODE => (ZwlrOutputHeadV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrOutputHeadV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrOutputHeadV1,
+        event: zwlr_output_head_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let zwlr_output_head_v1::Event::Finished = event {
+            state.heads.retain(|h| &h.proxy != proxy);
+            return;
+        }
+        if let zwlr_output_head_v1::Event::Mode { mode } = &event {
+            state.modes.push(Mode {
+                proxy: mode.clone(),
+                width: 0,
+                height: 0,
+                refresh: 0,
+                preferred: false,
+            });
+        }
+
+        let Some(head) = state.heads.iter_mut().find(|h| &h.proxy == proxy) else {
+            return;
+        };
+        match event {
+            zwlr_output_head_v1::Event::Name { name } => head.name = name,
+            zwlr_output_head_v1::Event::Description { description } => {
+                head.description = description
+            }
+            zwlr_output_head_v1::Event::Make { make } => head.make = make,
+            zwlr_output_head_v1::Event::Model { model } => head.model = model,
+            zwlr_output_head_v1::Event::SerialNumber { serial_number } => {
+                head.serial_number = serial_number
+            }
+            zwlr_output_head_v1::Event::Mode { mode } => head.modes.push(mode),
+            zwlr_output_head_v1::Event::Enabled { enabled } => head.enabled = enabled != 0,
+            zwlr_output_head_v1::Event::CurrentMode { mode } => head.current_mode = Some(mode),
+            zwlr_output_head_v1::Event::Position { x, y } => head.position = (x, y),
+            zwlr_output_head_v1::Event::Transform {
+                transform: WEnum::Value(transform),
+            } => head.transform = transform,
+            zwlr_output_head_v1::Event::Scale { scale } => head.scale = scale,
+            _ => {}
+        }
+    }
+
+    event_created_child!(State, ZwlrOutputHeadV1, [
+        zwlr_output_head_v1::EVT_MODE_OPCODE => (ZwlrOutputModeV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrOutputModeV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrOutputModeV1,
+        event: zwlr_output_mode_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let zwlr_output_mode_v1::Event::Finished = event {
+            state.modes.retain(|m| &m.proxy != proxy);
+            for head in &mut state.heads {
+                head.modes.retain(|m| m != proxy);
+            }
+            return;
+        }
+
+        let Some(mode) = state.modes.iter_mut().find(|m| &m.proxy == proxy) else {
+            return;
+        };
+        match event {
+            zwlr_output_mode_v1::Event::Size { width, height } => {
+                mode.width = width;
+                mode.height = height;
+            }
+            zwlr_output_mode_v1::Event::Refresh { refresh } => mode.refresh = refresh,
+            zwlr_output_mode_v1::Event::Preferred => mode.preferred = true,
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZwlrOutputConfigurationV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrOutputConfigurationV1,
+        event: zwlr_output_configuration_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        state.outcome = match event {
+            zwlr_output_configuration_v1::Event::Succeeded => Some(Outcome::Succeeded),
+            zwlr_output_configuration_v1::Event::Failed => Some(Outcome::Failed),
+            zwlr_output_configuration_v1::Event::Cancelled => Some(Outcome::Cancelled),
+            _ => None,
+        };
+    }
+}
+
+impl Dispatch<ZwlrOutputConfigurationHeadV1, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &ZwlrOutputConfigurationHeadV1,
+        _: <ZwlrOutputConfigurationHeadV1 as wayland_client::Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
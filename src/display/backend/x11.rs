@@ -0,0 +1,929 @@
+//! X11 backend built on the RandR extension.
+//!
+//! Every connected output becomes a `Monitor`, addressed by its output name
+//! (e.g. `DP-1`). Outputs without a CRTC are reported as disabled, with the
+//! mode, rotation, position and scale they had before, which X forgets but
+//! yarm keeps in an output property. Like the Win32 backend, sizes are
+//! reported in the current orientation, so a portrait 1440p panel shows up
+//! as 1440x2560.
+//!
+//! Scaling uses CRTC transforms the way `xrandr --scale` does, a scale of 2
+//! samples a framebuffer area half the mode's size in each direction. How
+//! modes are fitted onto the panel goes through the "scaling mode" output
+//! property where the kernel driver has one, every mode is then listed once
+//! per scaling mode.
+
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use x11rb::connection::Connection as _;
+use x11rb::protocol::randr::{
+    self, ConnectionExt as _, Crtc, GetCrtcInfoReply, GetScreenResourcesCurrentReply, ModeFlag,
+    ModeInfo, NotifyMask, Output, Rotation, SetConfig,
+};
+use x11rb::protocol::render::{Fixed, Transform};
+use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt as _, PropMode, Window};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+use super::{check_scale, Capabilities, DisplayBackend};
+use crate::display::edid::Edid;
+use crate::display::identity::MonitorIdentity;
+use crate::display::{Monitor, Orientation, RefreshRate, Resolution, ScalingMode};
+
+// RandR has no notion of colour depth per output, report what a 24-bit X
+// screen scans out so profiles stay portable with Windows.
+const BITS_PER_PIXEL: u32 = 32;
+
+// Used to keep the physical screen size consistent when resizing it.
+const DEFAULT_DPI: f64 = 96.0;
+
+// 16.16 fixed point one, as used by RENDER transforms
+const FIXED_ONE: f64 = 65536.0;
+
+// Output property holding the CRTC configuration of a disabled output, as
+// mode, x, y, rotation and 16.16 scale
+const LAST_CONFIG: &[u8] = b"_YARM_LAST_CONFIG";
+
+pub struct X11Backend {
+    conn: RustConnection,
+    root: Window,
+    edid_atom: Atom,
+    scaling_atom: Atom,
+    last_config_atom: Atom,
+}
+
+/// An active output together with the CRTC currently driving it.
+struct ActiveOutput {
+    output: Output,
+    name: String,
+    crtc: Crtc,
+    crtc_info: GetCrtcInfoReply,
+    modes: Vec<randr::Mode>,
+}
+
+/// How a disabled output is driven once enabled again.
+struct CrtcConfig<'a> {
+    mode: &'a ModeInfo,
+    rotation: Rotation,
+    position: (i16, i16),
+    scale: f64,
+}
+
+/// A connected output no CRTC is driving, e.g. after `xrandr --off`.
+struct InactiveOutput {
+    output: Output,
+    name: String,
+    crtcs: Vec<Crtc>,
+    // Preferred modes come first
+    modes: Vec<randr::Mode>,
+}
+
+impl X11Backend {
+    pub fn connect() -> Result<Self> {
+        let (conn, screen_num) =
+            RustConnection::connect(None).context("Failed to connect to the X server")?;
+        let root = conn.setup().roots[screen_num].root;
+
+        let version = conn.randr_query_version(1, 5)?.reply()?;
+        if (version.major_version, version.minor_version) < (1, 3) {
+            return Err(anyhow!(
+                "RandR 1.3 or newer is required (server has {}.{})",
+                version.major_version,
+                version.minor_version
+            ));
+        }
+
+        let edid_atom = conn.intern_atom(false, b"EDID")?.reply()?.atom;
+        let scaling_atom = conn.intern_atom(false, b"scaling mode")?.reply()?.atom;
+        let last_config_atom = conn.intern_atom(false, LAST_CONFIG)?.reply()?.atom;
+
+        Ok(Self {
+            conn,
+            root,
+            edid_atom,
+            scaling_atom,
+            last_config_atom,
+        })
+    }
+
+    fn resources(&self) -> Result<GetScreenResourcesCurrentReply> {
+        Ok(self
+            .conn
+            .randr_get_screen_resources_current(self.root)?
+            .reply()?)
+    }
+
+    fn active_outputs(&self, res: &GetScreenResourcesCurrentReply) -> Result<Vec<ActiveOutput>> {
+        let mut outputs = Vec::new();
+        for &output in &res.outputs {
+            let info = self
+                .conn
+                .randr_get_output_info(output, res.config_timestamp)?
+                .reply()?;
+            if info.connection != randr::Connection::CONNECTED || info.crtc == 0 {
+                continue;
+            }
+            let crtc_info = self
+                .conn
+                .randr_get_crtc_info(info.crtc, res.config_timestamp)?
+                .reply()?;
+            outputs.push(ActiveOutput {
+                output,
+                name: String::from_utf8_lossy(&info.name).into_owned(),
+                crtc: info.crtc,
+                crtc_info,
+                modes: info.modes,
+            });
+        }
+        Ok(outputs)
+    }
+
+    fn inactive_outputs(
+        &self,
+        res: &GetScreenResourcesCurrentReply,
+    ) -> Result<Vec<InactiveOutput>> {
+        let mut outputs = Vec::new();
+        for &output in &res.outputs {
+            let info = self
+                .conn
+                .randr_get_output_info(output, res.config_timestamp)?
+                .reply()?;
+            if info.connection != randr::Connection::CONNECTED || info.crtc != 0 {
+                continue;
+            }
+            outputs.push(InactiveOutput {
+                output,
+                name: String::from_utf8_lossy(&info.name).into_owned(),
+                crtcs: info.crtcs,
+                modes: info.modes,
+            });
+        }
+        Ok(outputs)
+    }
+
+    /// Display name, identity and EDID of a connected output.
+    fn describe(
+        &self,
+        output: Output,
+        output_name: &str,
+    ) -> Result<(String, MonitorIdentity, Option<Edid>)> {
+        let edid = Edid::parse(&self.edid(output)?).ok();
+        let name = edid
+            .as_ref()
+            .and_then(|e| e.model_name.clone())
+            .unwrap_or_else(|| output_name.to_string());
+        let identity =
+            MonitorIdentity::from_edid(edid.as_ref(), Some(output_name.to_string()), &name);
+        Ok((name, identity, edid))
+    }
+
+    /// Raw EDID of an output, empty if the driver does not expose it.
+    fn edid(&self, output: Output) -> Result<Vec<u8>> {
+        // 256 longs cover the base block and up to seven extensions
+        let reply = self
+            .conn
+            .randr_get_output_property(output, self.edid_atom, AtomEnum::ANY, 0, 256, false, false)?
+            .reply()?;
+        Ok(reply.data)
+    }
+
+    /// Keeps the configuration of an output about to be disabled on the
+    /// output itself, so any later yarm process can restore it.
+    fn remember(&self, target: &ActiveOutput) -> Result<()> {
+        let c = &target.crtc_info;
+        let scale = (self.scale(target.crtc)? * FIXED_ONE).round() as u32;
+        let values = [
+            c.mode,
+            c.x as i32 as u32,
+            c.y as i32 as u32,
+            u16::from(c.rotation) as u32,
+            scale,
+        ];
+        let data: Vec<u8> = values.iter().flat_map(|v| v.to_ne_bytes()).collect();
+        self.conn
+            .randr_change_output_property(
+                target.output,
+                self.last_config_atom,
+                AtomEnum::INTEGER.into(),
+                32,
+                PropMode::REPLACE,
+                values.len() as u32,
+                &data,
+            )?
+            .check()?;
+        Ok(())
+    }
+
+    /// The configuration a disabled output comes back with: the one it had
+    /// before if its mode is still offered, else its preferred mode right
+    /// of the desktop. `None` if the output has no modes at all.
+    fn restore_config<'a>(
+        &self,
+        res: &'a GetScreenResourcesCurrentReply,
+        output: &InactiveOutput,
+        active: &[ActiveOutput],
+    ) -> Result<Option<CrtcConfig<'a>>> {
+        let reply = self
+            .conn
+            .randr_get_output_property(
+                output.output,
+                self.last_config_atom,
+                AtomEnum::INTEGER,
+                0,
+                5,
+                false,
+                false,
+            )?
+            .reply()?;
+        let values: Vec<u32> = reply
+            .data
+            .chunks_exact(4)
+            .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        if let &[mode, x, y, rotation, scale] = values.as_slice() {
+            let mode = output
+                .modes
+                .contains(&mode)
+                .then(|| lookup_mode(res, mode))
+                .flatten();
+            if let Some(mode) = mode.filter(|_| reply.format == 32 && scale > 0) {
+                return Ok(Some(CrtcConfig {
+                    mode,
+                    rotation: Rotation::from(rotation as u16),
+                    position: (x as i32 as i16, y as i32 as i16),
+                    scale: scale as f64 / FIXED_ONE,
+                }));
+            }
+        }
+        Ok(output
+            .modes
+            .first()
+            .and_then(|&id| lookup_mode(res, id))
+            .map(|mode| CrtcConfig {
+                mode,
+                rotation: Rotation::ROTATE0,
+                position: (desktop_right_edge(active), 0),
+                scale: 1.0,
+            }))
+    }
+
+    /// The scaling modes an output's scaler offers with the atoms naming
+    /// them, empty if the driver has no "scaling mode" property.
+    fn scaling_modes(&self, output: Output) -> Result<Vec<(ScalingMode, Atom)>> {
+        // The property is simply missing on drivers without a scaler
+        let Ok(reply) = self
+            .conn
+            .randr_query_output_property(output, self.scaling_atom)?
+            .reply()
+        else {
+            return Ok(Vec::new());
+        };
+        let mut modes = Vec::new();
+        for value in reply.valid_values {
+            let name = self.conn.get_atom_name(value as Atom)?.reply()?.name;
+            if let Some(mode) = scaling_from_name(&name) {
+                modes.push((mode, value as Atom));
+            }
+        }
+        Ok(modes)
+    }
+
+    fn current_scaling(&self, output: Output) -> Result<ScalingMode> {
+        let reply = self
+            .conn
+            .randr_get_output_property(
+                output,
+                self.scaling_atom,
+                AtomEnum::ATOM,
+                0,
+                1,
+                false,
+                false,
+            )?
+            .reply()?;
+        let atom = match reply.data.get(..4) {
+            Some(&[a, b, c, d]) if reply.format == 32 => u32::from_ne_bytes([a, b, c, d]),
+            _ => return Ok(ScalingMode::Default),
+        };
+        let name = self.conn.get_atom_name(atom)?.reply()?.name;
+        Ok(scaling_from_name(&name).unwrap_or_default())
+    }
+
+    /// Switches the scaler of an output, effective from the next modeset.
+    fn set_scaling(&self, output: Output, name: &str, scaling: ScalingMode) -> Result<()> {
+        let atom = self
+            .scaling_modes(output)?
+            .into_iter()
+            .find(|&(mode, _)| mode == scaling)
+            .map(|(_, atom)| atom);
+        let Some(atom) = atom else {
+            return match scaling {
+                ScalingMode::Default => Ok(()),
+                _ => Err(anyhow!("{} does not support {} scaling", name, scaling)),
+            };
+        };
+        self.conn
+            .randr_change_output_property(
+                output,
+                self.scaling_atom,
+                AtomEnum::ATOM.into(),
+                32,
+                PropMode::REPLACE,
+                1,
+                &atom.to_ne_bytes(),
+            )?
+            .check()
+            .with_context(|| format!("Failed to change the scaling of {}", name))?;
+        Ok(())
+    }
+
+    fn find_output(
+        &self,
+        res: &GetScreenResourcesCurrentReply,
+        device_name: &str,
+    ) -> Result<(Vec<ActiveOutput>, usize)> {
+        let outputs = self.active_outputs(res)?;
+        let index = outputs
+            .iter()
+            .position(|o| o.name == device_name)
+            .ok_or_else(|| anyhow!("Output '{}' not found or not active", device_name))?;
+        Ok((outputs, index))
+    }
+
+    /// The scale a CRTC's current transform applies, 1.0 if it has none.
+    fn scale(&self, crtc: Crtc) -> Result<f64> {
+        let transform = self
+            .conn
+            .randr_get_crtc_transform(crtc)?
+            .reply()?
+            .current_transform;
+        Ok(scale_from_transform(&transform))
+    }
+
+    /// Reprograms one CRTC, growing the X screen first if the new geometry does
+    /// not fit and shrinking it afterwards if it became larger than needed.
+    ///
+    /// `scale` is set as a pending transform, which RandR only picks up with
+    /// the CRTC configuration that follows it.
+    #[allow(clippy::too_many_arguments)]
+    fn configure(
+        &self,
+        res: &GetScreenResourcesCurrentReply,
+        outputs: &[ActiveOutput],
+        index: usize,
+        mode: &ModeInfo,
+        rotation: Rotation,
+        (x, y): (i16, i16),
+        scale: f64,
+    ) -> Result<()> {
+        let target = &outputs[index];
+        let (width, height) = rotated_size(mode, rotation);
+        let (width, height) = (
+            (width as f64 / scale).round() as u32,
+            (height as f64 / scale).round() as u32,
+        );
+
+        // Bounding box of the desktop after the change
+        let (mut max_x, mut max_y) = (0u32, 0u32);
+        for (i, o) in outputs.iter().enumerate() {
+            let (ox, oy, w, h) = if i == index {
+                (x, y, width, height)
+            } else {
+                let c = &o.crtc_info;
+                (c.x, c.y, c.width as u32, c.height as u32)
+            };
+            max_x = max_x.max((ox.max(0) as u32) + w);
+            max_y = max_y.max((oy.max(0) as u32) + h);
+        }
+
+        let geometry = self.conn.get_geometry(self.root)?.reply()?;
+        let (screen_w, screen_h) = (geometry.width as u32, geometry.height as u32);
+
+        if max_x > screen_w || max_y > screen_h {
+            self.set_screen_size(max_x.max(screen_w), max_y.max(screen_h))?;
+        }
+
+        // Smooth filtering unless every pixel maps onto whole pixels
+        let filter: &[u8] = if scale.fract() == 0.0 || (1.0 / scale).fract() == 0.0 {
+            b"nearest"
+        } else {
+            b"bilinear"
+        };
+        self.conn
+            .randr_set_crtc_transform(target.crtc, transform_from_scale(scale), filter, &[])?
+            .check()
+            .with_context(|| format!("Failed to scale {}", target.name))?;
+
+        let reply = self
+            .conn
+            .randr_set_crtc_config(
+                target.crtc,
+                x11rb::CURRENT_TIME,
+                res.config_timestamp,
+                x,
+                y,
+                mode.id,
+                rotation,
+                &[target.output],
+            )?
+            .reply()?;
+        if reply.status != SetConfig::SUCCESS {
+            return Err(anyhow!(
+                "Failed to configure {}: RandR status {:?}",
+                target.name,
+                reply.status
+            ));
+        }
+
+        if max_x < screen_w || max_y < screen_h {
+            self.set_screen_size(max_x, max_y)?;
+        }
+
+        Ok(())
+    }
+
+    /// Turns off the CRTC driving an output and shrinks the X screen to the
+    /// outputs that are left.
+    fn disable(
+        &self,
+        res: &GetScreenResourcesCurrentReply,
+        outputs: &[ActiveOutput],
+        index: usize,
+    ) -> Result<()> {
+        let target = &outputs[index];
+        if let Err(e) = self.remember(target) {
+            warn!(
+                "Cannot remember the configuration of {}: {}",
+                target.name, e
+            );
+        }
+        let reply = self
+            .conn
+            .randr_set_crtc_config(
+                target.crtc,
+                x11rb::CURRENT_TIME,
+                res.config_timestamp,
+                0,
+                0,
+                0,
+                Rotation::ROTATE0,
+                &[],
+            )?
+            .reply()?;
+        if reply.status != SetConfig::SUCCESS {
+            return Err(anyhow!(
+                "Failed to disable {}: RandR status {:?}",
+                target.name,
+                reply.status
+            ));
+        }
+
+        let (mut max_x, mut max_y) = (0u32, 0u32);
+        for (_, o) in outputs.iter().enumerate().filter(|(i, _)| *i != index) {
+            let c = &o.crtc_info;
+            max_x = max_x.max(c.x.max(0) as u32 + c.width as u32);
+            max_y = max_y.max(c.y.max(0) as u32 + c.height as u32);
+        }
+        if max_x > 0 && max_y > 0 {
+            self.set_screen_size(max_x, max_y)?;
+        }
+        Ok(())
+    }
+
+    fn set_screen_size(&self, width: u32, height: u32) -> Result<()> {
+        let range = self.conn.randr_get_screen_size_range(self.root)?.reply()?;
+        let width = width.clamp(range.min_width as u32, range.max_width as u32);
+        let height = height.clamp(range.min_height as u32, range.max_height as u32);
+        let mm = |px: u32| (px as f64 * 25.4 / DEFAULT_DPI).round() as u32;
+
+        self.conn
+            .randr_set_screen_size(
+                self.root,
+                width as u16,
+                height as u16,
+                mm(width),
+                mm(height),
+            )?
+            .check()
+            .context("Failed to resize the X screen")?;
+        Ok(())
+    }
+}
+
+/// Reports RandR screen, output and CRTC changes until the receiver goes
+/// away, on a connection of its own so it can block on events.
+fn watch_randr(changes: Sender<()>) -> Result<()> {
+    let (conn, screen_num) = RustConnection::connect(None)?;
+    let root = conn.setup().roots[screen_num].root;
+    conn.randr_query_version(1, 5)?.reply()?;
+    conn.randr_select_input(
+        root,
+        NotifyMask::SCREEN_CHANGE | NotifyMask::OUTPUT_CHANGE | NotifyMask::CRTC_CHANGE,
+    )?;
+    conn.flush()?;
+    loop {
+        let event = conn.wait_for_event()?;
+        let relevant = matches!(
+            event,
+            Event::RandrScreenChangeNotify(_) | Event::RandrNotify(_)
+        );
+        if relevant && changes.send(()).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+fn orientation_from_rotation(rotation: Rotation) -> Orientation {
+    if rotation.contains(Rotation::ROTATE90) {
+        Orientation::Portrait
+    } else if rotation.contains(Rotation::ROTATE180) {
+        Orientation::LandscapeFlipped
+    } else if rotation.contains(Rotation::ROTATE270) {
+        Orientation::PortraitFlipped
+    } else {
+        Orientation::Landscape
+    }
+}
+
+fn rotation_from_orientation(orientation: Orientation) -> Rotation {
+    match orientation {
+        Orientation::Landscape => Rotation::ROTATE0,
+        Orientation::Portrait => Rotation::ROTATE90,
+        Orientation::LandscapeFlipped => Rotation::ROTATE180,
+        Orientation::PortraitFlipped => Rotation::ROTATE270,
+    }
+}
+
+/// Keeps any reflection bits of the current rotation while replacing the angle.
+fn replace_angle(current: Rotation, orientation: Orientation) -> Rotation {
+    let reflect = current & (Rotation::REFLECT_X | Rotation::REFLECT_Y);
+    rotation_from_orientation(orientation) | reflect
+}
+
+fn is_portrait(rotation: Rotation) -> bool {
+    matches!(
+        orientation_from_rotation(rotation),
+        Orientation::Portrait | Orientation::PortraitFlipped
+    )
+}
+
+fn rotated_size(mode: &ModeInfo, rotation: Rotation) -> (u32, u32) {
+    if is_portrait(rotation) {
+        (mode.height as u32, mode.width as u32)
+    } else {
+        (mode.width as u32, mode.height as u32)
+    }
+}
+
+/// Vertical refresh derived from the pixel clock, as xrandr does.
+fn refresh_rate(mode: &ModeInfo) -> RefreshRate {
+    let mut total = mode.htotal as u64 * mode.vtotal as u64;
+    if mode.mode_flags.contains(ModeFlag::DOUBLE_SCAN) {
+        total *= 2;
+    }
+    if mode.mode_flags.contains(ModeFlag::INTERLACE) {
+        total /= 2;
+    }
+    RefreshRate::from_timing(mode.dot_clock as u64, total)
+}
+
+fn to_resolution(mode: &ModeInfo, rotation: Rotation, scaling: ScalingMode) -> Resolution {
+    let (width, height) = rotated_size(mode, rotation);
+    Resolution {
+        width,
+        height,
+        refresh: refresh_rate(mode),
+        bits_per_pixel: BITS_PER_PIXEL,
+        interlaced: mode.mode_flags.contains(ModeFlag::INTERLACE),
+        scaling,
+    }
+}
+
+/// Values of the "scaling mode" property as the kernel names them.
+fn scaling_from_name(name: &[u8]) -> Option<ScalingMode> {
+    match name {
+        b"None" => Some(ScalingMode::Default),
+        b"Full" => Some(ScalingMode::Stretch),
+        b"Center" => Some(ScalingMode::Center),
+        b"Full aspect" => Some(ScalingMode::Aspect),
+        _ => None,
+    }
+}
+
+/// The supported resolutions of an output, largest and fastest first, once
+/// per scaling mode in `scalings`.
+fn resolutions(
+    res: &GetScreenResourcesCurrentReply,
+    modes: &[randr::Mode],
+    rotation: Rotation,
+    scalings: &[ScalingMode],
+) -> Vec<Resolution> {
+    let scalings = if scalings.is_empty() {
+        &[ScalingMode::Default][..]
+    } else {
+        scalings
+    };
+    let mut resolutions = Vec::new();
+    for mode in modes.iter().filter_map(|&id| lookup_mode(res, id)) {
+        for &scaling in scalings {
+            let r = to_resolution(mode, rotation, scaling);
+            if !resolutions.contains(&r) {
+                resolutions.push(r);
+            }
+        }
+    }
+    resolutions.sort_by(|a, b| {
+        b.width
+            .cmp(&a.width)
+            .then(b.height.cmp(&a.height))
+            .then(b.refresh.cmp(&a.refresh))
+            .then(a.interlaced.cmp(&b.interlaced))
+    });
+    resolutions
+}
+
+/// Where an output enabled without a position goes, right of everything.
+fn desktop_right_edge(outputs: &[ActiveOutput]) -> i16 {
+    outputs
+        .iter()
+        .map(|o| o.crtc_info.x.saturating_add_unsigned(o.crtc_info.width))
+        .max()
+        .unwrap_or(0)
+}
+
+fn scale_from_transform(transform: &Transform) -> f64 {
+    if transform.matrix11 == 0 {
+        return 1.0;
+    }
+    transform.matrix33 as f64 / transform.matrix11 as f64
+}
+
+fn transform_from_scale(scale: f64) -> Transform {
+    let diagonal = (FIXED_ONE / scale).round() as Fixed;
+    Transform {
+        matrix11: diagonal,
+        matrix12: 0,
+        matrix13: 0,
+        matrix21: 0,
+        matrix22: diagonal,
+        matrix23: 0,
+        matrix31: 0,
+        matrix32: 0,
+        matrix33: FIXED_ONE as Fixed,
+    }
+}
+
+fn lookup_mode(res: &GetScreenResourcesCurrentReply, id: randr::Mode) -> Option<&ModeInfo> {
+    res.modes.iter().find(|m| m.id == id)
+}
+
+impl DisplayBackend for X11Backend {
+    fn name(&self) -> &'static str {
+        "x11"
+    }
+
+    fn enumerate_monitors(&self) -> Result<Vec<Monitor>> {
+        let res = self.resources()?;
+        let primary = self
+            .conn
+            .randr_get_output_primary(self.root)?
+            .reply()?
+            .output;
+
+        let active = self.active_outputs(&res)?;
+        let mut monitors = Vec::new();
+        for output in &active {
+            let rotation = output.crtc_info.rotation;
+            let current_resolution = match lookup_mode(&res, output.crtc_info.mode) {
+                Some(mode) => to_resolution(mode, rotation, self.current_scaling(output.output)?),
+                None => continue,
+            };
+            let (name, identity, edid) = self.describe(output.output, &output.name)?;
+            let scalings: Vec<_> = self
+                .scaling_modes(output.output)?
+                .into_iter()
+                .map(|(mode, _)| mode)
+                .collect();
+
+            monitors.push(Monitor {
+                id: output.name.clone(),
+                name,
+                device_name: output.name.clone(),
+                current_resolution,
+                current_orientation: orientation_from_rotation(rotation),
+                position: (output.crtc_info.x as i32, output.crtc_info.y as i32),
+                is_primary: output.output == primary,
+                enabled: true,
+                scale: self.scale(output.crtc)?,
+                identity,
+                edid,
+                available_resolutions: resolutions(&res, &output.modes, rotation, &scalings),
+            });
+        }
+
+        // Reported as they would be enabled again
+        for output in self.inactive_outputs(&res)? {
+            let Some(config) = self.restore_config(&res, &output, &active)? else {
+                continue;
+            };
+            let (name, identity, edid) = self.describe(output.output, &output.name)?;
+
+            monitors.push(Monitor {
+                id: output.name.clone(),
+                name,
+                device_name: output.name.clone(),
+                current_resolution: to_resolution(
+                    config.mode,
+                    config.rotation,
+                    ScalingMode::Default,
+                ),
+                current_orientation: orientation_from_rotation(config.rotation),
+                position: (config.position.0 as i32, config.position.1 as i32),
+                is_primary: false,
+                enabled: false,
+                scale: config.scale,
+                identity,
+                edid,
+                available_resolutions: resolutions(&res, &output.modes, config.rotation, &[]),
+            });
+        }
+
+        Ok(monitors)
+    }
+
+    fn set_resolution(&self, device_name: &str, resolution: &Resolution) -> Result<()> {
+        let res = self.resources()?;
+        let (outputs, index) = self.find_output(&res, device_name)?;
+        let target = &outputs[index];
+        let rotation = target.crtc_info.rotation;
+
+        // Prefer the mode currently in use when several share the same timing
+        let mode = target
+            .modes
+            .iter()
+            .filter_map(|&id| lookup_mode(&res, id))
+            .filter(|m| to_resolution(m, rotation, resolution.scaling) == *resolution)
+            .max_by_key(|m| m.id == target.crtc_info.mode)
+            .ok_or_else(|| anyhow!("Mode {} not supported by {}", resolution, device_name))?;
+        if resolution.scaling != self.current_scaling(target.output)? {
+            self.set_scaling(target.output, device_name, resolution.scaling)?;
+        }
+
+        let position = (target.crtc_info.x, target.crtc_info.y);
+        let scale = self.scale(target.crtc)?;
+        self.configure(&res, &outputs, index, mode, rotation, position, scale)
+    }
+
+    fn set_orientation(&self, device_name: &str, orientation: Orientation) -> Result<()> {
+        let res = self.resources()?;
+        let (outputs, index) = self.find_output(&res, device_name)?;
+        let target = &outputs[index];
+
+        let rotation = replace_angle(target.crtc_info.rotation, orientation);
+        if !target
+            .crtc_info
+            .rotations
+            .contains(rotation_from_orientation(orientation))
+        {
+            return Err(anyhow!(
+                "{} does not support {} rotation",
+                device_name,
+                orientation
+            ));
+        }
+
+        let mode = lookup_mode(&res, target.crtc_info.mode)
+            .ok_or_else(|| anyhow!("Current mode of {} is unknown", device_name))?;
+
+        let position = (target.crtc_info.x, target.crtc_info.y);
+        let scale = self.scale(target.crtc)?;
+        self.configure(&res, &outputs, index, mode, rotation, position, scale)
+    }
+
+    fn set_position(&self, device_name: &str, (x, y): (i32, i32)) -> Result<()> {
+        // The X screen starts at the origin, there is nothing left or above it
+        let position = match (i16::try_from(x), i16::try_from(y)) {
+            (Ok(x), Ok(y)) if x >= 0 && y >= 0 => (x, y),
+            _ => return Err(anyhow!("Position ({}, {}) is outside the X screen", x, y)),
+        };
+
+        let res = self.resources()?;
+        let (outputs, index) = self.find_output(&res, device_name)?;
+        let target = &outputs[index];
+        let mode = lookup_mode(&res, target.crtc_info.mode)
+            .ok_or_else(|| anyhow!("Current mode of {} is unknown", device_name))?;
+
+        self.configure(
+            &res,
+            &outputs,
+            index,
+            mode,
+            target.crtc_info.rotation,
+            position,
+            self.scale(target.crtc)?,
+        )
+    }
+
+    fn set_scale(&self, device_name: &str, scale: f64) -> Result<()> {
+        check_scale(true, device_name, scale)?;
+        let res = self.resources()?;
+        let (outputs, index) = self.find_output(&res, device_name)?;
+        let target = &outputs[index];
+        let mode = lookup_mode(&res, target.crtc_info.mode)
+            .ok_or_else(|| anyhow!("Current mode of {} is unknown", device_name))?;
+
+        let position = (target.crtc_info.x, target.crtc_info.y);
+        let rotation = target.crtc_info.rotation;
+        self.configure(&res, &outputs, index, mode, rotation, position, scale)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            scale: true,
+            change_events: true,
+            primary: true,
+            ..Default::default()
+        }
+    }
+
+    fn watch_changes(&self) -> Result<Receiver<()>> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            if let Err(e) = watch_randr(sender) {
+                warn!("Stopped listening for RandR events: {}", e);
+            }
+        });
+        Ok(receiver)
+    }
+
+    fn set_primary(&self, device_name: &str) -> Result<()> {
+        // The X screen always starts at the top left monitor, so unlike
+        // Windows the layout stays where it is
+        let res = self.resources()?;
+        let (outputs, index) = self.find_output(&res, device_name)?;
+        self.conn
+            .randr_set_output_primary(self.root, outputs[index].output)?
+            .check()
+            .with_context(|| format!("Failed to make {} primary", device_name))?;
+        Ok(())
+    }
+
+    fn set_enabled(&self, device_name: &str, enabled: bool) -> Result<()> {
+        let res = self.resources()?;
+        let mut outputs = self.active_outputs(&res)?;
+        if let Some(index) = outputs.iter().position(|o| o.name == device_name) {
+            return match enabled {
+                true => Ok(()),
+                false => self.disable(&res, &outputs, index),
+            };
+        }
+
+        let output = self
+            .inactive_outputs(&res)?
+            .into_iter()
+            .find(|o| o.name == device_name)
+            .ok_or_else(|| anyhow!("Output '{}' not found or not connected", device_name))?;
+        if !enabled {
+            return Ok(());
+        }
+
+        // Any CRTC able to drive the output that is not driving another one
+        let mut free = None;
+        for &crtc in &output.crtcs {
+            let info = self
+                .conn
+                .randr_get_crtc_info(crtc, res.config_timestamp)?
+                .reply()?;
+            if info.outputs.is_empty() {
+                free = Some((crtc, info));
+                break;
+            }
+        }
+        let (crtc, crtc_info) =
+            free.ok_or_else(|| anyhow!("No free CRTC left to drive {}", device_name))?;
+        let config = self
+            .restore_config(&res, &output, &outputs)?
+            .ok_or_else(|| anyhow!("{} has no modes", device_name))?;
+
+        outputs.push(ActiveOutput {
+            output: output.output,
+            name: output.name,
+            crtc,
+            crtc_info,
+            modes: output.modes,
+        });
+        let index = outputs.len() - 1;
+        // The transform is always set, a CRTC keeps its last one while idle
+        self.configure(
+            &res,
+            &outputs,
+            index,
+            config.mode,
+            config.rotation,
+            config.position,
+            config.scale,
+        )
+    }
+}
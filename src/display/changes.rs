@@ -0,0 +1,62 @@
+//! Noticing monitors being plugged, unplugged or reconfigured: from the
+//! backend's events where it has them, by polling otherwise.
+
+use log::warn;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::manager::DisplayManager;
+
+pub struct Changes {
+    events: Option<Receiver<()>>,
+    poll_interval: Option<Duration>,
+    next_poll: Instant,
+}
+
+impl Changes {
+    /// Listens to the backend, or polls every `poll_interval` if it has no
+    /// events or they stop. `None` never polls.
+    pub fn watch(display: &DisplayManager, poll_interval: Option<Duration>) -> Self {
+        let events = if display.capabilities().change_events {
+            display
+                .watch_changes()
+                .map_err(|e| warn!("Polling for display changes: {}", e))
+                .ok()
+        } else {
+            None
+        };
+        Self {
+            events,
+            poll_interval,
+            next_poll: Instant::now() + poll_interval.unwrap_or_default(),
+        }
+    }
+
+    /// Waits up to `timeout` for a change. When polling, `true` only means
+    /// it is time to look again, the monitors may well be the same.
+    pub fn wait(&mut self, timeout: Duration) -> bool {
+        if let Some(events) = &self.events {
+            match events.recv_timeout(timeout) {
+                Ok(()) => return true,
+                Err(RecvTimeoutError::Timeout) => return false,
+                Err(RecvTimeoutError::Disconnected) => {
+                    warn!("Display change events stopped, polling instead");
+                    self.events = None;
+                    self.next_poll = Instant::now();
+                }
+            }
+        }
+        let now = Instant::now();
+        let Some(interval) = self.poll_interval else {
+            thread::sleep(timeout);
+            return false;
+        };
+        if now >= self.next_poll {
+            self.next_poll = now + interval;
+            return true;
+        }
+        thread::sleep(timeout.min(self.next_poll - now));
+        false
+    }
+}
@@ -0,0 +1,599 @@
+//! Decoder for EDID blobs as read from the monitor.
+//!
+//! Covers the 128 byte base block plus the CTA-861 and DisplayID extension
+//! blocks, which is where modern monitors keep their native timing, HDR and
+//! variable refresh capabilities. Parsing is lenient: unknown or truncated
+//! data blocks are skipped rather than failing the whole EDID, since plenty
+//! of monitors in the wild ship slightly broken ones. Blocks failing their
+//! checksum are not trusted though: a corrupt base block fails, a corrupt
+//! extension is ignored.
+
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::identity::pnp_id;
+use super::resolution::RefreshRate;
+
+const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+const BLOCK_LEN: usize = 128;
+
+const EXT_CTA: u8 = 0x02;
+const EXT_DISPLAYID: u8 = 0x70;
+
+const DESC_SERIAL: u8 = 0xff;
+const DESC_RANGE_LIMITS: u8 = 0xfd;
+const DESC_NAME: u8 = 0xfc;
+
+const CTA_VENDOR_BLOCK: u8 = 3;
+const CTA_EXTENDED_BLOCK: u8 = 7;
+const CTA_EXT_HDR_STATIC: u8 = 6;
+const OUI_HDMI_FORUM: u32 = 0xc45dd8;
+
+const DISPLAYID_TYPE_I_TIMING: u8 = 0x03;
+const DISPLAYID_TYPE_VII_TIMING: u8 = 0x22;
+const DISPLAYID_ADAPTIVE_SYNC: u8 = 0x2b;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Edid {
+    /// Three letter PNP id, e.g. `DEL`
+    pub manufacturer: String,
+    pub product_code: u16,
+    /// Numeric serial from the header, `None` if left at zero
+    pub serial_number: Option<u32>,
+    /// Serial number descriptor, usually what is printed on the label
+    pub serial: Option<String>,
+    /// Model name descriptor
+    pub model_name: Option<String>,
+    pub version: (u8, u8),
+    /// Physical size of the image area in millimetres
+    pub size_mm: Option<(u32, u32)>,
+    /// The timing the monitor wants to be driven at, i.e. its native mode
+    pub preferred_timing: Option<DetailedTiming>,
+    /// Every detailed timing found in the base block and extensions
+    pub detailed_timings: Vec<DetailedTiming>,
+    pub hdr: Option<HdrStaticMetadata>,
+    pub vrr: Option<VrrRange>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DetailedTiming {
+    pub pixel_clock_khz: u32,
+    pub width: u32,
+    /// Full frame height, already doubled for interlaced timings
+    pub height: u32,
+    pub h_blank: u32,
+    pub v_blank: u32,
+    pub interlaced: bool,
+}
+
+/// CTA-861.3 HDR static metadata, luminance values in cd/m².
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HdrStaticMetadata {
+    pub sdr: bool,
+    pub hdr: bool,
+    /// SMPTE ST 2084 (PQ), what "HDR10" means in practice
+    pub pq: bool,
+    pub hlg: bool,
+    pub max_luminance: Option<f32>,
+    pub max_frame_average: Option<f32>,
+    pub min_luminance: Option<f32>,
+}
+
+/// Refresh range supported by adaptive sync, in Hz.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VrrRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl DetailedTiming {
+    /// Vertical refresh rate in millihertz.
+    pub fn refresh_mhz(&self) -> u32 {
+        let h_total = (self.width + self.h_blank) as u64;
+        let field_height = if self.interlaced {
+            self.height / 2
+        } else {
+            self.height
+        };
+        let v_total = (field_height + self.v_blank) as u64;
+        if h_total == 0 || v_total == 0 {
+            return 0;
+        }
+        (self.pixel_clock_khz as u64 * 1_000_000 / (h_total * v_total)) as u32
+    }
+}
+
+impl Edid {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < BLOCK_LEN {
+            return Err(anyhow!("EDID too short ({} bytes)", data.len()));
+        }
+        if data[..8] != HEADER {
+            return Err(anyhow!("Invalid EDID header"));
+        }
+        let base = &data[..BLOCK_LEN];
+        if !checksum_ok(base) {
+            return Err(anyhow!("EDID checksum mismatch"));
+        }
+
+        let manufacturer = pnp_id(u16::from_be_bytes([base[8], base[9]]))
+            .ok_or_else(|| anyhow!("Invalid EDID manufacturer id"))?;
+        let serial_number = u32::from_le_bytes([base[12], base[13], base[14], base[15]]);
+
+        let mut edid = Edid {
+            manufacturer,
+            product_code: u16::from_le_bytes([base[10], base[11]]),
+            serial_number: (serial_number != 0).then_some(serial_number),
+            serial: None,
+            model_name: None,
+            version: (base[18], base[19]),
+            // Only a coarse size in cm here, replaced by the DTD's mm size below
+            size_mm: (base[21] != 0 && base[22] != 0)
+                .then(|| (base[21] as u32 * 10, base[22] as u32 * 10)),
+            preferred_timing: None,
+            detailed_timings: Vec::new(),
+            hdr: None,
+            vrr: None,
+        };
+
+        for (i, descriptor) in base[54..126].chunks_exact(18).enumerate() {
+            if let Some(timing) = parse_dtd(descriptor) {
+                // The first DTD is the preferred timing since EDID 1.3
+                if i == 0 {
+                    edid.preferred_timing = Some(timing);
+                    if let Some(size) = dtd_size_mm(descriptor) {
+                        edid.size_mm = Some(size);
+                    }
+                }
+                edid.detailed_timings.push(timing);
+            } else {
+                edid.parse_display_descriptor(descriptor, base[24] & 0x01 != 0);
+            }
+        }
+
+        let extensions = base[126] as usize;
+        for (i, block) in data[BLOCK_LEN..]
+            .chunks_exact(BLOCK_LEN)
+            .take(extensions)
+            .enumerate()
+        {
+            if !checksum_ok(block) {
+                warn!(
+                    "Ignoring EDID extension {}, its checksum does not match",
+                    i + 1
+                );
+                continue;
+            }
+            match block[0] {
+                EXT_CTA => edid.parse_cta(block),
+                EXT_DISPLAYID => edid.parse_displayid(&block[1..]),
+                _ => {}
+            }
+        }
+
+        if edid.preferred_timing.is_none() {
+            edid.preferred_timing = edid.detailed_timings.first().copied();
+        }
+
+        Ok(edid)
+    }
+
+    /// The native mode as (width, height, refresh rate).
+    pub fn native_mode(&self) -> Option<(u32, u32, RefreshRate)> {
+        self.preferred_timing.map(|t| {
+            (
+                t.width,
+                t.height,
+                RefreshRate::from_millihertz(t.refresh_mhz()),
+            )
+        })
+    }
+
+    fn parse_display_descriptor(&mut self, descriptor: &[u8], continuous_frequency: bool) {
+        match descriptor[3] {
+            DESC_SERIAL => self.serial = descriptor_text(descriptor),
+            DESC_NAME => self.model_name = descriptor_text(descriptor),
+            // Only a VRR range if the monitor supports continuous frequencies,
+            // otherwise it just bounds the fixed modes it accepts
+            DESC_RANGE_LIMITS if continuous_frequency => {
+                // Offsets of 255 Hz for monitors beyond 255 Hz (EDID 1.4)
+                let flags = descriptor[4];
+                let min = descriptor[5] as u32 + if flags & 0x03 == 0x03 { 255 } else { 0 };
+                let max = descriptor[6] as u32 + if flags & 0x02 != 0 { 255 } else { 0 };
+                if min > 0 && max > min && self.vrr.is_none() {
+                    self.vrr = Some(VrrRange { min, max });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn parse_cta(&mut self, block: &[u8]) {
+        // Data blocks sit between the header and the first DTD, zero means
+        // neither are present
+        let dtd_start = (block[2] as usize).min(127);
+        if dtd_start < 4 {
+            return;
+        }
+
+        let mut offset = 4;
+        while offset < dtd_start {
+            let header = block[offset];
+            let len = (header & 0x1f) as usize;
+            let end = offset + 1 + len;
+            if end > dtd_start {
+                break;
+            }
+            self.parse_cta_data_block(header >> 5, &block[offset + 1..end]);
+            offset = end;
+        }
+
+        for descriptor in block[dtd_start..127].chunks_exact(18) {
+            match parse_dtd(descriptor) {
+                Some(timing) => self.detailed_timings.push(timing),
+                None => break,
+            }
+        }
+    }
+
+    fn parse_cta_data_block(&mut self, tag: u8, payload: &[u8]) {
+        match tag {
+            CTA_EXTENDED_BLOCK if payload.len() >= 3 && payload[0] == CTA_EXT_HDR_STATIC => {
+                self.hdr = Some(parse_hdr_static(&payload[1..]));
+            }
+            CTA_VENDOR_BLOCK if payload.len() >= 3 => {
+                let oui = u32::from_le_bytes([payload[0], payload[1], payload[2], 0]);
+                // HF-VSDB: VRRmin in bits 5:0, VRRmax bits 9:8 in 7:6 then 7:0
+                if oui == OUI_HDMI_FORUM && payload.len() >= 10 {
+                    let min = (payload[8] & 0x3f) as u32;
+                    let max = (((payload[8] & 0xc0) as u32) << 2) | payload[9] as u32;
+                    if min > 0 && max > min {
+                        self.vrr = Some(VrrRange { min, max });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// `section` starts at the DisplayID section header.
+    fn parse_displayid(&mut self, section: &[u8]) {
+        if section.len() < 4 {
+            return;
+        }
+        let end = (4 + section[1] as usize).min(section.len());
+        let mut offset = 4;
+        while offset + 3 <= end {
+            let tag = section[offset];
+            let len = section[offset + 2] as usize;
+            let payload_end = offset + 3 + len;
+            if tag == 0 || payload_end > end {
+                break;
+            }
+            let payload = &section[offset + 3..payload_end];
+            match tag {
+                DISPLAYID_TYPE_I_TIMING => self.parse_displayid_timings(payload, 10),
+                DISPLAYID_TYPE_VII_TIMING => self.parse_displayid_timings(payload, 1),
+                DISPLAYID_ADAPTIVE_SYNC => {
+                    // Descriptors of 6 bytes, max is stored minus one
+                    if let Some(d) = payload.chunks_exact(6).next() {
+                        let min = d[2] as u32;
+                        let max = (((d[4] & 0x03) as u32) << 8 | d[3] as u32) + 1;
+                        if min > 0 && max > min {
+                            self.vrr = Some(VrrRange { min, max });
+                        }
+                    }
+                }
+                _ => {}
+            }
+            offset = payload_end;
+        }
+    }
+
+    /// Type I and Type VII detailed timings share a layout, only the pixel
+    /// clock unit (in kHz) differs.
+    fn parse_displayid_timings(&mut self, payload: &[u8], clock_unit_khz: u32) {
+        for d in payload.chunks_exact(20) {
+            let clock = (u32::from_le_bytes([d[0], d[1], d[2], 0]) + 1) * clock_unit_khz;
+            let field = |i: usize| u16::from_le_bytes([d[i], d[i + 1]]) as u32 + 1;
+            let interlaced = d[3] & 0x10 != 0;
+            let timing = DetailedTiming {
+                pixel_clock_khz: clock,
+                width: field(4),
+                height: if interlaced { field(12) * 2 } else { field(12) },
+                h_blank: field(6),
+                v_blank: field(14),
+                interlaced,
+            };
+            if d[3] & 0x80 != 0 && self.preferred_timing.is_none() {
+                self.preferred_timing = Some(timing);
+            }
+            self.detailed_timings.push(timing);
+        }
+    }
+}
+
+/// Every block's bytes add up to zero modulo 256.
+fn checksum_ok(block: &[u8]) -> bool {
+    block.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// Decodes an 18 byte detailed timing descriptor, `None` for display
+/// descriptors (pixel clock of zero).
+fn parse_dtd(d: &[u8]) -> Option<DetailedTiming> {
+    let clock = u16::from_le_bytes([d[0], d[1]]) as u32;
+    if clock == 0 {
+        return None;
+    }
+    let width = d[2] as u32 | ((d[4] as u32 & 0xf0) << 4);
+    let h_blank = d[3] as u32 | ((d[4] as u32 & 0x0f) << 8);
+    let height = d[5] as u32 | ((d[7] as u32 & 0xf0) << 4);
+    let v_blank = d[6] as u32 | ((d[7] as u32 & 0x0f) << 8);
+    let interlaced = d[17] & 0x80 != 0;
+    Some(DetailedTiming {
+        pixel_clock_khz: clock * 10,
+        width,
+        height: if interlaced { height * 2 } else { height },
+        h_blank,
+        v_blank,
+        interlaced,
+    })
+}
+
+fn dtd_size_mm(d: &[u8]) -> Option<(u32, u32)> {
+    let width = d[12] as u32 | ((d[14] as u32 & 0xf0) << 4);
+    let height = d[13] as u32 | ((d[14] as u32 & 0x0f) << 8);
+    (width > 0 && height > 0).then_some((width, height))
+}
+
+/// Text of a display descriptor, terminated by a newline and space padded.
+fn descriptor_text(d: &[u8]) -> Option<String> {
+    let text: String = d[5..]
+        .iter()
+        .take_while(|&&b| b != b'\n')
+        .map(|&b| b as char)
+        .collect();
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+fn parse_hdr_static(d: &[u8]) -> HdrStaticMetadata {
+    let eotf = d[0];
+    // Code values as defined by CTA-861.3
+    let max = d.get(2).filter(|&&v| v != 0).map(|&v| luminance(v));
+    let max_frame_average = d.get(3).filter(|&&v| v != 0).map(|&v| luminance(v));
+    let min = match (max, d.get(4)) {
+        (Some(max), Some(&v)) => Some(max * (v as f32 / 255.0).powi(2) / 100.0),
+        _ => None,
+    };
+    HdrStaticMetadata {
+        sdr: eotf & 0x01 != 0,
+        hdr: eotf & 0x02 != 0,
+        pq: eotf & 0x04 != 0,
+        hlg: eotf & 0x08 != 0,
+        max_luminance: max,
+        max_frame_average,
+        min_luminance: min,
+    }
+}
+
+fn luminance(code: u8) -> f32 {
+    50.0 * 2f32.powf(code as f32 / 32.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 27" 1440p panel: base block plus a CTA-861 extension with HDR
+    /// static metadata, an HDMI Forum VSDB with a VRR range and a 1080p DTD.
+    const DELL_U2719D: [&str; 8] = [
+        "00ffffffffffff0010acb1a034120000011e0104b53c22783b00000000000000",
+        "00000000000001010101010101010101010101010101565e00a0a0a029503020",
+        "350055502100001e000000fd003090a0a03c010a202020202020000000fc0044",
+        "454c4c205532373139440a20000000ff004142433132330a2020202020200146",
+        "02031600e6060d016050206ad85dc401780000003090023a801871382d403020",
+        "350055502100001e000000000000000000000000000000000000000000000000",
+        "0000000000000000000000000000000000000000000000000000000000000000",
+        "000000000000000000000000000000000000000000000000000000000000002c",
+    ];
+
+    /// A DisplayID 2.0 extension with a preferred Type VII timing of
+    /// 3840x2160 at 144 Hz and an adaptive sync range of 48-165 Hz.
+    const DISPLAYID_4K: [&str; 4] = [
+        "702020030022001482231380ff0e4f0007001f006f083d00020005002b000600",
+        "0030a400000d0000000000000000000000000000000000000000000000000000",
+        "0000000000000000000000000000000000000000000000000000000000000000",
+        "0000000000000000000000000000000000000000000000000000000000000090",
+    ];
+
+    fn bytes(lines: &[&str]) -> Vec<u8> {
+        let hex = lines.concat();
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn dell() -> Vec<u8> {
+        bytes(&DELL_U2719D)
+    }
+
+    /// The Dell base block followed by the DisplayID extension.
+    fn displayid() -> Vec<u8> {
+        let mut data = dell();
+        data.truncate(BLOCK_LEN);
+        data.extend(bytes(&DISPLAYID_4K));
+        data
+    }
+
+    fn fix_checksums(data: &mut [u8]) {
+        for block in data.chunks_exact_mut(BLOCK_LEN) {
+            let sum = block[..127].iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+            block[127] = sum.wrapping_neg();
+        }
+    }
+
+    #[test]
+    fn parses_base_block() {
+        let edid = Edid::parse(&dell()).unwrap();
+        assert_eq!(edid.manufacturer, "DEL");
+        assert_eq!(edid.product_code, 0xa0b1);
+        assert_eq!(edid.serial_number, Some(0x1234));
+        assert_eq!(edid.serial.as_deref(), Some("ABC123"));
+        assert_eq!(edid.model_name.as_deref(), Some("DELL U2719D"));
+        assert_eq!(edid.version, (1, 4));
+        // From the DTD, finer than the header's centimetres
+        assert_eq!(edid.size_mm, Some((597, 336)));
+
+        let preferred = edid.preferred_timing.unwrap();
+        assert_eq!((preferred.width, preferred.height), (2560, 1440));
+        assert_eq!(preferred.pixel_clock_khz, 241_500);
+        assert!(!preferred.interlaced);
+        assert_eq!(preferred.refresh_mhz(), 59_950);
+        assert_eq!(
+            edid.native_mode(),
+            Some((2560, 1440, RefreshRate::from_millihertz(59_950)))
+        );
+    }
+
+    #[test]
+    fn parses_cta_extension() {
+        let edid = Edid::parse(&dell()).unwrap();
+        // The base block's DTD, then the CTA block's 1080p one
+        assert_eq!(edid.detailed_timings.len(), 2);
+        let cta = edid.detailed_timings[1];
+        assert_eq!((cta.width, cta.height), (1920, 1080));
+        assert_eq!(cta.refresh_mhz(), 60_000);
+
+        let hdr = edid.hdr.unwrap();
+        assert!(hdr.sdr && hdr.pq && hdr.hlg);
+        assert!(!hdr.hdr);
+        assert_eq!(hdr.max_luminance, Some(400.0));
+        assert!((hdr.max_frame_average.unwrap() - 282.84).abs() < 0.01);
+        assert!((hdr.min_luminance.unwrap() - 0.063).abs() < 0.001);
+
+        assert_eq!(edid.vrr, Some(VrrRange { min: 48, max: 144 }));
+    }
+
+    #[test]
+    fn parses_displayid_extension() {
+        let edid = Edid::parse(&displayid()).unwrap();
+        // The base block's preferred timing comes first
+        assert_eq!(edid.preferred_timing.unwrap().width, 2560);
+        let timing = edid.detailed_timings[1];
+        assert_eq!((timing.width, timing.height), (3840, 2160));
+        assert_eq!((timing.h_blank, timing.v_blank), (80, 62));
+        assert_eq!(timing.refresh_mhz(), 144_000);
+        assert_eq!(edid.vrr, Some(VrrRange { min: 48, max: 165 }));
+        assert_eq!(edid.hdr, None);
+    }
+
+    #[test]
+    fn displayid_preferred_timing_without_base_dtd() {
+        let mut data = displayid();
+        // Turn the base block's DTD into a dummy descriptor
+        data[54..72].copy_from_slice(&[0, 0, 0, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        fix_checksums(&mut data);
+        let edid = Edid::parse(&data).unwrap();
+        assert_eq!(edid.preferred_timing.unwrap().width, 3840);
+    }
+
+    #[test]
+    fn range_limits_need_continuous_frequency() {
+        let mut data = dell();
+        data.truncate(BLOCK_LEN);
+        data[24] &= !0x01;
+        data[126] = 0;
+        fix_checksums(&mut data);
+        assert_eq!(Edid::parse(&data).unwrap().vrr, None);
+    }
+
+    #[test]
+    fn rejects_corrupt_base_block() {
+        let mut data = dell();
+        data[20] ^= 0x01;
+        assert!(Edid::parse(&data).is_err());
+
+        let mut data = dell();
+        data[0] = 0x01;
+        fix_checksums(&mut data);
+        assert!(Edid::parse(&data).is_err());
+    }
+
+    #[test]
+    fn ignores_corrupt_extension() {
+        let mut data = dell();
+        data[BLOCK_LEN + 10] ^= 0xff;
+        let edid = Edid::parse(&data).unwrap();
+        assert_eq!(edid.hdr, None);
+        assert_eq!(edid.detailed_timings.len(), 1);
+        assert_eq!(edid.model_name.as_deref(), Some("DELL U2719D"));
+    }
+
+    #[test]
+    fn truncated_input_does_not_panic() {
+        for data in [dell(), displayid()] {
+            for len in 0..data.len() {
+                let result = Edid::parse(&data[..len]);
+                assert_eq!(result.is_ok(), len >= BLOCK_LEN, "{} bytes", len);
+            }
+        }
+    }
+
+    #[test]
+    fn garbage_does_not_panic() {
+        let mut seed: u64 = 42;
+        let mut next = || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize
+        };
+        for source in [dell(), displayid()] {
+            for round in 0..20_000 {
+                let mut data = source.clone();
+                for _ in 0..next() % 20 + 1 {
+                    let i = 8 + next() % (data.len() - 8);
+                    data[i] = next() as u8;
+                }
+                // Mostly valid checksums, so the mutations reach the parsers
+                if round % 4 != 0 {
+                    fix_checksums(&mut data);
+                }
+                if round % 7 == 0 {
+                    data.truncate(next() % data.len());
+                }
+                if let Ok(edid) = Edid::parse(&data) {
+                    let _ = edid.native_mode();
+                    for timing in &edid.detailed_timings {
+                        let _ = timing.refresh_mhz();
+                    }
+                }
+            }
+        }
+        // Pure noise after a valid header
+        for _ in 0..20_000 {
+            let mut data: Vec<u8> = (0..BLOCK_LEN * (1 + next() % 4))
+                .map(|_| next() as u8)
+                .collect();
+            data[..8].copy_from_slice(&HEADER);
+            fix_checksums(&mut data);
+            let _ = Edid::parse(&data);
+        }
+    }
+
+    #[test]
+    fn refresh_of_empty_timing_is_zero() {
+        let timing = DetailedTiming {
+            pixel_clock_khz: 100_000,
+            width: 0,
+            height: 0,
+            h_blank: 0,
+            v_blank: 0,
+            interlaced: true,
+        };
+        assert_eq!(timing.refresh_mhz(), 0);
+    }
+}
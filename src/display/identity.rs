@@ -0,0 +1,259 @@
+//! Recognising the same physical monitor across ports, docks and reboots.
+//!
+//! `Monitor.id` is whatever the backend calls the port (`\\.\DISPLAY1`,
+//! `DP-1`), which moves around when cables are swapped. Profiles therefore
+//! also store a `MonitorIdentity`, and `assign` resolves profile entries to
+//! the monitors connected today using the strongest evidence available:
+//! EDID vendor/product/serial, then the connector path, then the name.
+
+use serde::{Deserialize, Serialize};
+
+use super::edid::Edid;
+use super::monitor::Monitor;
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct MonitorIdentity {
+    /// EDID manufacturer (PNP id such as `DEL`) or the compositor's make
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<String>,
+    /// EDID product code or model name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    /// Port the monitor is plugged into (device path, output name)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connector: Option<String>,
+    #[serde(default)]
+    pub name: String,
+}
+
+/// How strongly two identities agree, higher is better.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Match {
+    /// Only the backend's legacy `Monitor.id` is the same
+    LegacyId,
+    Name,
+    Connector,
+    /// Same model, no serial to tell units apart
+    Model,
+    /// Same model and serial number
+    Serial,
+}
+
+impl MonitorIdentity {
+    /// Builds an identity from a decoded EDID, if the monitor provided one.
+    pub fn from_edid(edid: Option<&Edid>, connector: Option<String>, name: &str) -> Self {
+        let mut identity = Self {
+            connector,
+            name: name.to_string(),
+            ..Default::default()
+        };
+        if let Some(edid) = edid {
+            identity.vendor = Some(edid.manufacturer.clone());
+            identity.product = Some(format!("{:04X}", edid.product_code));
+            // The serial string descriptor is preferred, many panels leave the
+            // numeric serial at zero or set it to the same value for every unit
+            identity.serial = edid
+                .serial
+                .clone()
+                .or_else(|| edid.serial_number.map(|n| n.to_string()));
+        }
+        identity
+    }
+
+    fn has_model(&self) -> bool {
+        self.vendor.is_some() && self.product.is_some()
+    }
+
+    /// Compares a stored identity with the one of a connected monitor, `None`
+    /// if they are known to be different monitors.
+    pub fn matches(&self, other: &MonitorIdentity) -> Option<Match> {
+        if self.has_model() && other.has_model() {
+            // Another model on the same port is another monitor
+            if self.vendor != other.vendor || self.product != other.product {
+                return None;
+            }
+            return match (&self.serial, &other.serial) {
+                (Some(a), Some(b)) if a == b => Some(Match::Serial),
+                // Two units of the same model
+                (Some(_), Some(_)) => None,
+                _ => Some(Match::Model),
+            };
+        }
+        if self.connector.is_some() && self.connector == other.connector {
+            return Some(Match::Connector);
+        }
+        if !self.name.is_empty() && self.name == other.name {
+            return Some(Match::Name);
+        }
+        None
+    }
+}
+
+/// Decodes the three letter PNP manufacturer id packed in an EDID header.
+pub fn pnp_id(code: u16) -> Option<String> {
+    let letters = [(code >> 10) & 0x1f, (code >> 5) & 0x1f, code & 0x1f];
+    letters
+        .iter()
+        .map(|&l| (1..=26).contains(&l).then(|| (b'A' + l as u8 - 1) as char))
+        .collect()
+}
+
+/// Pairs each wanted monitor with at most one connected monitor.
+///
+/// `wanted` holds the stored identity (if any) and legacy id of each entry.
+/// The strongest matches are assigned first, so a monitor recognised by its
+/// serial is never taken by an entry that merely shares its port.
+pub fn assign(
+    wanted: &[(Option<&MonitorIdentity>, &str)],
+    monitors: &[Monitor],
+) -> Vec<Option<usize>> {
+    let mut candidates = Vec::new();
+    for (i, (identity, legacy_id)) in wanted.iter().enumerate() {
+        for (j, monitor) in monitors.iter().enumerate() {
+            let quality = match identity {
+                Some(identity) => identity.matches(&monitor.identity),
+                None => (monitor.id == *legacy_id).then_some(Match::LegacyId),
+            };
+            // Among equally good matches, such as identical panels without
+            // serials, the monitor still on the same port wins
+            let same_port = identity.is_some_and(|identity| {
+                identity.connector.is_some() && identity.connector == monitor.identity.connector
+            });
+            if let Some(quality) = quality {
+                candidates.push(((quality, same_port), i, j));
+            }
+        }
+    }
+    // Stable sort keeps profile order among equally good matches
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.0));
+
+    let mut assigned = vec![None; wanted.len()];
+    let mut taken = vec![false; monitors.len()];
+    for (_, i, j) in candidates {
+        if assigned[i].is_none() && !taken[j] {
+            assigned[i] = Some(j);
+            taken[j] = true;
+        }
+    }
+    assigned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(model: Option<(&str, &str)>, serial: Option<&str>, port: &str) -> MonitorIdentity {
+        MonitorIdentity {
+            vendor: model.map(|(vendor, _)| vendor.to_string()),
+            product: model.map(|(_, product)| product.to_string()),
+            serial: serial.map(str::to_string),
+            connector: Some(port.to_string()),
+            name: "Monitor".to_string(),
+        }
+    }
+
+    const DELL: Option<(&str, &str)> = Some(("DEL", "A0B1"));
+    const LG: Option<(&str, &str)> = Some(("GSM", "5B09"));
+
+    #[test]
+    fn matches_by_the_strongest_evidence() {
+        let stored = identity(DELL, Some("CN01"), "DP-1");
+        assert_eq!(
+            stored.matches(&identity(DELL, Some("CN01"), "DP-2")),
+            Some(Match::Serial)
+        );
+        assert_eq!(
+            stored.matches(&identity(DELL, None, "DP-2")),
+            Some(Match::Model)
+        );
+        let bare = identity(None, None, "DP-1");
+        assert_eq!(
+            bare.matches(&identity(DELL, None, "DP-1")),
+            Some(Match::Connector)
+        );
+        let renamed = MonitorIdentity {
+            connector: None,
+            ..bare.clone()
+        };
+        assert_eq!(
+            renamed.matches(&identity(None, None, "HDMI-1")),
+            Some(Match::Name)
+        );
+    }
+
+    #[test]
+    fn tells_different_monitors_apart() {
+        let stored = identity(DELL, Some("CN01"), "DP-1");
+        // Another unit of the same model
+        assert_eq!(stored.matches(&identity(DELL, Some("CN02"), "DP-1")), None);
+        // Another model plugged into the same port, under the same name
+        assert_eq!(stored.matches(&identity(LG, None, "DP-1")), None);
+        let nameless = MonitorIdentity {
+            name: String::new(),
+            ..identity(None, None, "DP-1")
+        };
+        assert_eq!(nameless.matches(&identity(None, None, "DP-2")), None);
+    }
+
+    fn connected(identities: &[MonitorIdentity]) -> Vec<Monitor> {
+        identities
+            .iter()
+            .enumerate()
+            .map(|(i, identity)| {
+                let mut monitor = Monitor::fake(&format!("M{}", i), "1920x1080@60", (0, 0));
+                monitor.identity = identity.clone();
+                monitor
+            })
+            .collect()
+    }
+
+    #[test]
+    fn assigns_strong_matches_first() {
+        // The first entry only knows the port, which the second entry's
+        // monitor moved to; the serial wins it
+        let by_port = MonitorIdentity {
+            name: "Old".to_string(),
+            ..identity(None, None, "DP-2")
+        };
+        let by_serial = identity(DELL, Some("CN01"), "DP-1");
+        let monitors = connected(&[
+            identity(LG, None, "DP-1"),
+            identity(DELL, Some("CN01"), "DP-2"),
+        ]);
+        let wanted = [(Some(&by_port), ""), (Some(&by_serial), "")];
+        assert_eq!(assign(&wanted, &monitors), [None, Some(1)]);
+    }
+
+    #[test]
+    fn breaks_ties_on_the_port_then_the_order() {
+        let left = identity(DELL, None, "DP-1");
+        let right = identity(DELL, None, "DP-2");
+        let monitors = connected(&[right.clone(), left.clone()]);
+        let wanted = [(Some(&left), ""), (Some(&right), "")];
+        assert_eq!(assign(&wanted, &monitors), [Some(1), Some(0)]);
+
+        // Without ports the first entry gets the first monitor
+        let portless = MonitorIdentity {
+            connector: None,
+            ..left.clone()
+        };
+        let wanted = [(Some(&portless), ""), (Some(&portless), "")];
+        assert_eq!(assign(&wanted, &monitors), [Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn falls_back_to_legacy_ids() {
+        let monitors = connected(&[identity(DELL, None, "DP-1"), identity(LG, None, "DP-2")]);
+        let wanted = [(None, "M1"), (None, "M7")];
+        assert_eq!(assign(&wanted, &monitors), [Some(1), None]);
+    }
+
+    #[test]
+    fn decodes_pnp_ids() {
+        assert_eq!(pnp_id(0x10AC).as_deref(), Some("DEL"));
+        assert_eq!(pnp_id(0x1E6D).as_deref(), Some("GSM"));
+        assert_eq!(pnp_id(0), None);
+    }
+}
@@ -0,0 +1,395 @@
+//! Geometry checks for the desktop layout a transaction would produce.
+//!
+//! Monitors showing exactly the same area of the desktop mirror each other,
+//! any other overlap is an error.
+
+use anyhow::{anyhow, Result};
+
+use super::monitor::Monitor;
+use super::resolution::Resolution;
+use super::transaction::MonitorChange;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i64,
+    pub y: i64,
+    pub width: i64,
+    pub height: i64,
+}
+
+impl Rect {
+    fn right(&self) -> i64 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> i64 {
+        self.y + self.height
+    }
+
+    fn overlaps(&self, other: &Rect) -> bool {
+        self.x < other.right()
+            && other.x < self.right()
+            && self.y < other.bottom()
+            && other.y < self.bottom()
+    }
+
+    /// Shares a stretch of edge with `other`, touching at a corner is not
+    /// enough for the OS to move the cursor across.
+    fn adjoins(&self, other: &Rect) -> bool {
+        let spans = |a0: i64, a1: i64, b0: i64, b1: i64| a0.max(b0) < a1.min(b1);
+        ((self.right() == other.x || other.right() == self.x)
+            && spans(self.y, self.bottom(), other.y, other.bottom()))
+            || ((self.bottom() == other.y || other.bottom() == self.y)
+                && spans(self.x, self.right(), other.x, other.right()))
+    }
+}
+
+/// Where every monitor that is enabled once `changes` are applied ends up,
+/// in desktop coordinates. Sizes follow the target orientation and shrink
+/// with the monitor's scale.
+pub fn planned<'a>(monitors: &'a [Monitor], changes: &[MonitorChange]) -> Vec<(&'a Monitor, Rect)> {
+    monitors
+        .iter()
+        .filter_map(|m| {
+            let change = changes.iter().find(|c| c.device_name == m.device_name);
+            if !change.and_then(|c| c.enabled).unwrap_or(m.enabled) {
+                return None;
+            }
+            let orientation = change
+                .and_then(|c| c.orientation)
+                .unwrap_or(m.current_orientation);
+            let (width, height) = match change.and_then(|c| c.resolution.as_ref()) {
+                Some(r) => (r.width, r.height),
+                None if orientation.is_portrait() != m.current_orientation.is_portrait() => {
+                    (m.current_resolution.height, m.current_resolution.width)
+                }
+                None => (m.current_resolution.width, m.current_resolution.height),
+            };
+            let scale = change.and_then(|c| c.scale).unwrap_or(m.scale);
+            let (x, y) = change.and_then(|c| c.position).unwrap_or(m.position);
+            let rect = Rect {
+                x: x as i64,
+                y: y as i64,
+                width: (width as f64 / scale).round() as i64,
+                height: (height as f64 / scale).round() as i64,
+            };
+            Some((m, rect))
+        })
+        .collect()
+}
+
+/// Checks that the resulting desktop has no overlapping monitors and no
+/// monitor cut off from the others.
+pub fn validate(monitors: &[Monitor], changes: &[MonitorChange]) -> Result<()> {
+    let rects = planned(monitors, changes);
+    if rects.is_empty() && !monitors.is_empty() {
+        return Err(anyhow!("No monitor would be left enabled"));
+    }
+
+    for (i, (monitor, rect)) in rects.iter().enumerate() {
+        if let Some((other, _)) = rects[i + 1..]
+            .iter()
+            .find(|(_, r)| r != rect && rect.overlaps(r))
+        {
+            return Err(anyhow!("{} would overlap {}", monitor.name, other.name));
+        }
+    }
+
+    // Flood fill from the first monitor along shared edges and mirrors
+    let mut reached = vec![false; rects.len()];
+    let mut pending = if rects.is_empty() { vec![] } else { vec![0] };
+    while let Some(i) = pending.pop() {
+        if std::mem::replace(&mut reached[i], true) {
+            continue;
+        }
+        let (_, rect) = rects[i];
+        pending.extend(
+            (0..rects.len())
+                .filter(|&j| !reached[j] && (rects[j].1 == rect || rect.adjoins(&rects[j].1))),
+        );
+    }
+    if let Some(i) = reached.iter().position(|r| !r) {
+        return Err(anyhow!(
+            "{} would not touch any other monitor",
+            rects[i].0.name
+        ));
+    }
+
+    Ok(())
+}
+
+/// Extends `changes` so the monitor being made primary ends up at the
+/// origin, shifting every other monitor by the same offset. Needed by
+/// platforms where the primary monitor defines (0, 0), like Windows.
+pub fn reanchor(monitors: &[Monitor], changes: &[MonitorChange]) -> Vec<MonitorChange> {
+    let mut changes = changes.to_vec();
+    let Some(primary) = changes.iter().find(|c| c.primary) else {
+        return changes;
+    };
+    let rects = planned(monitors, &changes);
+    let Some((_, anchor)) = rects
+        .iter()
+        .find(|(m, _)| m.device_name == primary.device_name)
+    else {
+        return changes;
+    };
+    let (dx, dy) = (anchor.x, anchor.y);
+    if (dx, dy) == (0, 0) {
+        return changes;
+    }
+    for (monitor, rect) in &rects {
+        let position = ((rect.x - dx) as i32, (rect.y - dy) as i32);
+        match changes
+            .iter_mut()
+            .find(|c| c.device_name == monitor.device_name)
+        {
+            Some(change) => change.position = Some(position),
+            None => changes.push(MonitorChange {
+                position: Some(position),
+                ..MonitorChange::new(&monitor.device_name)
+            }),
+        }
+    }
+    changes
+}
+
+/// Groups monitors showing the same area of the desktop, in enumeration
+/// order. Groups of more than one monitor are mirrored.
+pub fn mirror_groups<'a>(rects: &[(&'a Monitor, Rect)]) -> Vec<Vec<&'a Monitor>> {
+    let mut groups: Vec<(Rect, Vec<&Monitor>)> = Vec::new();
+    for &(monitor, rect) in rects {
+        match groups.iter_mut().find(|(r, _)| *r == rect) {
+            Some((_, group)) => group.push(monitor),
+            None => groups.push((rect, vec![monitor])),
+        }
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+/// Pairs every mirroring monitor with the monitor it mirrors, the first one
+/// enumerated among those showing the same area.
+pub fn mirrors<'a>(rects: &[(&'a Monitor, Rect)]) -> Vec<(&'a Monitor, &'a Monitor)> {
+    mirror_groups(rects)
+        .into_iter()
+        .flat_map(|group| {
+            let source = group[0];
+            group.into_iter().skip(1).map(move |m| (m, source))
+        })
+        .collect()
+}
+
+/// Where `device_name` fits right of the rightmost other monitor, top edges
+/// aligned, used to take a monitor out of a mirror.
+pub fn beside(rects: &[(&Monitor, Rect)], device_name: &str) -> (i32, i32) {
+    rects
+        .iter()
+        .filter(|(m, _)| m.device_name != device_name)
+        .map(|(_, r)| r)
+        .max_by_key(|r| r.right())
+        .map_or((0, 0), |r| (r.right() as i32, r.y as i32))
+}
+
+/// The mode each monitor should use to mirror the others: the largest size
+/// they all support, at the fastest refresh rate they share or, failing
+/// that, the fastest each supports at that size. Progressive modes at the
+/// default scaling win over interlaced or rescaled ones.
+pub fn common_mode(monitors: &[&Monitor]) -> Option<Vec<Resolution>> {
+    let (first, rest) = monitors.split_first()?;
+    let supports = |m: &Monitor, width: u32, height: u32| {
+        m.available_resolutions
+            .iter()
+            .any(|r| r.width == width && r.height == height)
+    };
+    let (width, height) = first
+        .available_resolutions
+        .iter()
+        .map(|r| (r.width, r.height))
+        .filter(|&(w, h)| rest.iter().all(|m| supports(m, w, h)))
+        .max_by_key(|&(w, h)| (w as u64 * h as u64, w))?;
+
+    let at_size = |m: &'_ Monitor| {
+        m.available_resolutions
+            .iter()
+            .filter(|r| r.width == width && r.height == height)
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    let shared = at_size(first)
+        .iter()
+        .map(|r| r.refresh)
+        .filter(|&f| {
+            rest.iter()
+                .all(|m| at_size(m).iter().any(|r| r.refresh == f))
+        })
+        .max();
+
+    monitors
+        .iter()
+        .map(|m| {
+            at_size(m)
+                .into_iter()
+                .filter(|r| shared.is_none_or(|f| r.refresh == f))
+                .max_by_key(|r| {
+                    (
+                        !r.interlaced,
+                        r.scaling.is_default(),
+                        r.refresh,
+                        r.bits_per_pixel,
+                    )
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::orientation::Orientation;
+    use crate::display::transaction::Transaction;
+
+    /// Two 1080p monitors side by side.
+    fn desk() -> Vec<Monitor> {
+        vec![
+            Monitor::fake("A", "1920x1080@60", (0, 0)),
+            Monitor::fake("B", "1920x1080@60", (1920, 0)),
+        ]
+    }
+
+    fn change(device_name: &str) -> MonitorChange {
+        MonitorChange::new(device_name)
+    }
+
+    #[test]
+    fn accepts_adjoining_monitors() {
+        assert!(validate(&desk(), &[]).is_ok());
+        let below = MonitorChange {
+            position: Some((500, 1080)),
+            ..change("B")
+        };
+        assert!(validate(&desk(), &[below]).is_ok());
+    }
+
+    #[test]
+    fn rejects_overlap_and_gaps() {
+        let overlap = MonitorChange {
+            position: Some((1000, 0)),
+            ..change("B")
+        };
+        let e = validate(&desk(), &[overlap]).unwrap_err();
+        assert_eq!(e.to_string(), "A would overlap B");
+
+        let gap = MonitorChange {
+            position: Some((2000, 0)),
+            ..change("B")
+        };
+        let e = validate(&desk(), &[gap]).unwrap_err();
+        assert_eq!(e.to_string(), "B would not touch any other monitor");
+
+        let corner = MonitorChange {
+            position: Some((1920, 1080)),
+            ..change("B")
+        };
+        assert!(validate(&desk(), &[corner]).is_err());
+    }
+
+    #[test]
+    fn identical_areas_mirror() {
+        let mirror = MonitorChange {
+            position: Some((0, 0)),
+            ..change("B")
+        };
+        assert!(validate(&desk(), std::slice::from_ref(&mirror)).is_ok());
+        let monitors = desk();
+        let rects = planned(&monitors, &[mirror]);
+        let pairs = mirrors(&rects);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!((pairs[0].0.id.as_str(), pairs[0].1.id.as_str()), ("B", "A"));
+    }
+
+    #[test]
+    fn size_changes_are_checked() {
+        let larger = MonitorChange {
+            resolution: Some("2560x1440@60".parse().unwrap()),
+            ..change("A")
+        };
+        assert!(Transaction::from(vec![larger.clone()]).reshapes_layout());
+        assert_eq!(
+            validate(&desk(), &[larger]).unwrap_err().to_string(),
+            "A would overlap B"
+        );
+
+        let rotated = MonitorChange {
+            orientation: Some(Orientation::Portrait),
+            ..change("A")
+        };
+        let monitors = desk();
+        let rects = planned(&monitors, std::slice::from_ref(&rotated));
+        assert_eq!((rects[0].1.width, rects[0].1.height), (1080, 1920));
+        assert!(validate(&desk(), &[rotated]).is_err());
+
+        let scaled = MonitorChange {
+            scale: Some(2.0),
+            ..change("A")
+        };
+        let rects = planned(&monitors, std::slice::from_ref(&scaled));
+        assert_eq!((rects[0].1.width, rects[0].1.height), (960, 540));
+        assert!(validate(&desk(), &[scaled]).is_err());
+    }
+
+    #[test]
+    fn needs_an_enabled_monitor() {
+        let off = |name| MonitorChange {
+            enabled: Some(false),
+            ..change(name)
+        };
+        assert!(validate(&desk(), &[off("B")]).is_ok());
+        assert_eq!(
+            validate(&desk(), &[off("A"), off("B")])
+                .unwrap_err()
+                .to_string(),
+            "No monitor would be left enabled"
+        );
+    }
+
+    #[test]
+    fn reanchor_moves_primary_to_origin() {
+        let primary = MonitorChange {
+            primary: true,
+            ..change("B")
+        };
+        let changes = reanchor(&desk(), &[primary]);
+        let position = |name| {
+            changes
+                .iter()
+                .find(|c| c.device_name == name)
+                .and_then(|c| c.position)
+        };
+        assert_eq!(position("A"), Some((-1920, 0)));
+        assert_eq!(position("B"), Some((0, 0)));
+        assert!(
+            changes
+                .iter()
+                .find(|c| c.device_name == "B")
+                .unwrap()
+                .primary
+        );
+
+        // Already at the origin, nothing to shift
+        let primary = MonitorChange {
+            primary: true,
+            ..change("A")
+        };
+        assert_eq!(
+            reanchor(&desk(), std::slice::from_ref(&primary)),
+            vec![primary]
+        );
+    }
+
+    #[test]
+    fn beside_the_rightmost_monitor() {
+        let monitors = desk();
+        let rects = planned(&monitors, &[]);
+        assert_eq!(beside(&rects, "A"), (3840, 0));
+        assert_eq!(beside(&rects, "B"), (1920, 0));
+    }
+}
@@ -1,9 +1,18 @@
+pub mod backend;
+pub mod changes;
+pub mod edid;
+pub mod identity;
+pub mod layout;
 pub mod manager;
 pub mod monitor;
 pub mod orientation;
 pub mod resolution;
+pub mod transaction;
 
+pub use backend::{BackendKind, BackendOptions};
+pub use changes::Changes;
 pub use manager::DisplayManager;
 pub use monitor::Monitor;
 pub use orientation::Orientation;
-pub use resolution::Resolution;
+pub use resolution::{ModeSpec, RefreshRate, Resolution, ScalingMode};
+pub use transaction::Transaction;
@@ -1,3 +1,5 @@
+use super::edid::Edid;
+use super::identity::MonitorIdentity;
 use super::orientation::Orientation;
 use super::resolution::Resolution;
 use serde::{Deserialize, Serialize};
@@ -11,6 +13,63 @@ pub struct Monitor {
     pub current_orientation: Orientation,
     pub position: (i32, i32),
     pub is_primary: bool,
+    /// Connected but detached from the desktop when false, the resolution
+    /// and position are then the ones it had when it was last enabled
+    pub enabled: bool,
+    /// Desktop scaling, at 2.0 everything is drawn twice as large and the
+    /// monitor covers half its resolution of the desktop in each direction
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    pub identity: MonitorIdentity,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edid: Option<Edid>,
     #[serde(skip)]
     pub available_resolutions: Vec<Resolution>,
 }
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+impl Monitor {
+    /// The supported mode matching the panel's preferred EDID timing, in the
+    /// frame of the current orientation like every other resolution here.
+    pub fn native_resolution(&self) -> Option<&Resolution> {
+        let (width, height, refresh) = self.edid.as_ref()?.native_mode()?;
+        let (width, height) = if self.current_orientation.is_portrait() {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        self.available_resolutions
+            .iter()
+            .filter(|r| r.width == width && r.height == height)
+            .min_by_key(|r| r.refresh.millihertz().abs_diff(refresh.millihertz()))
+    }
+}
+
+#[cfg(test)]
+impl Monitor {
+    /// A landscape monitor at `position` running `mode` (`1920x1080@60`),
+    /// which is also its only mode. Its name and device name are `id`.
+    pub fn fake(id: &str, mode: &str, position: (i32, i32)) -> Monitor {
+        let resolution: Resolution = mode.parse().unwrap();
+        Monitor {
+            id: id.to_string(),
+            name: id.to_string(),
+            device_name: id.to_string(),
+            current_resolution: resolution.clone(),
+            current_orientation: Orientation::Landscape,
+            position,
+            is_primary: false,
+            enabled: true,
+            scale: 1.0,
+            identity: MonitorIdentity {
+                name: id.to_string(),
+                ..Default::default()
+            },
+            edid: None,
+            available_resolutions: vec![resolution],
+        }
+    }
+}
@@ -1,5 +1,7 @@
+use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub enum Orientation {
@@ -11,6 +13,8 @@ pub enum Orientation {
 }
 
 impl Orientation {
+    // DMDO_* values, only the Win32 backend speaks these
+    #[cfg_attr(not(windows), allow(dead_code))]
     pub fn from_u32(val: u32) -> Self {
         match val {
             0 => Orientation::Landscape,
@@ -21,10 +25,15 @@ impl Orientation {
         }
     }
 
+    #[cfg_attr(not(windows), allow(dead_code))]
     pub fn to_u32(self) -> u32 {
         self as u32
     }
 
+    pub fn is_portrait(self) -> bool {
+        matches!(self, Orientation::Portrait | Orientation::PortraitFlipped)
+    }
+
     pub fn degrees(self) -> &'static str {
         match self {
             Orientation::Landscape => "0°",
@@ -40,3 +49,90 @@ impl fmt::Display for Orientation {
         write!(f, "{}", self.degrees())
     }
 }
+
+/// Parses degrees of counterclockwise rotation (`90`, `90°`) or the names
+/// xrandr and Windows use for them (`left`, `portrait`, `inverted`, ...).
+impl FromStr for Orientation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().trim_end_matches('°').to_ascii_lowercase();
+        match name.as_str() {
+            "0" | "normal" | "landscape" => Ok(Orientation::Landscape),
+            "90" | "left" | "portrait" => Ok(Orientation::Portrait),
+            "180" | "inverted" | "landscape-flipped" => Ok(Orientation::LandscapeFlipped),
+            "270" | "right" | "portrait-flipped" => Ok(Orientation::PortraitFlipped),
+            _ => Err(anyhow!(
+                "Invalid orientation '{}', expected 0, 90, 180, 270, normal, left, inverted, right, landscape or portrait",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_spelling() {
+        let spellings = [
+            (Orientation::Landscape, ["0", "0°", "normal", "landscape"]),
+            (Orientation::Portrait, ["90", "90°", "left", "portrait"]),
+            (
+                Orientation::LandscapeFlipped,
+                ["180", "180°", "inverted", "landscape-flipped"],
+            ),
+            (
+                Orientation::PortraitFlipped,
+                ["270", "270°", "right", "portrait-flipped"],
+            ),
+        ];
+        for (orientation, names) in spellings {
+            for name in names {
+                assert_eq!(
+                    name.parse::<Orientation>().unwrap(),
+                    orientation,
+                    "{}",
+                    name
+                );
+            }
+            // Case and surrounding blanks do not matter
+            let shouted = format!(" {} ", names[2].to_uppercase());
+            assert_eq!(shouted.parse::<Orientation>().unwrap(), orientation);
+        }
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        for value in 0..4 {
+            let orientation = Orientation::from_u32(value);
+            assert_eq!(
+                orientation.to_string().parse::<Orientation>().unwrap(),
+                orientation
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        for bad in [
+            "",
+            "°",
+            "45",
+            "-90",
+            "360",
+            "up",
+            "portrait flipped",
+            "90°°x",
+        ] {
+            let error = bad.parse::<Orientation>().unwrap_err().to_string();
+            assert!(
+                error.contains("Invalid orientation"),
+                "{:?}: {}",
+                bad,
+                error
+            );
+        }
+    }
+}
@@ -1,19 +1,618 @@
+use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::str::FromStr;
 
+use super::monitor::Monitor;
+
+/// A refresh rate in millihertz, exact enough to tell 59.94 Hz from 60 Hz.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RefreshRate(u32);
+
+impl RefreshRate {
+    /// `None` for rates beyond what millihertz hold in a `u32`.
+    pub fn from_hz(hz: u32) -> Option<Self> {
+        hz.checked_mul(1000).map(Self)
+    }
+
+    pub fn from_millihertz(millihertz: u32) -> Self {
+        Self(millihertz)
+    }
+
+    /// The rate of a mode given its pixel clock and total frame size,
+    /// blanking included.
+    pub fn from_timing(pixel_clock_hz: u64, total_pixels: u64) -> Self {
+        if total_pixels == 0 {
+            return Self(0);
+        }
+        Self(((pixel_clock_hz * 1000 + total_pixels / 2) / total_pixels) as u32)
+    }
+
+    pub fn millihertz(self) -> u32 {
+        self.0
+    }
+
+    /// Whole hertz, as APIs without fractional rates (GDI) expect them.
+    /// Fractional rates are truncated the way Windows reports them, so
+    /// 59.94 Hz becomes 59.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn whole_hz(self) -> u32 {
+        self.0 / 1000
+    }
+}
+
+/// Formats as hertz with up to two decimals, e.g. `60` or `59.94`.
+impl std::fmt::Display for RefreshRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hundredths = (self.0 + 5) / 10;
+        match hundredths % 100 {
+            0 => write!(f, "{}", hundredths / 100),
+            cents if cents % 10 == 0 => write!(f, "{}.{}", hundredths / 100, cents / 10),
+            cents => write!(f, "{}.{:02}", hundredths / 100, cents),
+        }
+    }
+}
+
+/// Parses hertz with up to three decimals, e.g. `144` or `59.94`, with an
+/// optional `Hz` suffix.
+impl FromStr for RefreshRate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hz = s.trim();
+        let hz = hz
+            .strip_suffix("Hz")
+            .or_else(|| hz.strip_suffix("hz"))
+            .unwrap_or(hz)
+            .trim_end();
+        let invalid = || anyhow!("Invalid refresh rate '{}'", s);
+        let (whole, fraction) = hz.split_once('.').unwrap_or((hz, ""));
+        if whole.is_empty() || fraction.len() > 3 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let whole: u32 = whole.parse().map_err(|_| invalid())?;
+        let fraction: u32 = format!("{:0<3}", fraction).parse().map_err(|_| invalid())?;
+        whole
+            .checked_mul(1000)
+            .and_then(|mhz| mhz.checked_add(fraction))
+            .map(RefreshRate)
+            .ok_or_else(invalid)
+    }
+}
+
+/// How a mode other than the panel's native one is fitted onto the panel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScalingMode {
+    /// Whatever the driver or monitor does by default
+    #[default]
+    Default,
+    /// Fill the whole panel, distorting the aspect ratio if needed
+    Stretch,
+    /// Show the mode 1:1 in the middle of the panel
+    Center,
+    /// Scale up as far as the aspect ratio allows, with black bars
+    Aspect,
+}
+
+impl ScalingMode {
+    pub fn is_default(&self) -> bool {
+        *self == ScalingMode::Default
+    }
+}
+
+impl std::fmt::Display for ScalingMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScalingMode::Default => write!(f, "Default"),
+            ScalingMode::Stretch => write!(f, "Stretched"),
+            ScalingMode::Center => write!(f, "Centered"),
+            ScalingMode::Aspect => write!(f, "Keep aspect ratio"),
+        }
+    }
+}
+
+/// A display mode. Modes differing only in interlacing or scaling are
+/// distinct, e.g. 1920x1080 stretched and 1920x1080 keeping its aspect ratio
+/// on a 2560x1440 panel.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "StoredResolution")]
 pub struct Resolution {
     pub width: u32,
     pub height: u32,
-    pub frequency: u32,
+    #[serde(rename = "refresh_mhz")]
+    pub refresh: RefreshRate,
     pub bits_per_pixel: u32,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub interlaced: bool,
+    #[serde(skip_serializing_if = "ScalingMode::is_default")]
+    pub scaling: ScalingMode,
+}
+
+/// What profiles and topology files hold: a table, including the integer
+/// `frequency` in Hz written before refresh rates were stored in
+/// millihertz, or a hand written string like `"1920x1080@59.94"`.
+enum StoredResolution {
+    Text(String),
+    Table(StoredTable),
+}
+
+#[derive(Deserialize)]
+struct StoredTable {
+    width: u32,
+    height: u32,
+    refresh_mhz: Option<u32>,
+    frequency: Option<u32>,
+    bits_per_pixel: u32,
+    #[serde(default)]
+    interlaced: bool,
+    #[serde(default)]
+    scaling: ScalingMode,
+}
+
+// Written out rather than `#[serde(untagged)]` to keep the table's own
+// errors, e.g. a missing field, instead of "did not match any variant"
+impl<'de> Deserialize<'de> for StoredResolution {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StoredVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for StoredVisitor {
+            type Value = StoredResolution;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a mode table or a string like \"1920x1080@60\"")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(StoredResolution::Text(v.to_string()))
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                map: A,
+            ) -> Result<Self::Value, A::Error> {
+                StoredTable::deserialize(serde::de::value::MapAccessDeserializer::new(map))
+                    .map(StoredResolution::Table)
+            }
+        }
+
+        deserializer.deserialize_any(StoredVisitor)
+    }
+}
+
+impl TryFrom<StoredResolution> for Resolution {
+    type Error = anyhow::Error;
+
+    fn try_from(stored: StoredResolution) -> Result<Self, Self::Error> {
+        match stored {
+            StoredResolution::Text(text) => text.parse(),
+            StoredResolution::Table(StoredTable {
+                width,
+                height,
+                refresh_mhz,
+                frequency,
+                bits_per_pixel,
+                interlaced,
+                scaling,
+            }) => {
+                let refresh = match (refresh_mhz, frequency) {
+                    (Some(millihertz), _) => RefreshRate::from_millihertz(millihertz),
+                    (None, Some(hz)) => RefreshRate::from_hz(hz)
+                        .ok_or_else(|| anyhow!("frequency {} is out of range", hz))?,
+                    (None, None) => return Err(anyhow!("missing field `refresh_mhz`")),
+                };
+                Ok(Resolution {
+                    width,
+                    height,
+                    refresh,
+                    bits_per_pixel,
+                    interlaced,
+                    scaling,
+                })
+            }
+        }
+    }
+}
+
+/// A mode as users write it: `native`, `max`, or `WIDTHxHEIGHT` optionally
+/// followed by `i` for interlaced, `@RATE` in hertz and `:BITS` per pixel,
+/// e.g. `1920x1080@59.94:32`. Whatever is left out is picked per monitor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModeSpec {
+    /// The panel's preferred mode from its EDID
+    Native,
+    /// The largest mode, at its fastest refresh rate
+    Max,
+    Exact {
+        width: u32,
+        height: u32,
+        interlaced: bool,
+        refresh: Option<RefreshRate>,
+        bits_per_pixel: Option<u32>,
+    },
+}
+
+impl ModeSpec {
+    /// The mode of `monitor` meant by this spec, in the frame of its current
+    /// orientation. A given rate picks the nearest one the size supports,
+    /// left out parts prefer progressive modes at the default scaling, then
+    /// the fastest rate and deepest color.
+    pub fn resolve<'a>(&self, monitor: &'a Monitor) -> Option<&'a Resolution> {
+        let preference = |r: &&Resolution| {
+            (
+                !r.interlaced,
+                r.scaling.is_default(),
+                r.refresh,
+                r.bits_per_pixel,
+            )
+        };
+        match *self {
+            ModeSpec::Native => monitor.native_resolution(),
+            ModeSpec::Max => monitor
+                .available_resolutions
+                .iter()
+                .max_by_key(|r| (r.width as u64 * r.height as u64, preference(r))),
+            ModeSpec::Exact {
+                width,
+                height,
+                interlaced,
+                refresh,
+                bits_per_pixel,
+            } => {
+                let candidates = monitor.available_resolutions.iter().filter(|r| {
+                    r.width == width
+                        && r.height == height
+                        && r.interlaced == interlaced
+                        && bits_per_pixel.is_none_or(|bpp| r.bits_per_pixel == bpp)
+                });
+                match refresh {
+                    Some(refresh) => candidates.min_by_key(|r| {
+                        (
+                            r.refresh.0.abs_diff(refresh.0),
+                            !r.scaling.is_default(),
+                            Reverse(r.bits_per_pixel),
+                        )
+                    }),
+                    None => candidates.max_by_key(preference),
+                }
+            }
+        }
+    }
+
+    /// The same spec for an output turned by 90°.
+    pub fn rotated(&self) -> ModeSpec {
+        match *self {
+            ModeSpec::Exact {
+                width,
+                height,
+                interlaced,
+                refresh,
+                bits_per_pixel,
+            } => ModeSpec::Exact {
+                width: height,
+                height: width,
+                interlaced,
+                refresh,
+                bits_per_pixel,
+            },
+            spec => spec,
+        }
+    }
+}
+
+impl FromStr for ModeSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let spec = s.trim();
+        match spec.to_ascii_lowercase().as_str() {
+            "native" => return Ok(ModeSpec::Native),
+            "max" => return Ok(ModeSpec::Max),
+            _ => {}
+        }
+        let invalid = || {
+            anyhow!(
+                "Invalid mode '{}', expected e.g. 2560x1440, 1920x1080@59.94:32, native or max",
+                s
+            )
+        };
+
+        let (spec, bits_per_pixel) = match spec.split_once(':') {
+            Some((spec, bpp)) => {
+                let bpp = bpp.trim();
+                let bpp = bpp.strip_suffix("bit").unwrap_or(bpp);
+                (spec, Some(bpp.parse().map_err(|_| invalid())?))
+            }
+            None => (spec, None),
+        };
+        let (size, refresh) = match spec.split_once('@') {
+            Some((size, rate)) => (size, Some(rate.parse().map_err(|_| invalid())?)),
+            None => (spec, None),
+        };
+        let (width, height) = size.trim().split_once(['x', 'X']).ok_or_else(invalid)?;
+        let (height, interlaced) = match height.strip_suffix('i') {
+            Some(height) => (height, true),
+            None => (height, false),
+        };
+        Ok(ModeSpec::Exact {
+            width: width.parse().map_err(|_| invalid())?,
+            height: height.parse().map_err(|_| invalid())?,
+            interlaced,
+            refresh,
+            bits_per_pixel,
+        })
+    }
+}
+
+/// Formats the spec the way it is parsed.
+impl std::fmt::Display for ModeSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModeSpec::Native => write!(f, "native"),
+            ModeSpec::Max => write!(f, "max"),
+            ModeSpec::Exact {
+                width,
+                height,
+                interlaced,
+                refresh,
+                bits_per_pixel,
+            } => {
+                write!(
+                    f,
+                    "{}x{}{}",
+                    width,
+                    height,
+                    if *interlaced { "i" } else { "" }
+                )?;
+                if let Some(refresh) = refresh {
+                    write!(f, "@{}", refresh)?;
+                }
+                if let Some(bpp) = bits_per_pixel {
+                    write!(f, ":{}", bpp)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Parses the explicit forms of `ModeSpec`. Without a monitor to pick from,
+/// a left out rate means 60 Hz and left out bits 32.
+impl FromStr for Resolution {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse()? {
+            ModeSpec::Exact {
+                width,
+                height,
+                interlaced,
+                refresh,
+                bits_per_pixel,
+            } => Ok(Resolution {
+                width,
+                height,
+                refresh: refresh.unwrap_or(RefreshRate(60_000)),
+                bits_per_pixel: bits_per_pixel.unwrap_or(32),
+                interlaced,
+                scaling: ScalingMode::Default,
+            }),
+            _ => Err(anyhow!(
+                "'{}' depends on the monitor, give a size like 1920x1080",
+                s.trim()
+            )),
+        }
+    }
+}
+
+impl Resolution {
+    /// The same mode with width and height swapped, as seen by a rotated output.
+    pub fn rotated(&self) -> Resolution {
+        Resolution {
+            width: self.height,
+            height: self.width,
+            ..self.clone()
+        }
+    }
+
+    /// The mode in `modes` meant by `self`: the exact one if listed, else the
+    /// same kind of mode at the nearest rate less than 1 Hz away, so 60 Hz
+    /// finds 59.94 Hz on a monitor without a true 60.
+    pub fn closest<'a>(&self, modes: &'a [Resolution]) -> Option<&'a Resolution> {
+        if let Some(exact) = modes.iter().find(|m| *m == self) {
+            return Some(exact);
+        }
+        modes
+            .iter()
+            .filter(|m| {
+                m.width == self.width
+                    && m.height == self.height
+                    && m.bits_per_pixel == self.bits_per_pixel
+                    && m.interlaced == self.interlaced
+                    && m.scaling == self.scaling
+            })
+            .map(|m| (m.refresh.0.abs_diff(self.refresh.0), m))
+            .filter(|&(distance, _)| distance < 1000)
+            .min_by_key(|&(distance, _)| distance)
+            .map(|(_, m)| m)
+    }
 }
 
 impl std::fmt::Display for Resolution {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{}x{} @ {}Hz ({}bit)",
-            self.width, self.height, self.frequency, self.bits_per_pixel
-        )
+            "{}x{}{} @ {}Hz ({}bit)",
+            self.width,
+            self.height,
+            if self.interlaced { "i" } else { "" },
+            self.refresh,
+            self.bits_per_pixel
+        )?;
+        if !self.scaling.is_default() {
+            write!(f, ", {}", self.scaling.to_string().to_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(text: &str) -> Resolution {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_refresh_rates() {
+        let rate = |s: &str| s.parse::<RefreshRate>().map(RefreshRate::millihertz);
+        assert_eq!(rate("144").unwrap(), 144_000);
+        assert_eq!(rate("59.94").unwrap(), 59_940);
+        assert_eq!(rate("59.940Hz").unwrap(), 59_940);
+        assert_eq!(rate(" 23.976 hz").unwrap(), 23_976);
+        for bad in [
+            "", "Hz", ".5", "60.1234", "60.-1", "-60", "sixty", "4294968",
+        ] {
+            assert!(rate(bad).is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn formats_refresh_rates() {
+        let shown = |mhz| RefreshRate::from_millihertz(mhz).to_string();
+        assert_eq!(shown(60_000), "60");
+        assert_eq!(shown(59_940), "59.94");
+        assert_eq!(shown(74_500), "74.5");
+        assert_eq!(shown(59_999), "60");
+        assert_eq!(
+            RefreshRate::from_timing(148_500_000, 2200 * 1125).millihertz(),
+            60_000
+        );
+        assert_eq!(RefreshRate::from_timing(1, 0).millihertz(), 0);
+    }
+
+    #[test]
+    fn parses_mode_specs() {
+        assert_eq!("Native".parse::<ModeSpec>().unwrap(), ModeSpec::Native);
+        assert_eq!(" max ".parse::<ModeSpec>().unwrap(), ModeSpec::Max);
+        assert_eq!(
+            "1920x1080i@59.94:32bit".parse::<ModeSpec>().unwrap(),
+            ModeSpec::Exact {
+                width: 1920,
+                height: 1080,
+                interlaced: true,
+                refresh: Some(RefreshRate::from_millihertz(59_940)),
+                bits_per_pixel: Some(32),
+            }
+        );
+        assert_eq!(
+            "2560X1440".parse::<ModeSpec>().unwrap(),
+            ModeSpec::Exact {
+                width: 2560,
+                height: 1440,
+                interlaced: false,
+                refresh: None,
+                bits_per_pixel: None,
+            }
+        );
+        for bad in [
+            "",
+            "1920",
+            "1920x",
+            "x1080",
+            "1920x1080@",
+            "1920x1080:deep",
+            "wide",
+        ] {
+            assert!(bad.parse::<ModeSpec>().is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn mode_specs_round_trip() {
+        for text in [
+            "native",
+            "max",
+            "1920x1080",
+            "1920x1080i@59.94:32",
+            "3840x2160@144",
+        ] {
+            assert_eq!(text.parse::<ModeSpec>().unwrap().to_string(), text);
+        }
+    }
+
+    #[test]
+    fn parses_resolutions_with_defaults() {
+        let r = mode("1280x720");
+        assert_eq!((r.width, r.height, r.bits_per_pixel), (1280, 720, 32));
+        assert_eq!(r.refresh, RefreshRate::from_millihertz(60_000));
+        assert!(!r.interlaced && r.scaling.is_default());
+        assert!("native".parse::<Resolution>().is_err());
+        assert_eq!(mode("1080x1920@75:16").rotated(), mode("1920x1080@75:16"));
+    }
+
+    #[test]
+    fn reads_stored_resolutions() {
+        #[derive(Deserialize)]
+        struct Stored {
+            mode: Resolution,
+        }
+        let read = |text: &str| toml::from_str::<Stored>(text).map(|s| s.mode);
+
+        assert_eq!(
+            read("mode = \"1920x1080@59.94\"").unwrap(),
+            mode("1920x1080@59.94")
+        );
+        let table = "mode = { width = 1920, height = 1080, bits_per_pixel = 32, ";
+        assert_eq!(
+            read(&format!("{}refresh_mhz = 59940 }}", table)).unwrap(),
+            mode("1920x1080@59.94")
+        );
+        // Written before rates were stored in millihertz
+        assert_eq!(
+            read(&format!("{}frequency = 60 }}", table)).unwrap(),
+            mode("1920x1080@60")
+        );
+        let fast = read(&format!("{}frequency = 4294968 }}", table)).unwrap_err();
+        assert!(fast.to_string().contains("out of range"), "{}", fast);
+        let missing = read(&format!("{}interlaced = false }}", table)).unwrap_err();
+        assert!(missing.to_string().contains("refresh_mhz"), "{}", missing);
+        assert!(read("mode = \"fast\"").is_err());
+    }
+
+    #[test]
+    fn finds_the_closest_rate() {
+        let modes = [
+            mode("1920x1080@59.94"),
+            mode("1920x1080@50"),
+            mode("1280x720@60"),
+        ];
+        assert_eq!(mode("1920x1080@60").closest(&modes), Some(&modes[0]));
+        assert_eq!(mode("1280x720@60").closest(&modes), Some(&modes[2]));
+        assert_eq!(mode("1920x1080@55").closest(&modes), None);
+        assert_eq!(mode("1920x1080@60:16").closest(&modes), None);
+    }
+
+    #[test]
+    fn resolves_specs_against_a_monitor() {
+        let mut monitor = Monitor::fake("A", "1920x1080@60", (0, 0));
+        monitor.available_resolutions = vec![
+            mode("1920x1080@60"),
+            mode("1920x1080@144"),
+            mode("1920x1080i@120"),
+            mode("1920x1080@120:16"),
+            mode("2560x1440@59.95"),
+        ];
+        let resolve = |spec: &str| spec.parse::<ModeSpec>().unwrap().resolve(&monitor).cloned();
+
+        assert_eq!(resolve("max"), Some(mode("2560x1440@59.95")));
+        assert_eq!(resolve("1920x1080"), Some(mode("1920x1080@144")));
+        assert_eq!(resolve("1920x1080@119.88"), Some(mode("1920x1080@120:16")));
+        assert_eq!(resolve("1920x1080i"), Some(mode("1920x1080i@120")));
+        assert_eq!(resolve("1920x1080:16"), Some(mode("1920x1080@120:16")));
+        assert_eq!(resolve("2560x1440@60"), Some(mode("2560x1440@59.95")));
+        assert_eq!(resolve("1024x768"), None);
     }
 }
@@ -0,0 +1,256 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use super::layout;
+use super::monitor::Monitor;
+use super::orientation::Orientation;
+use super::resolution::Resolution;
+
+/// Requested state of one monitor, `None` fields are left untouched.
+///
+/// `resolution` is expressed in the target orientation, i.e. a 1080p panel
+/// turned to portrait is requested as 1080x1920.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct MonitorChange {
+    pub device_name: String,
+    pub resolution: Option<Resolution>,
+    pub orientation: Option<Orientation>,
+    /// Top left corner in desktop coordinates
+    pub position: Option<(i32, i32)>,
+    /// Make this the primary monitor
+    #[serde(default)]
+    pub primary: bool,
+    /// Attach (`true`) or detach (`false`) the monitor from the desktop
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    /// Desktop scale factor
+    #[serde(default)]
+    pub scale: Option<f64>,
+}
+
+impl MonitorChange {
+    pub fn new(device_name: &str) -> Self {
+        Self {
+            device_name: device_name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether the change sets anything besides the enabled state, which a
+    /// disabled monitor cannot take.
+    pub fn configures(&self) -> bool {
+        self.resolution.is_some()
+            || self.orientation.is_some()
+            || self.position.is_some()
+            || self.scale.is_some()
+            || self.primary
+    }
+
+    /// Whether the change affects the area the monitor covers in the
+    /// layout: its place, its size or whether it is there at all.
+    pub fn reshapes(&self) -> bool {
+        self.resolution.is_some()
+            || self.orientation.is_some()
+            || self.scale.is_some()
+            || self.position.is_some()
+            || self.enabled.is_some()
+    }
+}
+
+impl fmt::Display for MonitorChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.enabled == Some(false) {
+            return write!(f, "disabled");
+        }
+        match &self.resolution {
+            Some(resolution) => write!(f, "{}", resolution)?,
+            None => write!(f, "current mode")?,
+        }
+        if let Some(orientation) = self.orientation {
+            write!(f, ", {}", orientation)?;
+        }
+        if let Some(scale) = self.scale {
+            write!(f, ", scale {}", scale)?;
+        }
+        if let Some((x, y)) = self.position {
+            write!(f, " at ({}, {})", x, y)?;
+        }
+        if self.primary {
+            write!(f, " as primary")?;
+        }
+        Ok(())
+    }
+}
+
+/// A set of monitor changes that is validated and committed as a whole.
+///
+/// Backends that can commit everything in one step do so, the others apply
+/// monitor by monitor and roll back the ones already changed on failure.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Transaction {
+    changes: Vec<MonitorChange>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entry(&mut self, device_name: &str) -> &mut MonitorChange {
+        let index = match self
+            .changes
+            .iter()
+            .position(|c| c.device_name == device_name)
+        {
+            Some(index) => index,
+            None => {
+                self.changes.push(MonitorChange::new(device_name));
+                self.changes.len() - 1
+            }
+        };
+        &mut self.changes[index]
+    }
+
+    pub fn set_resolution(&mut self, device_name: &str, resolution: Resolution) -> &mut Self {
+        self.entry(device_name).resolution = Some(resolution);
+        self
+    }
+
+    pub fn set_orientation(&mut self, device_name: &str, orientation: Orientation) -> &mut Self {
+        self.entry(device_name).orientation = Some(orientation);
+        self
+    }
+
+    pub fn set_position(&mut self, device_name: &str, position: (i32, i32)) -> &mut Self {
+        self.entry(device_name).position = Some(position);
+        self
+    }
+
+    pub fn set_scale(&mut self, device_name: &str, scale: f64) -> &mut Self {
+        self.entry(device_name).scale = Some(scale);
+        self
+    }
+
+    pub fn set_enabled(&mut self, device_name: &str, enabled: bool) -> &mut Self {
+        self.entry(device_name).enabled = Some(enabled);
+        self
+    }
+
+    pub fn set_primary(&mut self, device_name: &str) -> &mut Self {
+        for change in &mut self.changes {
+            change.primary = false;
+        }
+        self.entry(device_name).primary = true;
+        self
+    }
+
+    /// Puts `monitors` into clone mode: each gets the best mode they all
+    /// support and the same top left corner, `position`.
+    pub fn mirror(&mut self, monitors: &[&Monitor], position: (i32, i32)) -> Result<&mut Self> {
+        let modes = layout::common_mode(monitors).ok_or_else(|| {
+            let names: Vec<_> = monitors.iter().map(|m| m.name.as_str()).collect();
+            anyhow!("{} have no resolution in common", names.join(" and "))
+        })?;
+        for (monitor, mode) in monitors.iter().zip(modes) {
+            self.set_resolution(&monitor.device_name, mode)
+                .set_position(&monitor.device_name, position);
+        }
+        Ok(self)
+    }
+
+    /// Replaces each requested resolution with the closest mode its monitor
+    /// advertises, see `Resolution::closest`. Resolutions no mode is close
+    /// to are left for the backend to reject.
+    pub fn match_modes(&mut self, monitors: &[Monitor]) -> &mut Self {
+        for change in &mut self.changes {
+            let Some(m) = monitors
+                .iter()
+                .find(|m| m.device_name == change.device_name)
+            else {
+                continue;
+            };
+            let Some(requested) = &change.resolution else {
+                continue;
+            };
+            // Modes are listed in the current orientation's frame
+            let orientation = change.orientation.unwrap_or(m.current_orientation);
+            let rotates = orientation.is_portrait() != m.current_orientation.is_portrait();
+            let in_current_frame = if rotates {
+                requested.rotated()
+            } else {
+                requested.clone()
+            };
+            if let Some(mode) = in_current_frame.closest(&m.available_resolutions) {
+                change.resolution = Some(if rotates {
+                    mode.rotated()
+                } else {
+                    mode.clone()
+                });
+            }
+        }
+        self
+    }
+
+    /// Builds the transaction that restores the monitors touched by `self` to
+    /// the state they are in now.
+    pub fn inverse(&self, monitors: &[Monitor]) -> Transaction {
+        let mut inverse = Transaction::new();
+        for change in &self.changes {
+            if let Some(m) = monitors
+                .iter()
+                .find(|m| m.device_name == change.device_name)
+            {
+                if !m.enabled {
+                    inverse.set_enabled(&m.device_name, false);
+                    continue;
+                }
+                inverse
+                    .set_resolution(&m.device_name, m.current_resolution.clone())
+                    .set_orientation(&m.device_name, m.current_orientation);
+                if change.scale.is_some() {
+                    inverse.set_scale(&m.device_name, m.scale);
+                }
+                if change.position.is_some() || change.enabled == Some(false) {
+                    inverse.set_position(&m.device_name, m.position);
+                }
+                if change.enabled == Some(false) {
+                    inverse.set_enabled(&m.device_name, true);
+                }
+            }
+        }
+
+        // Changing the primary may shift every monitor to keep it at the origin
+        if self.changes.iter().any(|c| c.primary) {
+            for m in monitors.iter().filter(|m| m.enabled) {
+                inverse.set_position(&m.device_name, m.position);
+                if m.is_primary {
+                    inverse.set_primary(&m.device_name);
+                }
+            }
+        }
+        inverse
+    }
+
+    pub fn changes(&self) -> &[MonitorChange] {
+        &self.changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Whether any monitor is being moved, resized, rotated, rescaled,
+    /// attached or detached, which makes the layout worth checking before
+    /// applying.
+    pub fn reshapes_layout(&self) -> bool {
+        self.changes.iter().any(MonitorChange::reshapes)
+    }
+}
+
+/// Takes changes as they are, e.g. from a daemon client.
+impl From<Vec<MonitorChange>> for Transaction {
+    fn from(changes: Vec<MonitorChange>) -> Self {
+        Self { changes }
+    }
+}
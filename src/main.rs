@@ -1,12 +1,13 @@
 #![windows_subsystem = "windows"]
 
 mod cli;
+mod daemon;
 mod display;
 mod ui;
 mod utils;
 
 use clap::Parser;
-use log::error;
+use log::{debug, error};
 
 fn main() -> anyhow::Result<()> {
     // On Windows, attempt to attach to the parent process's console.
@@ -22,15 +23,33 @@ fn main() -> anyhow::Result<()> {
 
     let args = cli::Cli::parse();
 
+    // A running daemon owns the displays, unless a backend is asked for
+    let remote = match (&args.backend, &args.command) {
+        (None, Some(cli::Commands::Daemon)) | (Some(_), _) => None,
+        (None, _) => daemon::client::RemoteBackend::connect(),
+    };
+    let display = match remote {
+        Some(remote) => Ok(display::DisplayManager::from_backend(Box::new(remote))),
+        None => display::DisplayManager::new(args.backend, &args.backend_options()),
+    };
+    let display = match display {
+        Ok(display) => display,
+        Err(e) => {
+            error!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    debug!("Using {} display backend", display.backend_name());
+
     if args.command.is_some() {
         // CLI mode
-        if let Err(e) = cli::handle_cli(args) {
+        if let Err(e) = cli::handle_cli(args, &display) {
             error!("Error: {}", e);
             std::process::exit(1);
         }
     } else {
         // GUI mode
-        if let Err(e) = ui::run(args.debug) {
+        if let Err(e) = ui::run(args.debug, display) {
             error!("GUI Error: {}", e);
             std::process::exit(1);
         }
@@ -3,7 +3,8 @@ pub mod theme;
 pub mod views;
 pub mod widgets;
 
-use crate::display::{DisplayManager, Monitor};
+use crate::display::{layout, Changes, DisplayManager, Monitor, Transaction};
+use std::collections::HashMap;
 use crate::utils::config::{ConfigManager, MonitorSetting, Profile};
 use iced::border::Radius;
 use iced::widget::{button, column, container, row, text, text_input};
@@ -14,7 +15,7 @@ use self::theme::*;
 use crate::utils::config::AppConfig;
 use iced::Alignment;
 
-pub fn run(debug: bool) -> iced::Result {
+pub fn run(debug: bool, display: DisplayManager) -> iced::Result {
     iced::application(
         "Yarm - Yet Another Resolution Manager",
         YarmApp::update,
@@ -27,17 +28,18 @@ pub fn run(debug: bool) -> iced::Result {
         ..Default::default()
     })
     .subscription(YarmApp::subscription)
-    .run_with(move || YarmApp::new(debug))
+    .run_with(move || YarmApp::new(debug, display))
 }
 
 impl YarmApp {
-    fn new(debug: bool) -> (Self, Task<Message>) {
+    fn new(debug: bool, display: DisplayManager) -> (Self, Task<Message>) {
+        let task = Task::perform(load_data(display.clone()), Message::Loaded);
         (
             Self {
                 debug,
-                ..Self::default()
+                ..Self::with_display(display)
             },
-            Task::perform(load_data(), Message::Loaded),
+            task,
         )
     }
 
@@ -60,9 +62,14 @@ impl YarmApp {
             Subscription::none()
         };
 
+        // Backend events where there are any, polling every refresh_freq
+        // seconds otherwise
         let refresh_freq = self.config.general.refresh_freq;
-        let refresh_sub = if refresh_freq > 0 {
-             iced::time::every(std::time::Duration::from_secs(refresh_freq)).map(|_| Message::RefreshTick)
+        let refresh_sub = if self.display.capabilities().change_events || refresh_freq > 0 {
+            Subscription::run_with_id(
+                ("display-changes", refresh_freq),
+                display_changes(self.display.clone(), refresh_freq),
+            )
         } else {
             Subscription::none()
         };
@@ -70,6 +77,93 @@ impl YarmApp {
         Subscription::batch(vec![debug_sub, timer_sub, refresh_sub])
     }
 
+    /// Collects the staged values that differ from the current state.
+    fn staged_transaction(&self) -> Transaction {
+        let mut transaction = Transaction::new();
+        for m in &self.monitors {
+            let enabled = self.staging_enabled.get(&m.id).copied().unwrap_or(m.enabled);
+            if enabled != m.enabled {
+                transaction.set_enabled(&m.device_name, enabled);
+            }
+            if !enabled {
+                continue;
+            }
+            if let Some(res) = self.staging_resolutions.get(&m.id) {
+                if *res != m.current_resolution {
+                    transaction.set_resolution(&m.device_name, res.clone());
+                }
+            }
+            if let Some(orient) = self.staging_orientations.get(&m.id) {
+                if *orient != m.current_orientation {
+                    transaction.set_orientation(&m.device_name, *orient);
+                }
+            }
+            if let Some(position) = self.staging_positions.get(&m.id) {
+                if *position != m.position {
+                    transaction.set_position(&m.device_name, *position);
+                }
+            }
+            if let Some(scale) = self.staging_scales.get(&m.id) {
+                if *scale != m.scale {
+                    transaction.set_scale(&m.device_name, *scale);
+                }
+            }
+            if self.staging_primary.as_ref() == Some(&m.id) && !m.is_primary && self.display.capabilities().primary {
+                transaction.set_primary(&m.device_name);
+            }
+        }
+        transaction
+    }
+
+    /// The monitor each staged mirror copies, by monitor id.
+    fn staged_mirrors(&self) -> HashMap<String, String> {
+        let transaction = self.staged_transaction();
+        layout::mirrors(&layout::planned(&self.monitors, transaction.changes()))
+            .into_iter()
+            .map(|(mirror, source)| (mirror.id.clone(), source.id.clone()))
+            .collect()
+    }
+
+    /// Stages `monitors` at the best mode they share and the position of
+    /// the first one, false if they have no mode in common.
+    fn stage_mirror(&mut self, monitors: &[&Monitor]) -> bool {
+        let Some(modes) = layout::common_mode(monitors) else {
+            return false;
+        };
+        let position = self.staging_positions.get(&monitors[0].id).copied().unwrap_or(monitors[0].position);
+        for (m, mode) in monitors.iter().zip(modes) {
+            self.staging_resolutions.insert(m.id.clone(), mode);
+            self.staging_positions.insert(m.id.clone(), position);
+        }
+        true
+    }
+
+    /// Asks the backend whether `transaction` would be accepted and remembers
+    /// the rejected monitors so their cards can warn about it.
+    ///
+    /// If the check itself fails the transaction is let through, applying it
+    /// will report the actual problem.
+    fn check_staged(&mut self, transaction: &Transaction) -> bool {
+        self.rejected.clear();
+        let verdicts = match self.display.validate(transaction) {
+            Ok(verdicts) => verdicts,
+            Err(_) => return true,
+        };
+        for verdict in verdicts {
+            let Some(reason) = verdict.rejection else {
+                continue;
+            };
+            if let Some(m) = self
+                .monitors
+                .iter()
+                .find(|m| m.device_name == verdict.device_name)
+            {
+                self.rejected.insert(m.id.clone(), reason);
+            }
+        }
+        self.rejected.is_empty()
+    }
+
     fn update(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::Loaded(Ok((monitors, config))) => {
@@ -79,12 +173,20 @@ impl YarmApp {
                 // Initialize staging with current system state
                 self.staging_resolutions.clear();
                 self.staging_orientations.clear();
+                self.staging_positions.clear();
+                self.staging_scales.clear();
+                self.staging_enabled.clear();
+                self.rejected.clear();
                 for m in &self.monitors {
                     self.staging_resolutions
                         .insert(m.id.clone(), m.current_resolution.clone());
                     self.staging_orientations
                         .insert(m.id.clone(), m.current_orientation);
+                    self.staging_positions.insert(m.id.clone(), m.position);
+                    self.staging_scales.insert(m.id.clone(), m.scale);
+                    self.staging_enabled.insert(m.id.clone(), m.enabled);
                 }
+                self.staging_primary = self.monitors.iter().find(|m| m.is_primary).map(|m| m.id.clone());
 
                 self.status_message = "Ready".to_string();
                 Task::none()
@@ -98,47 +200,85 @@ impl YarmApp {
                 Task::perform(async {}, |_| Message::ApplyToSystem)
             }
             Message::OrientationChanged(id, orient) => {
-                self.staging_orientations.insert(id, orient);
+                // Staged resolutions follow the staged orientation
+                let previous = self.staging_orientations.insert(id.clone(), orient);
+                if previous.is_some_and(|p| p.is_portrait() != orient.is_portrait()) {
+                    if let Some(res) = self.staging_resolutions.get_mut(&id) {
+                        *res = res.rotated();
+                    }
+                }
+                Task::perform(async {}, |_| Message::ApplyToSystem)
+            }
+            Message::ScaleChanged(id, scale) => {
+                self.staging_scales.insert(id, scale);
+                Task::perform(async {}, |_| Message::ApplyToSystem)
+            }
+            Message::EnabledChanged(id, enabled) => {
+                self.staging_enabled.insert(id, enabled);
+                Task::perform(async {}, |_| Message::ApplyToSystem)
+            }
+            Message::MirrorChanged(id, Some(source)) => {
+                // Join the source's existing mirrors rather than break them up
+                let mirrors = self.staged_mirrors();
+                let monitors = self.monitors.clone();
+                let group: Vec<&Monitor> = monitors
+                    .iter()
+                    .filter(|m| m.id == source || m.id == id || mirrors.get(&m.id) == Some(&source))
+                    .collect();
+                if !self.stage_mirror(&group) {
+                    self.status_message = "Error: these monitors have no resolution in common".to_string();
+                    return Task::none();
+                }
+                Task::perform(async {}, |_| Message::ApplyToSystem)
+            }
+            Message::MirrorChanged(id, None) => {
+                let transaction = self.staged_transaction();
+                let rects = layout::planned(&self.monitors, transaction.changes());
+                if let Some(m) = self.monitors.iter().find(|m| m.id == id) {
+                    let position = layout::beside(&rects, &m.device_name);
+                    self.staging_positions.insert(id, position);
+                }
+                Task::perform(async {}, |_| Message::ApplyToSystem)
+            }
+            Message::MakePrimary(id) => {
+                self.staging_primary = Some(id);
                 Task::perform(async {}, |_| Message::ApplyToSystem)
             }
             Message::ApplyToSystem => {
-                // Backup current resolutions before applying
-                self.backup_resolutions.clear();
-                for m in &self.monitors {
-                    self.backup_resolutions.insert(m.id.clone(), m.current_resolution.clone());
+                let transaction = self.staged_transaction();
+                if transaction.is_empty() {
+                    self.rejected.clear();
+                    return Task::none();
                 }
-
-                let mut errors = Vec::new();
-
-                // Apply Resolutions
-                for (id, res) in &self.staging_resolutions {
-                    if let Some(monitor) = self.monitors.iter().find(|m| &m.id == id) {
-                        if let Err(e) = DisplayManager::set_resolution(&monitor.device_name, res) {
-                            errors.push(format!("Res {}: {}", monitor.name, e));
-                        }
-                    }
+                if !self.check_staged(&transaction) {
+                    self.status_message =
+                        "Error: some settings would be rejected, nothing was applied".to_string();
+                    return Task::none();
                 }
 
-                // Apply Orientations
-                for (id, orient) in &self.staging_orientations {
-                    if let Some(monitor) = self.monitors.iter().find(|m| &m.id == id) {
-                        if let Err(e) =
-                            DisplayManager::set_orientation(&monitor.device_name, *orient)
-                        {
-                            errors.push(format!("Orient {}: {}", monitor.name, e));
-                        }
-                    }
-                }
+                // Backup current state of the touched monitors before applying
+                let backup = transaction.inverse(&self.monitors);
 
-                if errors.is_empty() {
-                    self.status_message = "Applied successfully".to_string();
-                    // Start confirmation timer
-                    self.waiting_for_confirmation = true;
-                    self.confirmation_timer = self.config.general.reset_timeout;
+                // A daemon keeps the revert timer running if the window is closed
+                let result = if self.display.capabilities().revert_timer {
+                    let timeout = std::time::Duration::from_secs(self.config.general.reset_timeout.into());
+                    self.display.apply_with_revert(&transaction, timeout)
                 } else {
-                    self.status_message = format!("Errors: {}", errors.join("; "));
+                    self.display.apply(&transaction)
+                };
+                match result {
+                    Ok(()) => {
+                        self.backup = Some(backup);
+                        self.status_message = "Applied successfully".to_string();
+                        // Start confirmation timer
+                        self.waiting_for_confirmation = true;
+                        self.confirmation_timer = self.config.general.reset_timeout;
+                    }
+                    Err(e) => {
+                        self.status_message = format!("Error applying changes: {}", e);
+                    }
                 }
-                Task::perform(load_data(), Message::Loaded)
+                Task::perform(load_data(self.display.clone()), Message::Loaded)
             }
             Message::Tick => {
                 if self.waiting_for_confirmation {
@@ -152,35 +292,39 @@ impl YarmApp {
             }
             Message::ConfirmResolution => {
                 self.waiting_for_confirmation = false;
-                self.status_message = "Resolution confirmed".to_string();
+                self.status_message = match self.display.settle_revert(true) {
+                    Ok(()) => "Resolution confirmed".to_string(),
+                    Err(e) => format!("Confirm Error: {}", e),
+                };
                 Task::none()
             }
             Message::RevertResolution => {
                 self.waiting_for_confirmation = false;
-                let mut errors = Vec::new();
-                for (id, res) in &self.backup_resolutions {
-                    if let Some(monitor) = self.monitors.iter().find(|m| &m.id == id) {
-                         // Update staging to match revert
-                        self.staging_resolutions.insert(id.clone(), res.clone());
-                        if let Err(e) = DisplayManager::set_resolution(&monitor.device_name, res) {
-                            errors.push(format!("Revert {}: {}", monitor.name, e));
-                        }
+                if let Some(backup) = self.backup.take() {
+                    let result = if self.display.capabilities().revert_timer {
+                        self.display.settle_revert(false)
+                    } else {
+                        self.display.apply(&backup)
+                    };
+                    match result {
+                        Ok(()) => self.status_message = "Reverted changes".to_string(),
+                        Err(e) => self.status_message = format!("Revert Error: {}", e),
                     }
                 }
-                 if errors.is_empty() {
-                    self.status_message = "Reverted changes".to_string();
-                } else {
-                    self.status_message = format!("Revert Errors: {}", errors.join("; "));
-                }
-                Task::perform(load_data(), Message::Loaded)
+                Task::perform(load_data(self.display.clone()), Message::Loaded)
+            }
+            Message::RefreshTick => {
+                Task::perform(load_monitors(self.display.clone()), Message::AutoRefreshed)
             }
-            Message::RefreshTick => Task::perform(load_monitors(), Message::AutoRefreshed),
             Message::AutoRefreshed(Ok(new_monitors)) => {
                 self.monitors = new_monitors;
 
                 // Sync Staging: Remove stale
                 self.staging_resolutions.retain(|id, _| self.monitors.iter().any(|m| &m.id == id));
                 self.staging_orientations.retain(|id, _| self.monitors.iter().any(|m| &m.id == id));
+                self.staging_positions.retain(|id, _| self.monitors.iter().any(|m| &m.id == id));
+                self.staging_scales.retain(|id, _| self.monitors.iter().any(|m| &m.id == id));
+                self.staging_enabled.retain(|id, _| self.monitors.iter().any(|m| &m.id == id));
 
                 // Sync Staging: Add new (preserve existing user selections)
                 for m in &self.monitors {
@@ -190,6 +334,18 @@ impl YarmApp {
                     self.staging_orientations
                         .entry(m.id.clone())
                         .or_insert(m.current_orientation);
+                    self.staging_positions
+                        .entry(m.id.clone())
+                        .or_insert(m.position);
+                    self.staging_scales
+                        .entry(m.id.clone())
+                        .or_insert(m.scale);
+                    self.staging_enabled
+                        .entry(m.id.clone())
+                        .or_insert(m.enabled);
+                }
+                if !self.staging_primary.as_ref().is_some_and(|id| self.monitors.iter().any(|m| &m.id == id)) {
+                    self.staging_primary = self.monitors.iter().find(|m| m.is_primary).map(|m| m.id.clone());
                 }
                 Task::none()
             }
@@ -239,23 +395,35 @@ impl YarmApp {
                     return Task::none();
                 }
 
+                let mirrors = self.staged_mirrors();
+                let can_scale = self.display.capabilities().scale;
                 let mut settings = Vec::new();
                 for (id, res) in &self.staging_resolutions {
+                    let identity = self
+                        .monitors
+                        .iter()
+                        .find(|m| &m.id == id)
+                        .map(|m| m.identity.clone());
                     settings.push(MonitorSetting {
                         monitor_id: id.clone(),
+                        identity,
                         resolution: res.clone(),
+                        orientation: self.staging_orientations.get(id).copied(),
+                        position: self.staging_positions.get(id).copied(),
+                        scale: self.staging_scales.get(id).copied().filter(|_| can_scale),
+                        primary: self.staging_primary.as_ref() == Some(id),
+                        enabled: self.staging_enabled.get(id).copied().unwrap_or(true),
+                        mirror_of: mirrors.get(id).cloned(),
                     });
                 }
 
                 let new_profile = Profile {
                     name: self.new_profile_name.clone(),
+                    matching: None,
                     settings,
                 };
 
-                self.config
-                    .profiles
-                    .retain(|p| p.name != self.new_profile_name);
-                self.config.profiles.push(new_profile);
+                self.config.put_profile(new_profile);
 
                 if let Err(e) = ConfigManager::save(&self.config) {
                     self.status_message = format!("Failed to save config: {}", e);
@@ -267,13 +435,45 @@ impl YarmApp {
             }
             Message::LoadProfile(name) => {
                 if let Some(profile) = self.config.profiles.iter().find(|p| p.name == name) {
-                    for setting in &profile.settings {
-                        if self.monitors.iter().any(|m| m.id == setting.monitor_id) {
+                    for (setting, monitor) in profile.resolve(&self.monitors) {
+                        if let Some(monitor) = monitor {
                             self.staging_resolutions
-                                .insert(setting.monitor_id.clone(), setting.resolution.clone());
+                                .insert(monitor.id.clone(), setting.resolution.clone());
+                            if let Some(orientation) = setting.orientation {
+                                self.staging_orientations
+                                    .insert(monitor.id.clone(), orientation);
+                            }
+                            if let Some(position) = setting.position {
+                                self.staging_positions.insert(monitor.id.clone(), position);
+                            }
+                            if let Some(scale) = setting.scale.filter(|_| self.display.capabilities().scale) {
+                                self.staging_scales.insert(monitor.id.clone(), scale);
+                            }
+                            if setting.primary {
+                                self.staging_primary = Some(monitor.id.clone());
+                            }
+                            self.staging_enabled.insert(monitor.id.clone(), setting.enabled);
+                        }
+                    }
+                    let monitors = self.monitors.clone();
+                    for group in profile.clone().mirror_groups(&monitors) {
+                        let group: Vec<&Monitor> = group.into_iter().map(|(_, m)| m).collect();
+                        self.stage_mirror(&group);
+                    }
+                    // Stage the modes the monitors actually offer, e.g. 59.94 Hz for 60
+                    let mut transaction = self.staged_transaction();
+                    transaction.match_modes(&self.monitors);
+                    for change in transaction.changes() {
+                        let monitor = self.monitors.iter().find(|m| m.device_name == change.device_name);
+                        if let (Some(monitor), Some(res)) = (monitor, &change.resolution) {
+                            self.staging_resolutions.insert(monitor.id.clone(), res.clone());
                         }
                     }
                     self.status_message = format!("Loaded profile '{}' (click Apply to set)", name);
+                    if !self.check_staged(&transaction) {
+                        self.status_message =
+                            format!("Profile '{}' has settings this setup would reject", name);
+                    }
                 }
                 Task::none()
             }
@@ -296,6 +496,10 @@ impl YarmApp {
             &self.monitors,
             &self.staging_resolutions,
             &self.staging_orientations,
+            self.display.capabilities().scale.then_some(&self.staging_scales),
+            self.display.capabilities().primary,
+            self.staged_mirrors(),
+            &self.rejected,
             &self.status_message,
         );
 
@@ -313,11 +517,14 @@ impl YarmApp {
             let monitor_summary = self
                 .staging_resolutions
                 .iter()
-                .fold(String::new(), |acc, (_, res)| {
-                    format!(
-                        "{}• {}\n",
-                        acc, res
-                    )
+                .fold(String::new(), |acc, (id, res)| {
+                    if self.staging_enabled.get(id) == Some(&false) {
+                        return format!("{}• disabled\n", acc);
+                    }
+                    match self.staging_orientations.get(id) {
+                        Some(orient) => format!("{}• {}, {}\n", acc, res, orient),
+                        None => format!("{}• {}\n", acc, res),
+                    }
                 });
 
             let dialog_content = column![
@@ -568,12 +775,34 @@ impl YarmApp {
     }
 }
 
-async fn load_data() -> Result<(Vec<Monitor>, AppConfig), String> {
-    let monitors = DisplayManager::enumerate_monitors().map_err(|e| e.to_string())?;
+async fn load_data(display: DisplayManager) -> Result<(Vec<Monitor>, AppConfig), String> {
+    let monitors = display.enumerate_monitors().map_err(|e| e.to_string())?;
     let config = ConfigManager::load().map_err(|e| e.to_string())?;
     Ok((monitors, config))
 }
 
-async fn load_monitors() -> Result<Vec<Monitor>, String> {
-    DisplayManager::enumerate_monitors().map_err(|e| e.to_string())
+async fn load_monitors(display: DisplayManager) -> Result<Vec<Monitor>, String> {
+    display.enumerate_monitors().map_err(|e| e.to_string())
+}
+
+/// A `RefreshTick` for every display change, `Changes` blocks so it is
+/// waited on in its own thread.
+fn display_changes(display: DisplayManager, refresh_freq: u64) -> impl iced::futures::Stream<Item = Message> {
+    use iced::futures::{SinkExt, StreamExt};
+
+    iced::stream::channel(1, move |mut output| async move {
+        let (sender, mut receiver) = iced::futures::channel::mpsc::unbounded();
+        std::thread::spawn(move || {
+            let poll = (refresh_freq > 0).then(|| std::time::Duration::from_secs(refresh_freq));
+            let mut changes = Changes::watch(&display, poll);
+            loop {
+                if changes.wait(std::time::Duration::from_secs(1)) && sender.unbounded_send(()).is_err() {
+                    break;
+                }
+            }
+        });
+        while receiver.next().await.is_some() {
+            let _ = output.send(Message::RefreshTick).await;
+        }
+    })
 }
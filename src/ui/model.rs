@@ -1,4 +1,4 @@
-use crate::display::{Monitor, Orientation, Resolution};
+use crate::display::{DisplayManager, Monitor, Orientation, Resolution, Transaction};
 use crate::utils::config::AppConfig;
 use std::collections::HashMap;
 
@@ -7,6 +7,10 @@ pub enum Message {
     Loaded(Result<(Vec<Monitor>, AppConfig), String>),
     ResolutionChanged(String, Resolution),
     OrientationChanged(String, Orientation),
+    ScaleChanged(String, f64),
+    MakePrimary(String),
+    EnabledChanged(String, bool),
+    MirrorChanged(String, Option<String>),
     ApplyToSystem,
     OpenSaveDialog,
     CloseSaveDialog,
@@ -30,10 +34,17 @@ pub enum Message {
 }
 
 pub struct YarmApp {
+    pub display: DisplayManager,
     pub monitors: Vec<Monitor>,
     pub config: AppConfig,
     pub staging_resolutions: HashMap<String, Resolution>,
     pub staging_orientations: HashMap<String, Orientation>,
+    pub staging_positions: HashMap<String, (i32, i32)>,
+    pub staging_scales: HashMap<String, f64>,
+    pub staging_primary: Option<String>,
+    pub staging_enabled: HashMap<String, bool>,
+    // Staged settings the backend would refuse, by monitor id
+    pub rejected: HashMap<String, String>,
     pub new_profile_name: String,
     pub status_message: String,
     pub show_save_dialog: bool,
@@ -41,26 +52,32 @@ pub struct YarmApp {
     // Confirmation state
     pub waiting_for_confirmation: bool,
     pub confirmation_timer: u8,
-    pub backup_resolutions: HashMap<String, Resolution>,
+    pub backup: Option<Transaction>,
     pub show_settings: bool,
     // Profile Deletion
     pub profile_to_delete: Option<String>,
 }
 
-impl Default for YarmApp {
-    fn default() -> Self {
+impl YarmApp {
+    pub fn with_display(display: DisplayManager) -> Self {
         Self {
+            display,
             monitors: Vec::new(),
             config: AppConfig::default(),
             staging_resolutions: HashMap::new(),
             staging_orientations: HashMap::new(),
+            staging_positions: HashMap::new(),
+            staging_scales: HashMap::new(),
+            staging_primary: None,
+            staging_enabled: HashMap::new(),
+            rejected: HashMap::new(),
             new_profile_name: String::new(),
             status_message: "Loading...".to_string(),
             show_save_dialog: false,
             debug: false,
             waiting_for_confirmation: false,
             confirmation_timer: 0,
-            backup_resolutions: HashMap::new(),
+            backup: None,
             show_settings: false,
             profile_to_delete: None,
         }
@@ -1,11 +1,12 @@
-use crate::display::{Monitor, Orientation, Resolution};
+use crate::display::{Monitor, Orientation, RefreshRate, Resolution, ScalingMode};
 use crate::ui::model::Message;
 use crate::ui::theme::{
-    card_style, pick_list_style, settings_icon_button_style, COL_PRIMARY, COL_TEXT_DARK, COL_TEXT_MUTED,
+    card_style, compact_neutral_button_style, pick_list_style, settings_icon_button_style, COL_PRIMARY,
+    COL_TEXT_DARK, COL_TEXT_MUTED,
 };
 use crate::ui::widgets::orientation_switcher::OrientationSwitcher;
 use iced::border::Radius;
-use iced::widget::{button, column, container, pick_list, row, scrollable, svg, text};
+use iced::widget::{button, column, container, horizontal_space, pick_list, row, scrollable, svg, text};
 use iced::{Alignment, Background, Color, Element, Length};
 use std::collections::HashMap;
 
@@ -21,13 +22,97 @@ impl std::fmt::Display for Dimension {
     }
 }
 
+// A refresh rate as offered in the frequency list, interlaced modes marked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rate {
+    refresh: RefreshRate,
+    interlaced: bool,
+}
+
+impl std::fmt::Display for Rate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.refresh, if self.interlaced { "i" } else { "" })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Scale(f64);
+
+impl std::fmt::Display for Scale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}%", (self.0 * 100.0).round())
+    }
+}
+
+// Common desktop scales, the current one is added if it is something else
+const SCALES: [f64; 7] = [1.0, 1.25, 1.5, 1.75, 2.0, 2.5, 3.0];
+
+// A monitor to mirror, `None` for extending the desktop
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct MirrorChoice {
+    source: Option<String>,
+    label: String,
+}
+
+impl std::fmt::Display for MirrorChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.label)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn view<'a>(
     monitors: &'a [Monitor],
     staging_resolutions: &'a HashMap<String, Resolution>,
     staging_orientations: &'a HashMap<String, Orientation>,
+    // None if the backend cannot scale monitors
+    staging_scales: Option<&'a HashMap<String, f64>>,
+    // False if the backend has no primary monitor
+    can_make_primary: bool,
+    mirrors: HashMap<String, String>,
+    rejected: &'a HashMap<String, String>,
     status_message: &'a str,
 ) -> Element<'a, Message> {
     let monitors_list = monitors.iter().fold(column![].spacing(20), |col, monitor| {
+        // Connected but detached, greyed out with nothing to adjust
+        if !monitor.enabled {
+            return col.push(
+                container(
+                    column![
+                        row![
+                            column![
+                                text(&monitor.name)
+                                    .size(18)
+                                    .font(iced::Font {
+                                        weight: iced::font::Weight::Bold,
+                                        ..Default::default()
+                                    })
+                                    .color(COL_TEXT_MUTED),
+                                text(format!("ID: {} • Disabled", monitor.id))
+                                    .size(12)
+                                    .color(COL_TEXT_MUTED),
+                            ]
+                            .spacing(4)
+                            .width(Length::Fill),
+                            button(text("Enable").size(12))
+                                .on_press(Message::EnabledChanged(monitor.id.clone(), true))
+                                .padding([4, 10])
+                                .style(compact_neutral_button_style)
+                        ]
+                        .align_y(Alignment::Center)
+                    ]
+                    .push_maybe(rejected.get(&monitor.id).map(|reason| {
+                        text(format!("⚠ {}", reason))
+                            .size(12)
+                            .color(Color::from_rgb(0.9, 0.4, 0.4))
+                    }))
+                    .spacing(8),
+                )
+                .padding(20)
+                .style(card_style),
+            );
+        }
+
         let current_res_staging = staging_resolutions
             .get(&monitor.id)
             .unwrap_or(&monitor.current_resolution);
@@ -45,7 +130,7 @@ pub fn view<'a>(
                 height: r.height,
             })
             .collect();
-        available_dims.sort_by(|a, b| (b.width * b.height).cmp(&(a.width * a.height))); // Sort by area desc
+        available_dims.sort_by_key(|d| std::cmp::Reverse(d.width * d.height)); // Sort by area desc
         available_dims.dedup();
 
         let current_dim = Dimension {
@@ -56,18 +141,35 @@ pub fn view<'a>(
         let selected_dim = available_dims.iter().find(|d| **d == current_dim).cloned();
 
         // 2. Prepare Frequencies for current dimension
-        let mut available_freqs: Vec<u32> = monitor
+        let mut available_freqs: Vec<Rate> = monitor
             .available_resolutions
             .iter()
             .filter(|r| r.width == current_dim.width && r.height == current_dim.height)
-            .map(|r| r.frequency)
+            .map(|r| Rate { refresh: r.refresh, interlaced: r.interlaced })
             .collect();
-        available_freqs.sort_by(|a, b| b.cmp(a)); // Descending
+        available_freqs.sort_by(|a, b| b.refresh.cmp(&a.refresh).then(a.interlaced.cmp(&b.interlaced))); // Descending, progressive first
         available_freqs.dedup();
 
-        let current_freq = current_res_staging.frequency;
+        let current_freq = Rate {
+            refresh: current_res_staging.refresh,
+            interlaced: current_res_staging.interlaced,
+        };
         let selected_freq = available_freqs.iter().find(|f| **f == current_freq).cloned();
 
+        // 3. Prepare Scaling modes for current dimension and frequency
+        let current_scaling = current_res_staging.scaling;
+        let mut available_scalings: Vec<ScalingMode> = Vec::new();
+        for r in monitor.available_resolutions.iter().filter(|r| {
+            r.width == current_dim.width
+                && r.height == current_dim.height
+                && r.refresh == current_freq.refresh
+                && r.interlaced == current_freq.interlaced
+        }) {
+            if !available_scalings.contains(&r.scaling) {
+                available_scalings.push(r.scaling);
+            }
+        }
+
         // Controls
         let dim_pick_list = pick_list(
             available_dims,
@@ -80,7 +182,8 @@ pub fn view<'a>(
                     // Also need to handle bits_per_pixel. We'll pick the "best" resolution object matching dim and max freq.
                     let best_match = available_resolutions.iter()
                         .filter(|r| r.width == new_dim.width && r.height == new_dim.height)
-                        .max_by_key(|r| (r.frequency, r.bits_per_pixel)) // Prefer high freq, then high depth
+                        // Keep the scaling mode, prefer progressive, then high freq, then high depth
+                        .max_by_key(|r| (r.scaling == current_scaling, !r.interlaced, r.refresh, r.bits_per_pixel))
                         .cloned();
                     
                     if let Some(res) = best_match {
@@ -103,10 +206,11 @@ pub fn view<'a>(
                 let id = monitor.id.clone();
                 let available_resolutions = monitor.available_resolutions.clone();
                 move |new_freq| {
-                    // Find resolution matching current dim and new freq. Prefer highest bit depth.
+                    // Find resolution matching current dim and new freq. Keep the scaling mode, prefer highest bit depth.
                     let best_match = available_resolutions.iter()
-                        .filter(|r| r.width == current_dim.width && r.height == current_dim.height && r.frequency == new_freq)
-                        .max_by_key(|r| r.bits_per_pixel)
+                        .filter(|r| r.width == current_dim.width && r.height == current_dim.height)
+                        .filter(|r| r.refresh == new_freq.refresh && r.interlaced == new_freq.interlaced)
+                        .max_by_key(|r| (r.scaling == current_scaling, r.bits_per_pixel))
                         .cloned();
 
                     if let Some(res) = best_match {
@@ -121,12 +225,92 @@ pub fn view<'a>(
         .padding(12)
         .style(pick_list_style);
 
+        // Only worth showing when the driver offers a choice
+        let scaling_pick_list = (available_scalings.len() > 1).then(|| {
+            let id = monitor.id.clone();
+            let available_resolutions = monitor.available_resolutions.clone();
+            pick_list(available_scalings, Some(current_scaling), move |new_scaling| {
+                let best_match = available_resolutions.iter()
+                    .filter(|r| r.width == current_dim.width && r.height == current_dim.height)
+                    .filter(|r| r.refresh == current_freq.refresh && r.interlaced == current_freq.interlaced)
+                    .filter(|r| r.scaling == new_scaling)
+                    .max_by_key(|r| r.bits_per_pixel)
+                    .cloned();
+
+                match best_match {
+                    Some(res) => Message::ResolutionChanged(id.clone(), res),
+                    None => Message::RefreshTick,
+                }
+            })
+            .width(Length::Fill)
+            .padding(12)
+            .style(pick_list_style)
+        });
+
         let orient_control = OrientationSwitcher::new(*current_orient_staging, {
             let id = monitor.id.clone();
             move |orient| Message::OrientationChanged(id.clone(), orient)
         })
         .view();
 
+        let scale_pick_list = staging_scales.map(|staging_scales| {
+            let current = staging_scales.get(&monitor.id).copied().unwrap_or(monitor.scale);
+            let mut scales: Vec<Scale> = SCALES.iter().copied().map(Scale).collect();
+            if !SCALES.contains(&current) {
+                scales.push(Scale(current));
+                scales.sort_by(|a, b| a.0.total_cmp(&b.0));
+            }
+            let id = monitor.id.clone();
+            pick_list(scales, Some(Scale(current)), move |scale| {
+                Message::ScaleChanged(id.clone(), scale.0)
+            })
+            .width(Length::Fill)
+            .padding(12)
+            .style(pick_list_style)
+        });
+
+        // Any enabled monitor not mirroring another one can be mirrored
+        let mut mirror_choices = vec![MirrorChoice { source: None, label: "Off".to_string() }];
+        mirror_choices.extend(
+            monitors
+                .iter()
+                .filter(|m| m.enabled && m.id != monitor.id && !mirrors.contains_key(&m.id))
+                .map(|m| MirrorChoice { source: Some(m.id.clone()), label: m.name.clone() }),
+        );
+        let mirrored = mirrors.get(&monitor.id);
+        let selected_mirror = mirror_choices.iter().find(|c| c.source.as_ref() == mirrored).cloned();
+        let mirror_pick_list = (mirror_choices.len() > 1).then(|| {
+            let id = monitor.id.clone();
+            pick_list(mirror_choices, selected_mirror, move |choice| {
+                Message::MirrorChanged(id.clone(), choice.source)
+            })
+            .width(Length::Fill)
+            .padding(12)
+            .style(pick_list_style)
+        });
+
+        let primary_badge: Element<'a, Message> = if monitor.is_primary {
+            container(text("Primary").size(10).color(Color::WHITE))
+                .padding([2, 6])
+                .style(|_theme| container::Style {
+                    background: Some(Background::Color(COL_PRIMARY)),
+                    border: iced::Border {
+                        radius: Radius::from(12.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .into()
+        } else if !can_make_primary {
+            text("").size(10).into()
+        } else {
+            button(text("Make primary").size(10))
+                .on_press(Message::MakePrimary(monitor.id.clone()))
+                .padding([2, 8])
+                .style(compact_neutral_button_style)
+                .into()
+        };
+
         col.push(
             container(
                 column![
@@ -140,36 +324,44 @@ pub fn view<'a>(
                                     ..Default::default()
                                 })
                                 .color(COL_TEXT_DARK),
-                            if monitor.is_primary {
-                                container(text("Primary").size(10).color(Color::WHITE))
-                                    .padding([2, 6])
-                                    .style(|_theme| container::Style {
-                                        background: Some(Background::Color(COL_PRIMARY)),
-                                        border: iced::Border {
-                                            radius: Radius::from(12.0),
-                                            ..Default::default()
-                                        },
-                                        ..Default::default()
-                                    })
-                            } else {
-                                container(text("")).width(0)
-                            }
+                            primary_badge,
+                            horizontal_space(),
+                            button(text("Disable").size(10))
+                                .on_press(Message::EnabledChanged(monitor.id.clone(), false))
+                                .padding([2, 8])
+                                .style(compact_neutral_button_style)
                         ]
                         .spacing(8)
                         .align_y(Alignment::Center),
                         // Line 1: Specs
-                        text(format!(
-                            "{}Hz • {}bit",
-                            monitor.current_resolution.frequency,
-                            monitor.current_resolution.bits_per_pixel
-                        ))
+                        text(match monitor.native_resolution() {
+                            Some(native) => format!(
+                                "{}Hz • {}bit • Native {}x{}",
+                                monitor.current_resolution.refresh,
+                                monitor.current_resolution.bits_per_pixel,
+                                native.width,
+                                native.height
+                            ),
+                            None => format!(
+                                "{}Hz • {}bit",
+                                monitor.current_resolution.refresh,
+                                monitor.current_resolution.bits_per_pixel
+                            ),
+                        })
                         .size(12)
                         .color(COL_TEXT_MUTED),
-                        // Line 2: ID & Pos
-                        text(format!(
-                            "ID: {} • Pos: ({}, {})",
-                            monitor.id, monitor.position.0, monitor.position.1
-                        ))
+                        // Line 2: ID, Pos & Scale
+                        text(if monitor.scale != 1.0 {
+                            format!(
+                                "ID: {} • Pos: ({}, {}) • Scale {}",
+                                monitor.id, monitor.position.0, monitor.position.1, Scale(monitor.scale)
+                            )
+                        } else {
+                            format!(
+                                "ID: {} • Pos: ({}, {})",
+                                monitor.id, monitor.position.0, monitor.position.1
+                            )
+                        })
                         .size(12)
                         .color(COL_TEXT_MUTED),
                     ]
@@ -182,9 +374,21 @@ pub fn view<'a>(
                             dim_pick_list,
                             freq_pick_list
                         ].spacing(10),
-                        text("Orientation").size(12).color(COL_TEXT_MUTED),
-                        orient_control
                     ]
+                    .push_maybe(scaling_pick_list.is_some().then(|| text("Scaling mode").size(12).color(COL_TEXT_MUTED)))
+                    .push_maybe(scaling_pick_list)
+                    .push(text("Orientation").size(12).color(COL_TEXT_MUTED))
+                    .push(orient_control)
+                    .push_maybe(scale_pick_list.is_some().then(|| text("Scale").size(12).color(COL_TEXT_MUTED)))
+                    .push_maybe(scale_pick_list)
+                    .push_maybe(mirror_pick_list.is_some().then(|| text("Mirror").size(12).color(COL_TEXT_MUTED)))
+                    .push_maybe(mirror_pick_list)
+                    // Staged settings the backend refused in a dry run
+                    .push_maybe(rejected.get(&monitor.id).map(|reason| {
+                        text(format!("⚠ {}", reason))
+                            .size(12)
+                            .color(Color::from_rgb(0.9, 0.4, 0.4))
+                    }))
                     .spacing(8)
                 ]
                 .spacing(15),
@@ -230,7 +434,7 @@ pub fn view<'a>(
             .padding(6)
             .width(Length::Fixed(32.0))
             .height(Length::Fixed(32.0)),
-            iced::widget::horizontal_space(),
+            horizontal_space(),
             status_indicator
         ]
         .spacing(10)
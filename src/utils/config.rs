@@ -1,6 +1,8 @@
-use crate::display::Resolution;
+use crate::display::identity::{self, MonitorIdentity};
+use crate::display::{layout, DisplayManager, Monitor, Orientation, Resolution, Transaction};
 use anyhow::{anyhow, Result};
 use directories::ProjectDirs;
+use log::warn;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
@@ -11,6 +13,9 @@ pub struct AppConfig {
     pub general: GeneralConfig,
     #[serde(default)]
     pub profiles: Vec<Profile>,
+    /// Profiles `yarm watch` applies while matching programs run
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,6 +24,13 @@ pub struct GeneralConfig {
     pub reset_timeout: u8,
     #[serde(default = "default_refresh_freq")]
     pub refresh_freq: u64,
+    /// Seconds between two looks at the process list in `yarm watch`
+    #[serde(default = "default_watch_interval")]
+    pub watch_interval: u64,
+    /// Apply the profile matching the monitors when they are plugged or
+    /// unplugged, rather than only telling about it
+    #[serde(default = "default_enabled")]
+    pub auto_apply: bool,
 }
 
 impl Default for GeneralConfig {
@@ -26,6 +38,8 @@ impl Default for GeneralConfig {
         Self {
             reset_timeout: default_reset_timeout(),
             refresh_freq: default_refresh_freq(),
+            watch_interval: default_watch_interval(),
+            auto_apply: true,
         }
     }
 }
@@ -38,16 +52,363 @@ fn default_refresh_freq() -> u64 {
     1
 }
 
+fn default_watch_interval() -> u64 {
+    2
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Profile {
     pub name: String,
+    /// The monitors the profile is for, only profiles with one are picked
+    /// when monitors are plugged or unplugged
+    #[serde(rename = "match", default, skip_serializing_if = "Option::is_none")]
+    pub matching: Option<ProfileMatch>,
     pub settings: Vec<MonitorSetting>,
 }
 
+impl Profile {
+    /// Snapshots the current state of `monitors`, recording the scale only
+    /// if the backend can set it back. Monitors showing the same area are
+    /// saved as mirrors of the first of them.
+    pub fn capture(name: &str, monitors: &[&Monitor], can_scale: bool) -> Profile {
+        let owned: Vec<Monitor> = monitors.iter().map(|&m| m.clone()).collect();
+        let rects = layout::planned(&owned, &[]);
+        let mirrors = layout::mirrors(&rects);
+        let settings = monitors
+            .iter()
+            .map(|m| MonitorSetting {
+                monitor_id: m.id.clone(),
+                identity: Some(m.identity.clone()),
+                resolution: m.current_resolution.clone(),
+                orientation: Some(m.current_orientation),
+                position: Some(m.position),
+                scale: can_scale.then_some(m.scale),
+                primary: m.is_primary,
+                enabled: m.enabled,
+                mirror_of: mirrors
+                    .iter()
+                    .find(|(mirror, _)| mirror.id == m.id)
+                    .map(|(_, source)| source.id.clone()),
+            })
+            .collect();
+        Profile {
+            name: name.to_string(),
+            matching: None,
+            settings,
+        }
+    }
+
+    /// The changes that apply the profile to the connected monitors, with
+    /// the modes the monitors actually offer, e.g. 59.94 Hz for 60.
+    pub fn transaction(
+        &self,
+        display: &DisplayManager,
+        monitors: &[Monitor],
+    ) -> Result<Transaction> {
+        let mut transaction = Transaction::new();
+        for (setting, monitor) in self.resolve(monitors) {
+            let Some(monitor) = monitor else {
+                warn!("Monitor {} is not connected, skipping", setting.monitor_id);
+                continue;
+            };
+            if !setting.enabled {
+                transaction.set_enabled(&monitor.device_name, false);
+                continue;
+            }
+            if !monitor.enabled {
+                transaction.set_enabled(&monitor.device_name, true);
+            }
+            transaction.set_resolution(&monitor.device_name, setting.resolution.clone());
+            if let Some(orientation) = setting.orientation {
+                transaction.set_orientation(&monitor.device_name, orientation);
+            }
+            if let Some(position) = setting.position {
+                transaction.set_position(&monitor.device_name, position);
+            }
+            match setting.scale {
+                Some(scale) if display.capabilities().scale => {
+                    transaction.set_scale(&monitor.device_name, scale);
+                }
+                Some(_) => warn!(
+                    "The {} backend cannot scale monitors, ignoring the scale of {}",
+                    display.backend_name(),
+                    setting.monitor_id
+                ),
+                None => {}
+            }
+            if setting.primary {
+                if display.capabilities().primary {
+                    transaction.set_primary(&monitor.device_name);
+                } else {
+                    warn!(
+                        "The {} backend has no primary monitor, ignoring the primary flag of {}",
+                        display.backend_name(),
+                        setting.monitor_id
+                    );
+                }
+            }
+        }
+        for group in self.mirror_groups(monitors) {
+            let (source, monitor) = group[0];
+            let mirrored: Vec<_> = group.iter().map(|&(_, m)| m).collect();
+            transaction.mirror(&mirrored, source.position.unwrap_or(monitor.position))?;
+        }
+        transaction.match_modes(monitors);
+        Ok(transaction)
+    }
+
+    /// Pairs each setting with the connected monitor it refers to, `None` if
+    /// that monitor is not connected.
+    pub fn resolve<'a>(
+        &self,
+        monitors: &'a [Monitor],
+    ) -> Vec<(&MonitorSetting, Option<&'a Monitor>)> {
+        let wanted: Vec<_> = self
+            .settings
+            .iter()
+            .map(|s| (s.identity.as_ref(), s.monitor_id.as_str()))
+            .collect();
+        identity::assign(&wanted, monitors)
+            .into_iter()
+            .zip(&self.settings)
+            .map(|(index, setting)| (setting, index.map(|i| &monitors[i])))
+            .collect()
+    }
+
+    /// Groups of connected monitors the profile mirrors, each led by the
+    /// monitor the others mirror.
+    pub fn mirror_groups<'a>(
+        &self,
+        monitors: &'a [Monitor],
+    ) -> Vec<Vec<(&MonitorSetting, &'a Monitor)>> {
+        let resolved: Vec<_> = self
+            .resolve(monitors)
+            .into_iter()
+            .filter_map(|(setting, monitor)| Some((setting, monitor?)))
+            .filter(|(setting, _)| setting.enabled)
+            .collect();
+        resolved
+            .iter()
+            .filter(|(setting, _)| setting.mirror_of.is_none())
+            .map(|&source| {
+                let mirrors = resolved.iter().copied().filter(|(setting, _)| {
+                    setting.mirror_of.as_deref() == Some(source.0.monitor_id.as_str())
+                });
+                std::iter::once(source).chain(mirrors).collect::<Vec<_>>()
+            })
+            .filter(|group| group.len() > 1)
+            .collect()
+    }
+}
+
+/// The set of connected monitors a profile is meant for. It matches when
+/// every `required` monitor is connected and every connected monitor is
+/// either required or `optional`, as a dock or a projector would be.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct ProfileMatch {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub required: Vec<MonitorIdentity>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub optional: Vec<MonitorIdentity>,
+}
+
+/// How well a profile fits the connected monitors, higher is better. More
+/// required monitors make a profile more specific, so `[laptop, projector]`
+/// beats `[laptop]` with the projector optional, then stronger evidence
+/// wins (a serial number over a model, over a name).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MatchScore {
+    required: usize,
+    strength: u32,
+}
+
+impl ProfileMatch {
+    /// Requires exactly `monitors`.
+    pub fn exactly(monitors: &[Monitor]) -> Self {
+        Self {
+            required: monitors.iter().map(|m| m.identity.clone()).collect(),
+            optional: Vec::new(),
+        }
+    }
+
+    /// `None` if a required monitor is missing or a connected one is not
+    /// listed.
+    pub fn score(&self, monitors: &[Monitor]) -> Option<MatchScore> {
+        let wanted: Vec<_> = self
+            .required
+            .iter()
+            .chain(&self.optional)
+            .map(|identity| (Some(identity), ""))
+            .collect();
+        let assigned = identity::assign(&wanted, monitors);
+        if assigned[..self.required.len()].contains(&None)
+            || assigned.iter().flatten().count() < monitors.len()
+        {
+            return None;
+        }
+        let strength = self
+            .required
+            .iter()
+            .chain(&self.optional)
+            .zip(&assigned)
+            .filter_map(|(identity, index)| identity.matches(&monitors[(*index)?].identity))
+            .map(|quality| quality as u32)
+            .sum();
+        Some(MatchScore {
+            required: self.required.len(),
+            strength,
+        })
+    }
+}
+
+impl AppConfig {
+    /// The profile whose `[profiles.match]` fits the connected monitors
+    /// best, the first of equally good ones.
+    pub fn matching_profile(&self, monitors: &[Monitor]) -> Option<&Profile> {
+        let mut best: Option<(MatchScore, &Profile)> = None;
+        for profile in &self.profiles {
+            let Some(score) = profile.matching.as_ref().and_then(|m| m.score(monitors)) else {
+                continue;
+            };
+            if best.is_none_or(|(top, _)| score > top) {
+                best = Some((score, profile));
+            }
+        }
+        best.map(|(_, profile)| profile)
+    }
+
+    /// Captures the `selected` ones of `monitors`, all of them if none are,
+    /// as the profile `name` and adds it, replacing one of the same name
+    /// only with `force`. With `match_monitors` the profile is picked
+    /// whenever exactly `monitors` are connected. Returns the profile and
+    /// whether it replaced one.
+    pub fn save_profile(
+        &mut self,
+        display: &DisplayManager,
+        monitors: &[Monitor],
+        name: &str,
+        selected: &[String],
+        force: bool,
+        match_monitors: bool,
+    ) -> Result<(Profile, bool)> {
+        if name.trim().is_empty() {
+            return Err(anyhow!("Profile name cannot be empty"));
+        }
+        let exists = self.profiles.iter().any(|p| p.name == name);
+        if exists && !force {
+            return Err(anyhow!(
+                "Profile '{}' already exists, use --force to overwrite it",
+                name
+            ));
+        }
+
+        let captured: Vec<&Monitor> = if selected.is_empty() {
+            monitors.iter().collect()
+        } else {
+            selected
+                .iter()
+                .map(|wanted| find_monitor(monitors, wanted.trim()))
+                .collect::<Result<_>>()?
+        };
+        let mut profile = Profile::capture(name, &captured, display.capabilities().scale);
+        if match_monitors {
+            profile.matching = Some(ProfileMatch::exactly(monitors));
+        }
+        self.put_profile(profile.clone());
+        Ok((profile, exists))
+    }
+
+    /// Adds `profile`, replacing the one of the same name where it stands.
+    /// A replaced profile's match section is kept unless `profile` brings
+    /// its own.
+    pub fn put_profile(&mut self, mut profile: Profile) {
+        match self.profiles.iter_mut().find(|p| p.name == profile.name) {
+            Some(old) => {
+                profile.matching = profile.matching.or(old.matching.take());
+                *old = profile;
+            }
+            None => self.profiles.push(profile),
+        }
+    }
+}
+
+/// Looks a monitor up by any of the names it is shown under, or by its
+/// 1-based position in enumeration order.
+pub fn find_monitor<'a>(monitors: &'a [Monitor], name: &str) -> Result<&'a Monitor> {
+    monitors
+        .iter()
+        .find(|m| m.id == name || m.device_name == name)
+        .or_else(|| monitors.iter().find(|m| m.name == name))
+        .or_else(|| {
+            let index: usize = name.parse().ok()?;
+            monitors.get(index.checked_sub(1)?)
+        })
+        .ok_or_else(|| anyhow!("Monitor '{}' not found", name))
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct MonitorSetting {
+    /// Backend id the monitor had when the profile was saved
     pub monitor_id: String,
+    /// Missing in profiles saved before identities were recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<MonitorIdentity>,
+    /// In the frame of `orientation`, i.e. 1080x1920 for a portrait 1080p panel
     pub resolution: Resolution,
+    /// Left untouched when missing, as in profiles saved by older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<Orientation>,
+    /// Top left corner in desktop coordinates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<(i32, i32)>,
+    /// Desktop scale, left untouched when missing or unsupported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub primary: bool,
+    /// A disabled monitor is detached from the desktop, the other settings
+    /// are kept for when it is enabled again
+    #[serde(default = "default_enabled", skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+    /// `monitor_id` of the entry this monitor mirrors, the pair then shares
+    /// the source's position at the best mode both support
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror_of: Option<String>,
+}
+
+/// Applies `profile` while a process matching every given criterion runs.
+/// When several rules match, the highest `priority` wins, then the first.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Rule {
+    pub profile: String,
+    /// Glob (`*`, `?`) on the executable's file name, or on its full path if
+    /// the pattern contains a path separator. Case insensitive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exe: Option<String>,
+    /// Glob on the class of one of the process's windows (X11 WM_CLASS or
+    /// the Win32 window class)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_class: Option<String>,
+    /// Regular expression searched in the command line, Linux only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cmdline: Option<String>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: i32,
+    /// Restore the settings from before the profile once nothing matches
+    #[serde(default = "default_enabled", skip_serializing_if = "is_enabled")]
+    pub revert_on_exit: bool,
+}
+
+fn is_zero(priority: &i32) -> bool {
+    *priority == 0
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
 }
 
 pub struct ConfigManager;
@@ -83,3 +444,116 @@ impl ConfigManager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(id: &str, model: Option<(&str, &str)>, serial: Option<&str>, port: &str) -> Monitor {
+        let mut monitor = Monitor::fake(id, "1920x1080@60", (0, 0));
+        monitor.identity = MonitorIdentity {
+            vendor: model.map(|(vendor, _)| vendor.to_string()),
+            product: model.map(|(_, product)| product.to_string()),
+            serial: serial.map(str::to_string),
+            connector: Some(port.to_string()),
+            name: id.to_string(),
+        };
+        monitor
+    }
+
+    fn laptop() -> Monitor {
+        monitor("Built-in", Some(("BOE", "0A1B")), None, "eDP-1")
+    }
+
+    fn dell(serial: &str, port: &str) -> Monitor {
+        monitor("DELL U2719D", Some(("DEL", "A0B1")), Some(serial), port)
+    }
+
+    fn projector() -> Monitor {
+        monitor("Projector", None, None, "HDMI-1")
+    }
+
+    fn profile(name: &str, required: &[Monitor], optional: &[Monitor]) -> Profile {
+        Profile {
+            name: name.to_string(),
+            matching: Some(ProfileMatch {
+                required: required.iter().map(|m| m.identity.clone()).collect(),
+                optional: optional.iter().map(|m| m.identity.clone()).collect(),
+            }),
+            settings: Vec::new(),
+        }
+    }
+
+    fn config(profiles: Vec<Profile>) -> AppConfig {
+        AppConfig {
+            profiles,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn needs_every_required_monitor() {
+        let matching = ProfileMatch::exactly(&[laptop(), dell("CN01", "DP-1")]);
+        assert!(matching.score(&[laptop()]).is_none());
+        assert!(matching.score(&[laptop(), dell("CN01", "DP-1")]).is_some());
+        // The same model with another serial is another monitor
+        assert!(matching.score(&[laptop(), dell("CN02", "DP-1")]).is_none());
+        // Serials follow the monitor to another port
+        assert!(matching.score(&[dell("CN01", "DP-2"), laptop()]).is_some());
+    }
+
+    #[test]
+    fn rejects_unlisted_monitors() {
+        let alone = ProfileMatch::exactly(&[laptop()]);
+        assert!(alone.score(&[laptop(), projector()]).is_none());
+        let docked = profile("Docked", &[laptop()], &[projector()]);
+        let docked = docked.matching.unwrap();
+        assert!(docked.score(&[laptop(), projector()]).is_some());
+        assert!(docked.score(&[laptop()]).is_some());
+        assert!(docked.score(&[projector()]).is_none());
+    }
+
+    #[test]
+    fn prefers_specific_then_strong_matches() {
+        let by_name = monitor("Built-in", None, None, "eDP-9");
+        let config = config(vec![
+            profile("Unmatched", &[], &[]),
+            profile("By name", &[by_name], &[projector()]),
+            profile("Laptop", &[laptop()], &[projector()]),
+            profile("Presenting", &[laptop(), projector()], &[]),
+            profile("Also laptop", &[laptop()], &[projector()]),
+        ]);
+        let best = |monitors: &[Monitor]| config.matching_profile(monitors).map(|p| p.name.clone());
+
+        assert_eq!(
+            best(&[laptop(), projector()]).as_deref(),
+            Some("Presenting")
+        );
+        assert_eq!(best(&[laptop()]).as_deref(), Some("Laptop"));
+        assert_eq!(best(&[dell("CN01", "DP-1")]), None);
+        assert_eq!(best(&[]).as_deref(), Some("Unmatched"));
+    }
+
+    #[test]
+    fn replaces_profiles_in_place() {
+        let mut config = config(vec![
+            profile("Laptop", &[laptop()], &[]),
+            profile("Desk", &[laptop(), dell("CN01", "DP-1")], &[]),
+        ]);
+        let matching = config.profiles[0].matching.clone();
+        config.put_profile(Profile {
+            name: "Laptop".to_string(),
+            matching: None,
+            settings: Vec::new(),
+        });
+        config.put_profile(Profile {
+            name: "Movie".to_string(),
+            matching: None,
+            settings: Vec::new(),
+        });
+
+        let names: Vec<_> = config.profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["Laptop", "Desk", "Movie"]);
+        assert_eq!(config.profiles[0].matching, matching);
+    }
+}
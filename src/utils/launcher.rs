@@ -0,0 +1,257 @@
+//! Starts a program for `yarm run` and waits until it and every process it
+//! spawned are gone, so launchers that hand over to the real game and exit
+//! right away do not end the run early.
+//!
+//! Ctrl-C and termination requests are caught instead of killing yarm, the
+//! caller checks `interrupted()` and restores the displays before leaving.
+
+use anyhow::{anyhow, Result};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// How the wait for the program ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Everything exited, with the program's own exit code if it had one
+    Exited(Option<i32>),
+    /// yarm was asked to stop while the program was still running
+    Interrupted,
+}
+
+/// Whether Ctrl-C or a termination request arrived since
+/// `catch_interrupts`.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Runs `program` with `args` and waits for it and its descendants.
+pub fn run(program: &str, args: &[String]) -> Result<Outcome> {
+    imp::run(Command::new(program).args(args))
+        .map_err(|e| anyhow!("Failed to run {}: {}", program, e))
+}
+
+#[cfg(unix)]
+pub use imp::catch_interrupts;
+
+#[cfg(unix)]
+mod imp {
+    use super::{interrupted, Outcome, INTERRUPTED};
+    use anyhow::Result;
+    use std::io;
+    use std::process::Command;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::Duration;
+
+    // How often the children are checked, a blocking wait would miss a
+    // signal arriving just before it starts
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    extern "C" fn on_signal(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+
+    /// Turns SIGINT, SIGTERM and SIGHUP into a flag. A handler rather than
+    /// SIG_IGN, so the program started later still gets the default ones.
+    pub fn catch_interrupts() {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            action.sa_flags = 0;
+            for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+                libc::sigaction(signal, &action, std::ptr::null_mut());
+            }
+        }
+    }
+
+    pub fn run(command: &mut Command) -> Result<Outcome> {
+        // Orphaned descendants get reparented to us instead of init, so
+        // waiting for all children covers them too
+        #[cfg(target_os = "linux")]
+        unsafe {
+            libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0);
+        }
+
+        let child = command.spawn()?;
+        let pid = child.id() as libc::pid_t;
+        let mut code = None;
+        loop {
+            if interrupted() {
+                return Ok(Outcome::Interrupted);
+            }
+            let mut status = 0;
+            let reaped = unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) };
+            if reaped == 0 {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            if reaped == -1 {
+                let error = io::Error::last_os_error();
+                match error.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    Some(libc::ECHILD) => return Ok(Outcome::Exited(code)),
+                    _ => return Err(error.into()),
+                }
+            }
+            if reaped == pid && libc::WIFEXITED(status) {
+                code = Some(libc::WEXITSTATUS(status));
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+pub use imp::catch_interrupts;
+
+#[cfg(windows)]
+mod imp {
+    use super::{interrupted, Outcome, INTERRUPTED};
+    use anyhow::{anyhow, Result};
+    use log::warn;
+    use std::mem;
+    use std::os::windows::io::AsRawHandle;
+    use std::os::windows::process::CommandExt;
+    use std::process::Command;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+    use windows::core::PCWSTR;
+    use windows::Win32::Foundation::{CloseHandle, BOOL, HANDLE, TRUE};
+    use windows::Win32::System::Console::SetConsoleCtrlHandler;
+    use windows::Win32::System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD, THREADENTRY32,
+    };
+    use windows::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JobObjectBasicAccountingInformation,
+        QueryInformationJobObject, JOBOBJECT_BASIC_ACCOUNTING_INFORMATION,
+    };
+    use windows::Win32::System::Threading::{
+        OpenThread, ResumeThread, CREATE_SUSPENDED, THREAD_SUSPEND_RESUME,
+    };
+
+    // How often the job is checked for remaining processes
+    const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+    unsafe extern "system" fn on_ctrl(_: u32) -> BOOL {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        TRUE
+    }
+
+    /// Turns Ctrl-C, Ctrl-Break and closing the console into a flag.
+    pub fn catch_interrupts() {
+        if let Err(e) = unsafe { SetConsoleCtrlHandler(Some(on_ctrl), true) } {
+            warn!(
+                "Cannot catch Ctrl-C, the displays are not restored on it: {}",
+                e
+            );
+        }
+    }
+
+    /// Processes left in the job, every process started by one in the job
+    /// joins it.
+    fn active_processes(job: HANDLE) -> Result<u32> {
+        let mut info = JOBOBJECT_BASIC_ACCOUNTING_INFORMATION::default();
+        unsafe {
+            QueryInformationJobObject(
+                job,
+                JobObjectBasicAccountingInformation,
+                &mut info as *mut _ as *mut _,
+                mem::size_of::<JOBOBJECT_BASIC_ACCOUNTING_INFORMATION>() as u32,
+                None,
+            )?;
+        }
+        Ok(info.ActiveProcesses)
+    }
+
+    /// Lets a process started suspended run, by resuming its threads.
+    fn resume(pid: u32) -> Result<()> {
+        let mut resumed = false;
+        unsafe {
+            let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0)?;
+            let mut entry = THREADENTRY32 {
+                dwSize: mem::size_of::<THREADENTRY32>() as u32,
+                ..Default::default()
+            };
+            let mut next = Thread32First(snapshot, &mut entry);
+            while next.is_ok() {
+                if entry.th32OwnerProcessID == pid {
+                    if let Ok(thread) = OpenThread(THREAD_SUSPEND_RESUME, false, entry.th32ThreadID)
+                    {
+                        resumed |= ResumeThread(thread) != u32::MAX;
+                        let _ = CloseHandle(thread);
+                    }
+                }
+                next = Thread32Next(snapshot, &mut entry);
+            }
+            let _ = CloseHandle(snapshot);
+        }
+        if !resumed {
+            return Err(anyhow!("Cannot resume process {}", pid));
+        }
+        Ok(())
+    }
+
+    pub fn run(command: &mut Command) -> Result<Outcome> {
+        let job = unsafe { CreateJobObjectW(None, PCWSTR::null())? };
+        let outcome = wait_in_job(command, job);
+        unsafe {
+            let _ = CloseHandle(job);
+        }
+        outcome
+    }
+
+    fn wait_in_job(command: &mut Command, job: HANDLE) -> Result<Outcome> {
+        // Suspended until it is in the job, so nothing it starts right away
+        // escapes it
+        let mut child = command.creation_flags(CREATE_SUSPENDED.0).spawn()?;
+        let process = HANDLE(child.as_raw_handle());
+        let tracked = match unsafe { AssignProcessToJobObject(job, process) } {
+            Ok(()) => true,
+            Err(e) => {
+                warn!(
+                    "Only waiting for the program itself, not what it starts: {}",
+                    e
+                );
+                false
+            }
+        };
+        if let Err(e) = resume(child.id()) {
+            let _ = child.kill();
+            return Err(e);
+        }
+
+        let mut code = None;
+        loop {
+            if interrupted() {
+                return Ok(Outcome::Interrupted);
+            }
+            if code.is_none() {
+                if let Some(status) = child.try_wait()? {
+                    code = Some(status.code());
+                }
+            }
+            if let Some(code) = code {
+                if !tracked || active_processes(job)? == 0 {
+                    return Ok(Outcome::Exited(code));
+                }
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn catch_interrupts() {}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    use super::Outcome;
+    use anyhow::Result;
+    use std::process::Command;
+
+    pub fn run(command: &mut Command) -> Result<Outcome> {
+        Ok(Outcome::Exited(command.status()?.code()))
+    }
+}
@@ -1 +1,4 @@
 pub mod config;
+pub mod launcher;
+pub mod processes;
+pub mod rules;
@@ -0,0 +1,238 @@
+//! What is running right now, as far as rules care: every process with its
+//! executable, command line and the classes of its top level windows.
+
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Default)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    /// File name of the executable, e.g. `Cyberpunk2077.exe`
+    pub name: String,
+    /// Full path of the executable, when the OS lets us see it
+    pub exe: Option<PathBuf>,
+    /// Arguments joined by spaces, empty where unavailable (Windows)
+    pub cmdline: String,
+    pub window_classes: Vec<String>,
+}
+
+/// Lists the running processes. Processes that vanish or cannot be
+/// inspected while listing are skipped or listed with what is known.
+pub fn snapshot() -> Vec<ProcessInfo> {
+    let mut processes = imp::processes();
+    for (pid, class) in imp::window_classes() {
+        if let Some(process) = processes.iter_mut().find(|p| p.pid == pid) {
+            if !process.window_classes.contains(&class) {
+                process.window_classes.push(class);
+            }
+        }
+    }
+    processes
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::ProcessInfo;
+    use log::debug;
+    use std::fs;
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{AtomEnum, ConnectionExt};
+
+    pub fn processes() -> Vec<ProcessInfo> {
+        let Ok(entries) = fs::read_dir("/proc") else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .filter_map(|entry| {
+                let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+                let dir = entry.path();
+                // Kernel threads have no command line and no executable
+                let raw = fs::read(dir.join("cmdline")).ok()?;
+                let args: Vec<String> = raw
+                    .split(|&b| b == 0)
+                    .filter(|arg| !arg.is_empty())
+                    .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                    .collect();
+                let exe = fs::read_link(dir.join("exe")).ok();
+                // Wine and Proton games run as a Windows path in argv[0]
+                let name = args
+                    .first()
+                    .and_then(|arg0| arg0.rsplit(['/', '\\']).next())
+                    .map(str::to_string)
+                    .or_else(|| Some(exe.as_ref()?.file_name()?.to_string_lossy().into_owned()))?;
+                Some(ProcessInfo {
+                    pid,
+                    name,
+                    exe,
+                    cmdline: args.join(" "),
+                    window_classes: Vec::new(),
+                })
+            })
+            .collect()
+    }
+
+    /// `(pid, WM_CLASS class)` of every window the X window manager lists.
+    pub fn window_classes() -> Vec<(u32, String)> {
+        if std::env::var_os("DISPLAY").is_none() {
+            return Vec::new();
+        }
+        match x11_window_classes() {
+            Ok(classes) => classes,
+            Err(e) => {
+                debug!("Cannot list X11 windows: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn x11_window_classes() -> anyhow::Result<Vec<(u32, String)>> {
+        let (conn, screen_num) = x11rb::connect(None)?;
+        let root = conn.setup().roots[screen_num].root;
+        let intern = |name: &[u8]| -> anyhow::Result<u32> {
+            Ok(conn.intern_atom(false, name)?.reply()?.atom)
+        };
+        let client_list = intern(b"_NET_CLIENT_LIST")?;
+        let wm_pid = intern(b"_NET_WM_PID")?;
+
+        let windows: Vec<u32> = conn
+            .get_property(false, root, client_list, AtomEnum::WINDOW, 0, u32::MAX)?
+            .reply()?
+            .value32()
+            .map(|values| values.collect())
+            .unwrap_or_default();
+        let mut classes = Vec::new();
+        for window in windows {
+            // Windows can close while being listed, their replies are
+            // BadWindow errors
+            let Ok(reply) = conn
+                .get_property(false, window, wm_pid, AtomEnum::CARDINAL, 0, 1)?
+                .reply()
+            else {
+                continue;
+            };
+            let Some(pid) = reply.value32().and_then(|mut values| values.next()) else {
+                continue;
+            };
+            // WM_CLASS holds the instance and the class, NUL terminated
+            let Ok(reply) = conn
+                .get_property(false, window, AtomEnum::WM_CLASS, AtomEnum::STRING, 0, 256)?
+                .reply()
+            else {
+                continue;
+            };
+            for class in reply.value.split(|&b| b == 0).filter(|c| !c.is_empty()) {
+                classes.push((pid, String::from_utf8_lossy(class).into_owned()));
+            }
+        }
+        Ok(classes)
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use super::ProcessInfo;
+    use std::path::PathBuf;
+    use windows::core::PWSTR;
+    use windows::Win32::Foundation::{CloseHandle, BOOL, HWND, LPARAM, TRUE};
+    use windows::Win32::System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+        TH32CS_SNAPPROCESS,
+    };
+    use windows::Win32::System::Threading::{
+        OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+        PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+    use windows::Win32::UI::WindowsAndMessaging::{
+        EnumWindows, GetClassNameW, GetWindowThreadProcessId, IsWindowVisible,
+    };
+
+    fn from_wide(wide: &[u16]) -> String {
+        let len = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+        String::from_utf16_lossy(&wide[..len])
+    }
+
+    fn image_path(pid: u32) -> Option<PathBuf> {
+        unsafe {
+            let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+            let mut buffer = [0u16; 1024];
+            let mut size = buffer.len() as u32;
+            let result = QueryFullProcessImageNameW(
+                process,
+                PROCESS_NAME_WIN32,
+                PWSTR(buffer.as_mut_ptr()),
+                &mut size,
+            );
+            let _ = CloseHandle(process);
+            result.ok()?;
+            Some(PathBuf::from(from_wide(&buffer[..size as usize])))
+        }
+    }
+
+    // Reading another process's command line needs its PEB, which is not
+    // worth it here: `cmdline` stays empty on Windows
+    pub fn processes() -> Vec<ProcessInfo> {
+        let mut processes = Vec::new();
+        unsafe {
+            let Ok(snapshot) = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) else {
+                return processes;
+            };
+            let mut entry = PROCESSENTRY32W {
+                dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+                ..Default::default()
+            };
+            let mut next = Process32FirstW(snapshot, &mut entry);
+            while next.is_ok() {
+                let pid = entry.th32ProcessID;
+                processes.push(ProcessInfo {
+                    pid,
+                    name: from_wide(&entry.szExeFile),
+                    exe: image_path(pid),
+                    cmdline: String::new(),
+                    window_classes: Vec::new(),
+                });
+                next = Process32NextW(snapshot, &mut entry);
+            }
+            let _ = CloseHandle(snapshot);
+        }
+        processes
+    }
+
+    unsafe extern "system" fn collect_window(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let classes = &mut *(lparam.0 as *mut Vec<(u32, String)>);
+        if IsWindowVisible(hwnd).as_bool() {
+            let mut pid = 0;
+            GetWindowThreadProcessId(hwnd, Some(&mut pid));
+            let mut class = [0u16; 256];
+            let len = GetClassNameW(hwnd, &mut class);
+            if len > 0 {
+                classes.push((pid, String::from_utf16_lossy(&class[..len as usize])));
+            }
+        }
+        TRUE
+    }
+
+    /// `(pid, class name)` of every visible top level window.
+    pub fn window_classes() -> Vec<(u32, String)> {
+        let mut classes: Vec<(u32, String)> = Vec::new();
+        unsafe {
+            let _ = EnumWindows(
+                Some(collect_window),
+                LPARAM(&mut classes as *mut Vec<(u32, String)> as isize),
+            );
+        }
+        classes
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod imp {
+    use super::ProcessInfo;
+
+    pub fn processes() -> Vec<ProcessInfo> {
+        Vec::new()
+    }
+
+    pub fn window_classes() -> Vec<(u32, String)> {
+        Vec::new()
+    }
+}
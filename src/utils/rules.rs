@@ -0,0 +1,408 @@
+//! Matching `[[rules]]` from config.toml against the running processes, and
+//! the `Watcher` switching profiles as programs start and stop or monitors
+//! are plugged and unplugged. Which profile fits the connected monitors is
+//! up to `AppConfig::matching_profile`.
+
+use anyhow::{anyhow, Result};
+use regex::Regex;
+use std::path::Path;
+
+use super::config::{AppConfig, Profile, Rule};
+use super::processes::{self, ProcessInfo};
+use crate::display::identity::MonitorIdentity;
+use crate::display::{DisplayManager, Monitor};
+
+/// The rules of a config, checked and with their regular expressions
+/// compiled once.
+pub struct RuleSet {
+    rules: Vec<(Rule, Option<Regex>)>,
+}
+
+impl RuleSet {
+    /// Fails on rules naming a missing profile, matching nothing or holding
+    /// an invalid regular expression.
+    pub fn new(config: &AppConfig) -> Result<Self> {
+        let mut rules = Vec::new();
+        for rule in &config.rules {
+            if !config.profiles.iter().any(|p| p.name == rule.profile) {
+                return Err(anyhow!(
+                    "Rule for profile '{}': no such profile",
+                    rule.profile
+                ));
+            }
+            if rule.exe.is_none() && rule.window_class.is_none() && rule.cmdline.is_none() {
+                return Err(anyhow!(
+                    "Rule for profile '{}' needs exe, window_class or cmdline",
+                    rule.profile
+                ));
+            }
+            let cmdline = rule
+                .cmdline
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .map_err(|e| anyhow!("Rule for profile '{}': {}", rule.profile, e))?;
+            rules.push((rule.clone(), cmdline));
+        }
+        Ok(Self { rules })
+    }
+
+    /// The winning rule among those a running process matches, with the
+    /// process.
+    pub fn best<'p>(&self, processes: &'p [ProcessInfo]) -> Option<(&Rule, &'p ProcessInfo)> {
+        let mut best: Option<(&Rule, &'p ProcessInfo)> = None;
+        for (rule, cmdline) in &self.rules {
+            if best.is_some_and(|(b, _)| b.priority >= rule.priority) {
+                continue;
+            }
+            if let Some(process) = processes
+                .iter()
+                .find(|p| matches(rule, cmdline.as_ref(), p))
+            {
+                best = Some((rule, process));
+            }
+        }
+        best
+    }
+}
+
+/// Applies the profile of the winning rule as programs start and stop, and
+/// restores the desktop it found once no rule matches any more. Between
+/// rules, monitors being plugged or unplugged switch to the profile whose
+/// `[profiles.match]` fits them.
+#[derive(Default)]
+pub struct Watcher {
+    rules: Option<RuleSet>,
+    /// The rule in effect
+    active: Option<Rule>,
+    /// The desktop from before any rule applied
+    baseline: Option<Profile>,
+    /// The monitors connected at the last look
+    connected: Option<Vec<(String, MonitorIdentity)>>,
+    /// Whether a profile was applied since `take_applied`
+    applied: bool,
+}
+
+impl Watcher {
+    pub fn new(config: &AppConfig) -> Result<Self> {
+        Ok(Self {
+            rules: Some(RuleSet::new(config)?),
+            ..Self::default()
+        })
+    }
+
+    /// Replaces the rules, e.g. after config.toml changed. The profile in
+    /// effect stays until the next `tick` finds another winner.
+    pub fn reload(&mut self, config: &AppConfig) -> Result<()> {
+        self.rules = Some(RuleSet::new(config)?);
+        Ok(())
+    }
+
+    /// Looks at the running processes once and switches profiles if another
+    /// rule wins. Failing to apply a profile is reported, not returned, so
+    /// a watcher survives a profile that does not fit the monitors.
+    pub fn tick(&mut self, display: &DisplayManager, config: &AppConfig) -> Result<()> {
+        let Some(rules) = &self.rules else {
+            return Ok(());
+        };
+        let running = processes::snapshot();
+        let wanted = rules.best(&running);
+        if wanted.map(|(rule, _)| rule) == self.active.as_ref() {
+            return Ok(());
+        }
+        match wanted {
+            Some((rule, process)) => {
+                println!(
+                    "{} (pid {}) is running, switching to profile: {}",
+                    process.name, process.pid, rule.profile
+                );
+                let monitors = display.enumerate_monitors()?;
+                if self.baseline.is_none() {
+                    let all: Vec<&Monitor> = monitors.iter().collect();
+                    self.baseline = Some(Profile::capture(
+                        "before rules",
+                        &all,
+                        display.capabilities().scale,
+                    ));
+                }
+                self.applied |= switch(display, config, &rule.profile, &monitors);
+            }
+            None => {
+                if let Some(rule) = &self.active {
+                    println!("Nothing matches profile '{}' any more", rule.profile);
+                    let baseline = self.baseline.take();
+                    if rule.revert_on_exit {
+                        self.applied |= restore(display, baseline);
+                    }
+                }
+            }
+        }
+        self.active = wanted.map(|(rule, _)| rule.clone());
+        Ok(())
+    }
+
+    /// Reacts to monitors being plugged or unplugged. The profile of the
+    /// rule in effect is applied again so a monitor that just arrived gets
+    /// its settings too, otherwise the profile matching the monitors now is
+    /// applied, or only named without `auto_apply`, and returned. The first
+    /// call only takes note of the monitors.
+    pub fn displays_changed(
+        &mut self,
+        display: &DisplayManager,
+        config: &AppConfig,
+    ) -> Result<Option<String>> {
+        let monitors = display.enumerate_monitors()?;
+        let previous = self.connected.replace(connected(&monitors));
+        if previous.is_none() || previous == self.connected {
+            return Ok(None);
+        }
+        if let Some(rule) = &self.active {
+            println!(
+                "Monitors were plugged or unplugged, applying profile '{}' again",
+                rule.profile
+            );
+            self.applied |= switch(display, config, &rule.profile, &monitors);
+            return Ok(None);
+        }
+        let Some(profile) = config.matching_profile(&monitors) else {
+            return Ok(None);
+        };
+        if config.general.auto_apply {
+            println!(
+                "Monitors were plugged or unplugged, switching to profile: {}",
+                profile.name
+            );
+            self.applied |= switch(display, config, &profile.name, &monitors);
+        } else {
+            println!(
+                "Profile '{}' matches the connected monitors, `yarm switch {}` applies it",
+                profile.name, profile.name
+            );
+        }
+        Ok(Some(profile.name.clone()))
+    }
+
+    /// Whether `tick` or `displays_changed` changed the monitors since the
+    /// last call, which makes a revert waiting to undo an earlier change
+    /// stale.
+    pub fn take_applied(&mut self) -> bool {
+        std::mem::take(&mut self.applied)
+    }
+
+    /// Restores the desktop if the rule in effect asks for it, for when
+    /// watching ends.
+    pub fn stop(self, display: &DisplayManager) {
+        if self.active.is_some_and(|rule| rule.revert_on_exit) {
+            restore(display, self.baseline);
+        }
+    }
+}
+
+/// Applies a profile, reporting rather than returning failures. Returns
+/// whether it was applied.
+fn switch(
+    display: &DisplayManager,
+    config: &AppConfig,
+    profile: &str,
+    monitors: &[Monitor],
+) -> bool {
+    let result = config
+        .profiles
+        .iter()
+        .find(|p| p.name == profile)
+        .ok_or_else(|| anyhow!("Profile '{}' not found", profile))
+        .and_then(|profile| profile.transaction(display, monitors))
+        .and_then(|transaction| display.apply(&transaction));
+    if let Err(e) = &result {
+        eprintln!("Failed to switch to profile '{}': {}", profile, e);
+    }
+    result.is_ok()
+}
+
+/// Ports alone miss one monitor being swapped for another on the same port
+fn connected(monitors: &[Monitor]) -> Vec<(String, MonitorIdentity)> {
+    monitors
+        .iter()
+        .map(|m| (m.id.clone(), m.identity.clone()))
+        .collect()
+}
+
+/// Puts the monitors back the way the watcher found them, reporting rather
+/// than failing so watching goes on. Returns whether they were put back.
+fn restore(display: &DisplayManager, baseline: Option<Profile>) -> bool {
+    let Some(baseline) = baseline else {
+        return false;
+    };
+    println!("Restoring the previous display settings");
+    let result = display
+        .enumerate_monitors()
+        .and_then(|monitors| baseline.transaction(display, &monitors))
+        .and_then(|transaction| display.apply(&transaction));
+    if let Err(e) = &result {
+        eprintln!("Failed to restore the previous display settings: {}", e);
+    }
+    result.is_ok()
+}
+
+fn matches(rule: &Rule, cmdline: Option<&Regex>, process: &ProcessInfo) -> bool {
+    let exe_matches = |pattern: &str| {
+        if pattern.contains(['/', '\\']) {
+            process
+                .exe
+                .as_deref()
+                .is_some_and(|exe| glob(pattern, &exe.to_string_lossy()))
+        } else {
+            glob(pattern, &process.name)
+                || process
+                    .exe
+                    .as_deref()
+                    .and_then(Path::file_name)
+                    .is_some_and(|name| glob(pattern, &name.to_string_lossy()))
+        }
+    };
+    rule.exe.as_deref().is_none_or(exe_matches)
+        && rule
+            .window_class
+            .as_deref()
+            .is_none_or(|pattern| process.window_classes.iter().any(|c| glob(pattern, c)))
+        && cmdline.is_none_or(|regex| regex.is_match(&process.cmdline))
+}
+
+/// Case insensitive wildcard match, `*` for any run of characters and `?`
+/// for one.
+fn glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was and how much of the text it has eaten so far
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, eaten)) => {
+                    backtrack = Some((star, eaten + 1));
+                    p = star + 1;
+                    t = eaten + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn config(rules: &str) -> AppConfig {
+        let profiles = ["Game", "Movie", "Work"]
+            .iter()
+            .map(|name| format!("[[profiles]]\nname = \"{}\"\nsettings = []\n", name))
+            .collect::<String>();
+        toml::from_str(&format!("{}{}", profiles, rules)).unwrap()
+    }
+
+    fn process(name: &str, exe: &str, cmdline: &str, window_classes: &[&str]) -> ProcessInfo {
+        ProcessInfo {
+            pid: 1,
+            name: name.to_string(),
+            exe: Some(PathBuf::from(exe)),
+            cmdline: cmdline.to_string(),
+            window_classes: window_classes.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    fn desktop() -> Vec<ProcessInfo> {
+        vec![
+            process("bash", "/usr/bin/bash", "bash", &[]),
+            process(
+                "wine64-preloader",
+                "/opt/wine/bin/wine64-preloader",
+                "C:\\Games\\Witcher3\\witcher3.exe -nosplash",
+                &["witcher3.exe"],
+            ),
+            process("mpv", "/usr/bin/mpv", "mpv movie.mkv", &["mpv"]),
+        ]
+    }
+
+    fn winner(rules: &str) -> Option<String> {
+        let processes = desktop();
+        RuleSet::new(&config(rules))
+            .unwrap()
+            .best(&processes)
+            .map(|(rule, _)| rule.profile.clone())
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob("mpv", "MPV"));
+        assert!(glob("*", ""));
+        assert!(glob("*.exe", "witcher3.exe"));
+        assert!(glob("w?tcher*", "witcher3.exe"));
+        assert!(glob("*a*b*c", "xaxbxbxc"));
+        assert!(glob("**x", "x"));
+        assert!(!glob("*.exe", "witcher3.exe.log"));
+        assert!(!glob("w?", "w"));
+        assert!(!glob("", "mpv"));
+        assert!(!glob("mpv", "mpv2"));
+    }
+
+    #[test]
+    fn rejects_broken_rules() {
+        let error = |rules: &str| RuleSet::new(&config(rules)).err().unwrap().to_string();
+        assert!(error("[[rules]]\nprofile = \"Nope\"\nexe = \"mpv\"\n").contains("no such profile"));
+        assert!(error("[[rules]]\nprofile = \"Game\"\n").contains("needs exe"));
+        assert!(error("[[rules]]\nprofile = \"Game\"\ncmdline = \"(\"\n").contains("Game"));
+    }
+
+    #[test]
+    fn matches_the_name_path_class_and_cmdline() {
+        assert_eq!(
+            winner("[[rules]]\nprofile = \"Movie\"\nexe = \"MPV\"\n").as_deref(),
+            Some("Movie")
+        );
+        assert_eq!(
+            winner("[[rules]]\nprofile = \"Game\"\nexe = \"/opt/wine/*\"\n").as_deref(),
+            Some("Game")
+        );
+        // A pattern with a separator never falls back to the file name
+        assert_eq!(
+            winner("[[rules]]\nprofile = \"Game\"\nexe = \"*/mpv2\"\n"),
+            None
+        );
+        assert_eq!(
+            winner("[[rules]]\nprofile = \"Game\"\nwindow_class = \"*.exe\"\n").as_deref(),
+            Some("Game")
+        );
+        assert_eq!(
+            winner("[[rules]]\nprofile = \"Game\"\ncmdline = 'witcher3\\.exe\\b'\n").as_deref(),
+            Some("Game")
+        );
+        // Every criterion of a rule has to hold for the same process
+        assert_eq!(
+            winner("[[rules]]\nprofile = \"Game\"\nexe = \"mpv\"\nwindow_class = \"*.exe\"\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn picks_the_highest_priority_then_the_first_rule() {
+        let rules = "[[rules]]\nprofile = \"Work\"\nexe = \"bash\"\n\
+                     [[rules]]\nprofile = \"Movie\"\nexe = \"mpv\"\n\
+                     [[rules]]\nprofile = \"Game\"\nwindow_class = \"witcher3.exe\"\n";
+        assert_eq!(winner(rules).as_deref(), Some("Work"));
+        let rules = format!("{}priority = 10\n", rules);
+        assert_eq!(winner(&rules).as_deref(), Some("Game"));
+        assert_eq!(winner("").as_deref(), None);
+    }
+}